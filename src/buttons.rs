@@ -0,0 +1,249 @@
+use crate::narrative;
+use crate::render;
+use crate::units::{Temperature, UnitFormat};
+use crate::weather::{get_forecast_cached, get_weather_cached, WeatherResponse};
+use crate::{Context, Error};
+use futures::StreamExt;
+use poise::serenity_prelude as serenity;
+use poise::CreateReply;
+use serenity::{
+    ButtonStyle, ComponentInteraction, ComponentInteractionCollector, CreateActionRow,
+    CreateButton, CreateEmbed, CreateInteractionResponse, CreateInteractionResponseMessage,
+    EditInteractionResponse,
+};
+use std::collections::HashMap;
+use std::time::Duration;
+
+// Which view a weather message is currently showing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum View {
+    Current,
+    Hourly,
+    Daily,
+}
+
+// How long the buttons keep working, BUTTON_TIMEOUT_SECS or 2 minutes
+fn button_timeout() -> Duration {
+    let secs = std::env::var("BUTTON_TIMEOUT_SECS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(120);
+    Duration::from_secs(secs)
+}
+
+// Buttons under a weather reply; custom ids are prefixed with the invocation id
+fn components(prefix: &str, fmt: &UnitFormat, view: View, disabled: bool) -> Vec<CreateActionRow> {
+    let button = |id: &str, label: String, style: ButtonStyle| {
+        CreateButton::new(format!("{}-{}", prefix, id))
+            .label(label)
+            .style(style)
+            .disabled(disabled)
+    };
+    let view_style = |v: View| {
+        if v == view {
+            ButtonStyle::Primary
+        } else {
+            ButtonStyle::Secondary
+        }
+    };
+
+    vec![CreateActionRow::Buttons(vec![
+        button(
+            "units",
+            format!(
+                "{} → {}",
+                Temperature::symbol(fmt.system),
+                Temperature::symbol(fmt.system.next())
+            ),
+            ButtonStyle::Secondary,
+        ),
+        button("refresh", "🔄 Refresh".to_string(), ButtonStyle::Secondary),
+        button("current", "Now".to_string(), view_style(View::Current)),
+        button("hourly", "Hourly".to_string(), view_style(View::Hourly)),
+        // The forecast API only covers five days
+        button("daily", "5-day".to_string(), view_style(View::Daily)),
+    ])]
+}
+
+// Only the person who ran the command (or a moderator) can change the shared message
+fn is_authorized(ctx: Context<'_>, interaction: &ComponentInteraction) -> bool {
+    if interaction.user.id == ctx.author().id {
+        return true;
+    }
+    interaction
+        .member
+        .as_ref()
+        .and_then(|m| m.permissions)
+        .map(|p| p.manage_messages())
+        .unwrap_or(false)
+}
+
+// Current conditions, with the summary sentence in place of the description in narrative mode
+fn current_embed(
+    weather: &WeatherResponse,
+    flag: Option<&str>,
+    fmt: &UnitFormat,
+    summary: Option<&str>,
+) -> CreateEmbed {
+    let embed = render::weather_embed(weather, flag, fmt);
+    match summary {
+        Some(summary) => embed.description(summary),
+        None => embed,
+    }
+}
+
+// Reworded summaries by observation time and unit system, so going back to the
+// current view shows the same text without another LLM request
+type Polished = HashMap<(i64, &'static str), String>;
+
+// The narrative summary in narrative mode, reworded by the LLM when that's enabled
+async fn summary(
+    ctx: Context<'_>,
+    narrative: bool,
+    weather: &WeatherResponse,
+    fmt: &UnitFormat,
+    polished: &mut Polished,
+) -> Option<String> {
+    if !narrative {
+        return None;
+    }
+    let template = narrative::current(weather, fmt);
+    if !narrative::llm_enabled() {
+        return Some(template);
+    }
+    let key = (weather.dt, fmt.system.name());
+    if let Some(text) = polished.get(&key) {
+        return Some(text.clone());
+    }
+    let text = narrative::polish(ctx, template).await;
+    polished.insert(key, text.clone());
+    Some(text)
+}
+
+// Render the embed for the current view, fetching through the cache
+async fn render_view(
+    city: &str,
+    flag: Option<&str>,
+    fmt: &UnitFormat,
+    view: View,
+    weather: &WeatherResponse,
+    summary: Option<&str>,
+) -> Result<CreateEmbed, Error> {
+    Ok(match view {
+        View::Current => current_embed(weather, flag, fmt, summary),
+        View::Hourly => render::hourly_embed(&get_forecast_cached(city).await?, fmt),
+        View::Daily => render::daily_embed(&get_forecast_cached(city).await?, fmt),
+    })
+}
+
+// Post a weather report with unit/refresh/forecast buttons and handle clicks until they expire
+pub async fn send_weather(
+    ctx: Context<'_>,
+    city: &str,
+    weather: WeatherResponse,
+    flag: Option<&str>,
+    narrative: bool,
+) -> Result<(), Error> {
+    // Components need embeds; plain text channels get the plain report
+    if !render::embeds_allowed(ctx) {
+        return render::send_weather(ctx, &weather, flag).await;
+    }
+
+    let prefix = ctx.id().to_string();
+    let mut weather = weather;
+    let mut fmt = render::unit_format(ctx).await;
+    let mut view = View::Current;
+    let mut polished = Polished::new();
+    // Rewording goes to the LLM, which can take longer than Discord waits for a reply
+    if narrative && narrative::llm_enabled() {
+        ctx.defer().await?;
+    }
+    let text = summary(ctx, narrative, &weather, &fmt, &mut polished).await;
+    let mut embed = current_embed(&weather, flag, &fmt, text.as_deref());
+
+    let handle = ctx
+        .send(
+            CreateReply::default()
+                .embed(embed.clone())
+                .components(components(&prefix, &fmt, view, false)),
+        )
+        .await?;
+
+    let collector_prefix = format!("{}-", prefix);
+    let mut collector = ComponentInteractionCollector::new(ctx)
+        .filter(move |i| i.data.custom_id.starts_with(&collector_prefix))
+        .timeout(button_timeout())
+        .stream();
+
+    while let Some(interaction) = collector.next().await {
+        if !is_authorized(ctx, &interaction) {
+            let response = CreateInteractionResponseMessage::new()
+                .content(format!("Only {} can use these buttons.", ctx.author().name))
+                .ephemeral(true);
+            if let Err(e) = interaction
+                .create_response(ctx, CreateInteractionResponse::Message(response))
+                .await
+            {
+                println!("Error: {:?}", e);
+            }
+            continue;
+        }
+
+        // Acknowledge right away, fetching can take a moment. A click we can't
+        // answer is skipped; bailing out here would leave the buttons live.
+        if let Err(e) = interaction.defer(ctx).await {
+            println!("Error: {:?}", e);
+            continue;
+        }
+
+        match interaction
+            .data
+            .custom_id
+            .trim_start_matches(&format!("{}-", prefix))
+        {
+            "units" => fmt = fmt.with_system(fmt.system.next()),
+            "refresh" => match get_weather_cached(city).await {
+                Ok(fresh) => weather = fresh,
+                Err(e) => println!("Error: {:?}", e),
+            },
+            "current" => view = View::Current,
+            "hourly" => view = View::Hourly,
+            "daily" => view = View::Daily,
+            // Unknown button: redraw the current view so the deferred click still gets its edit
+            _ => {}
+        }
+
+        let text = match view {
+            View::Current => summary(ctx, narrative, &weather, &fmt, &mut polished).await,
+            _ => None,
+        };
+        embed = match render_view(city, flag, &fmt, view, &weather, text.as_deref()).await {
+            Ok(rendered) => rendered,
+            Err(e) => {
+                println!("Error: {:?}", e);
+                view = View::Current;
+                let text = summary(ctx, narrative, &weather, &fmt, &mut polished).await;
+                current_embed(&weather, flag, &fmt, text.as_deref())
+            }
+        };
+
+        let edit = EditInteractionResponse::new()
+            .embed(embed.clone())
+            .components(components(&prefix, &fmt, view, false));
+        if let Err(e) = interaction.edit_response(ctx, edit).await {
+            println!("Error: {:?}", e);
+        }
+    }
+
+    // Collector expired: leave the last view up but grey out the buttons
+    handle
+        .edit(
+            ctx,
+            CreateReply::default()
+                .embed(embed)
+                .components(components(&prefix, &fmt, view, true)),
+        )
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,494 @@
+use crate::storage::JsonStore;
+use crate::weather::{get_weather_at_cached, Coord, WeatherResponse};
+use futures::stream::{self, StreamExt};
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+
+// Built-in catalog, used unless CATALOG_FILE points somewhere else
+const DEFAULT_CATALOG: &str = include_str!("../resources/catalog.json");
+
+// Cities /random won't repeat in a channel until this many others have been shown
+const RECENT_PICKS: usize = 10;
+// Most cities we'll look up when hunting for particular conditions
+const CONDITIONS_SAMPLE: usize = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, poise::ChoiceParameter)]
+pub enum Continent {
+    Africa,
+    Asia,
+    Europe,
+    #[name = "North America"]
+    #[serde(rename = "North America")]
+    NorthAmerica,
+    Oceania,
+    #[name = "South America"]
+    #[serde(rename = "South America")]
+    SouthAmerica,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum Hemisphere {
+    Northern,
+    Southern,
+    Eastern,
+    Western,
+}
+
+impl Hemisphere {
+    fn contains(self, coord: Coord) -> bool {
+        match self {
+            Hemisphere::Northern => coord.lat >= 0.0,
+            Hemisphere::Southern => coord.lat < 0.0,
+            Hemisphere::Eastern => coord.lon >= 0.0,
+            Hemisphere::Western => coord.lon < 0.0,
+        }
+    }
+}
+
+// What it has to be like right now for /random to pick a city
+#[derive(Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum Conditions {
+    Sunny,
+    Cloudy,
+    Rainy,
+    Snowy,
+    #[name = "Below freezing"]
+    Freezing,
+    #[name = "Hot (over 30°C / 86°F)"]
+    Hot,
+}
+
+impl Conditions {
+    pub fn matches(self, weather: &WeatherResponse) -> bool {
+        let id = weather.condition().map(|c| c.id).unwrap_or(0);
+        match self {
+            // Clear or nearly so, and the sun is up
+            Conditions::Sunny => weather
+                .condition()
+                .is_some_and(|c| matches!(c.id, 800 | 801) && c.icon.ends_with('d')),
+            Conditions::Cloudy => matches!(id, 803 | 804),
+            // Thunderstorms, drizzle and rain
+            Conditions::Rainy => matches!(id, 200..=531),
+            Conditions::Snowy => matches!(id, 600..=622),
+            Conditions::Freezing => weather.main.temp.celsius() < 0.0,
+            Conditions::Hot => weather.main.temp.celsius() > 30.0,
+        }
+    }
+
+    // For "Couldn't find anywhere ... right now"
+    pub fn describe(self) -> &'static str {
+        match self {
+            Conditions::Sunny => "sunny",
+            Conditions::Cloudy => "cloudy",
+            Conditions::Rainy => "rainy",
+            Conditions::Snowy => "snowy",
+            Conditions::Freezing => "below freezing",
+            Conditions::Hot => "over 30°C / 86°F",
+        }
+    }
+}
+
+// A city we pick from for /random and sweep for /extremes
+#[derive(Debug, Clone, Deserialize)]
+pub struct CatalogCity {
+    pub name: String,
+    pub country: String,
+    pub flag: String,
+    pub continent: Continent,
+    pub lat: f64,
+    pub lon: f64,
+    pub population: u64,
+}
+
+impl CatalogCity {
+    pub fn coord(&self) -> Coord {
+        Coord {
+            lat: self.lat,
+            lon: self.lon,
+        }
+    }
+}
+
+static CATALOG: OnceLock<Vec<CatalogCity>> = OnceLock::new();
+
+fn parse(text: &str) -> Result<Vec<CatalogCity>, String> {
+    let cities: Vec<CatalogCity> = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    if cities.is_empty() {
+        return Err("no cities".to_string());
+    }
+    let mut seen = HashSet::new();
+    for city in &cities {
+        if city.name.trim().is_empty() || city.country.trim().is_empty() {
+            return Err("every city needs a name and a country".to_string());
+        }
+        if !(-90.0..=90.0).contains(&city.lat) || !(-180.0..=180.0).contains(&city.lon) {
+            return Err(format!("{} has coordinates off the map", city.name));
+        }
+        if !seen.insert((city.name.to_lowercase(), city.country.to_lowercase())) {
+            return Err(format!("{}, {} is listed twice", city.name, city.country));
+        }
+    }
+    Ok(cities)
+}
+
+// Load CATALOG_FILE, or the built-in resources/catalog.json. Call once at startup
+// so a broken file stops the bot instead of the first /random.
+pub fn load() -> Result<(), String> {
+    let cities = match std::env::var("CATALOG_FILE") {
+        Ok(path) => {
+            let text = std::fs::read_to_string(&path)
+                .map_err(|e| format!("could not read {}: {}", path, e))?;
+            parse(&text).map_err(|e| format!("{}: {}", path, e))?
+        }
+        Err(_) => parse(DEFAULT_CATALOG).map_err(|e| format!("built-in catalog.json: {}", e))?,
+    };
+    // Already loaded is fine, the first one wins
+    let _ = CATALOG.set(cities);
+    Ok(())
+}
+
+pub fn cities() -> &'static [CatalogCity] {
+    CATALOG.get_or_init(|| parse(DEFAULT_CATALOG).expect("resources/catalog.json should be valid"))
+}
+
+// Which catalog cities a command may use. Unset fields don't restrict anything.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub continent: Option<Continent>,
+    // "USA", "Japan", ...
+    pub country: Option<String>,
+    pub hemisphere: Option<Hemisphere>,
+    pub min_population: Option<u64>,
+}
+
+impl Filter {
+    fn matches(&self, city: &CatalogCity) -> bool {
+        let country = match &self.country {
+            Some(country) => city.country.eq_ignore_ascii_case(country.trim()),
+            None => true,
+        };
+        let hemisphere = match self.hemisphere {
+            Some(hemisphere) => hemisphere.contains(city.coord()),
+            None => true,
+        };
+        country
+            && hemisphere
+            && (self.continent.is_none() || self.continent == Some(city.continent))
+            && city.population >= self.min_population.unwrap_or(0)
+    }
+}
+
+pub fn filter(filter: &Filter) -> Vec<&'static CatalogCity> {
+    cities().iter().filter(|c| filter.matches(c)).collect()
+}
+
+// Cities /random has shown lately, per channel
+pub struct RecentPicks {
+    store: JsonStore<HashMap<u64, Vec<String>>>,
+}
+
+impl RecentPicks {
+    pub fn new() -> RecentPicks {
+        RecentPicks {
+            store: JsonStore::open("random_recent"),
+        }
+    }
+
+    // Drop the channel's recent cities from the candidates, unless that would leave none
+    pub async fn exclude(
+        &self,
+        channel: u64,
+        candidates: Vec<&'static CatalogCity>,
+    ) -> Vec<&'static CatalogCity> {
+        let recent = self
+            .store
+            .read(|picks| picks.get(&channel).cloned())
+            .await
+            .unwrap_or_default();
+        let fresh: Vec<&'static CatalogCity> = candidates
+            .iter()
+            .copied()
+            .filter(|c| !recent.contains(&key(c)))
+            .collect();
+        if fresh.is_empty() {
+            candidates
+        } else {
+            fresh
+        }
+    }
+
+    pub async fn record(&self, channel: u64, city: &CatalogCity) {
+        self.store
+            .update(|picks| {
+                let recent = picks.entry(channel).or_default();
+                recent.push(key(city));
+                if recent.len() > RECENT_PICKS {
+                    let extra = recent.len() - RECENT_PICKS;
+                    recent.drain(..extra);
+                }
+            })
+            .await;
+    }
+}
+
+impl Default for RecentPicks {
+    fn default() -> Self {
+        RecentPicks::new()
+    }
+}
+
+fn key(city: &CatalogCity) -> String {
+    format!("{}, {}", city.name, city.country)
+}
+
+// A random city from the candidates along with its weather. With conditions we
+// check a handful of them and pick one that matches, so None can mean nowhere
+// we looked fit as well as the lookups failing.
+pub async fn pick(
+    mut candidates: Vec<&'static CatalogCity>,
+    conditions: Option<Conditions>,
+) -> Option<Observation> {
+    candidates.shuffle(&mut rand::thread_rng());
+    let sample = if conditions.is_some() {
+        CONDITIONS_SAMPLE
+    } else {
+        1
+    };
+    candidates.truncate(sample);
+
+    // The sample is already shuffled, so the first match is as random as any
+    let (observations, _) = sweep(candidates).await;
+    observations.into_iter().find(|o| match conditions {
+        Some(conditions) => conditions.matches(&o.weather),
+        None => true,
+    })
+}
+
+// How many lookups may be in flight at once during a sweep, EXTREMES_CONCURRENCY or 5
+fn concurrency() -> usize {
+    std::env::var("EXTREMES_CONCURRENCY")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(5)
+}
+
+pub struct Observation {
+    pub city: &'static CatalogCity,
+    pub weather: WeatherResponse,
+}
+
+// Current weather for every city given, a few at a time so we don't hammer
+// the API. Recently fetched cities come from the cache. Also returns how many
+// lookups failed.
+pub async fn sweep(cities: Vec<&'static CatalogCity>) -> (Vec<Observation>, usize) {
+    // Built up front: a closure inside the stream makes the future not Send,
+    // which tokio::spawn and poise commands need. Looked up by coordinate since
+    // names like San Jose or Santiago can resolve to another country's city.
+    let lookups: Vec<_> = cities
+        .into_iter()
+        .map(|city| async move { (city, get_weather_at_cached(city.coord()).await) })
+        .collect();
+    let results: Vec<_> = stream::iter(lookups)
+        .buffer_unordered(concurrency())
+        .collect()
+        .await;
+
+    let mut observations = Vec::new();
+    let mut failed = 0;
+    for (city, result) in results {
+        match result {
+            Ok(mut weather) => {
+                // The nearest station can be named after a district, show the city
+                weather.name = city.name.clone();
+                observations.push(Observation { city, weather })
+            }
+            Err(e) => {
+                println!("Error fetching {}: {:?}", city.name, e);
+                failed += 1;
+            }
+        }
+    }
+    (observations, failed)
+}
+
+// The standout cities of a sweep
+pub struct Extremes<'a> {
+    pub hottest: &'a Observation,
+    pub coldest: &'a Observation,
+    pub windiest: &'a Observation,
+    pub most_humid: &'a Observation,
+    pub cloudiest: &'a Observation,
+}
+
+fn max_by(observations: &[Observation], key: impl Fn(&Observation) -> f64) -> Option<&Observation> {
+    observations.iter().max_by(|a, b| key(a).total_cmp(&key(b)))
+}
+
+pub fn extremes(observations: &[Observation]) -> Option<Extremes<'_>> {
+    Some(Extremes {
+        hottest: max_by(observations, |o| o.weather.main.temp.fahrenheit())?,
+        coldest: max_by(observations, |o| -o.weather.main.temp.fahrenheit())?,
+        windiest: max_by(observations, |o| o.weather.wind.speed.mph())?,
+        most_humid: max_by(observations, |o| o.weather.main.humidity as f64)?,
+        cloudiest: max_by(observations, |o| o.weather.clouds.all as f64)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::{Speed, Temperature};
+    use crate::weather::Weather;
+
+    fn city(name: &str, country: &str, continent: Continent, lat: f64, lon: f64) -> CatalogCity {
+        CatalogCity {
+            name: name.to_string(),
+            country: country.to_string(),
+            flag: String::new(),
+            continent,
+            lat,
+            lon,
+            population: 1_000_000,
+        }
+    }
+
+    fn weather(id: u32, icon: &str, celsius: f64) -> WeatherResponse {
+        let mut weather = WeatherResponse::default();
+        weather.weather.push(Weather {
+            id,
+            icon: icon.to_string(),
+            ..Weather::default()
+        });
+        weather.main.temp = Temperature::from_celsius(celsius);
+        weather
+    }
+
+    #[test]
+    fn filters_combine() {
+        let lima = city("Lima", "Peru", Continent::SouthAmerica, -12.05, -77.04);
+        assert!(Filter::default().matches(&lima));
+
+        let filter = Filter {
+            continent: Some(Continent::SouthAmerica),
+            country: Some(" peru ".to_string()),
+            hemisphere: Some(Hemisphere::Southern),
+            min_population: Some(1_000_000),
+        };
+        assert!(filter.matches(&lima));
+
+        let wrong = [
+            Filter {
+                continent: Some(Continent::Africa),
+                ..filter.clone()
+            },
+            Filter {
+                country: Some("Chile".to_string()),
+                ..filter.clone()
+            },
+            Filter {
+                hemisphere: Some(Hemisphere::Eastern),
+                ..filter.clone()
+            },
+            Filter {
+                min_population: Some(1_000_001),
+                ..filter.clone()
+            },
+        ];
+        for filter in wrong {
+            assert!(!filter.matches(&lima), "{:?}", filter);
+        }
+    }
+
+    #[test]
+    fn hemispheres_split_at_zero() {
+        let null_island = city("Null Island", "Nowhere", Continent::Africa, 0.0, 0.0);
+        assert!(Hemisphere::Northern.contains(null_island.coord()));
+        assert!(Hemisphere::Eastern.contains(null_island.coord()));
+        assert!(!Hemisphere::Southern.contains(null_island.coord()));
+        assert!(!Hemisphere::Western.contains(null_island.coord()));
+    }
+
+    #[test]
+    fn sunny_needs_the_sun_up() {
+        assert!(Conditions::Sunny.matches(&weather(800, "01d", 20.0)));
+        assert!(Conditions::Sunny.matches(&weather(801, "02d", 20.0)));
+        assert!(!Conditions::Sunny.matches(&weather(800, "01n", 20.0)));
+        assert!(!Conditions::Sunny.matches(&weather(802, "03d", 20.0)));
+        assert!(!Conditions::Sunny.matches(&WeatherResponse::default()));
+    }
+
+    #[test]
+    fn conditions_follow_the_id_ranges() {
+        assert!(Conditions::Cloudy.matches(&weather(803, "04d", 20.0)));
+        assert!(Conditions::Cloudy.matches(&weather(804, "04n", 20.0)));
+        assert!(!Conditions::Cloudy.matches(&weather(802, "03d", 20.0)));
+
+        // Thunderstorms, drizzle and rain, but not snow
+        assert!(Conditions::Rainy.matches(&weather(200, "11d", 20.0)));
+        assert!(Conditions::Rainy.matches(&weather(531, "09d", 20.0)));
+        assert!(!Conditions::Rainy.matches(&weather(600, "13d", 20.0)));
+
+        assert!(Conditions::Snowy.matches(&weather(600, "13d", -2.0)));
+        assert!(Conditions::Snowy.matches(&weather(622, "13d", -2.0)));
+        assert!(!Conditions::Snowy.matches(&weather(701, "50d", -2.0)));
+    }
+
+    #[test]
+    fn temperature_conditions_are_strict() {
+        assert!(Conditions::Freezing.matches(&weather(800, "01d", -0.5)));
+        assert!(!Conditions::Freezing.matches(&weather(800, "01d", 0.0)));
+        assert!(Conditions::Hot.matches(&weather(800, "01d", 30.5)));
+        assert!(!Conditions::Hot.matches(&weather(800, "01d", 30.0)));
+    }
+
+    fn observation(
+        name: &str,
+        celsius: f64,
+        wind_mps: f64,
+        humidity: u32,
+        clouds: u32,
+    ) -> Observation {
+        let city: &'static CatalogCity = Box::leak(Box::new(city(
+            name,
+            "Testland",
+            Continent::Europe,
+            50.0,
+            10.0,
+        )));
+        let mut weather = weather(800, "01d", celsius);
+        weather.wind.speed = Speed::from_meters_per_sec(wind_mps);
+        weather.main.humidity = humidity;
+        weather.clouds.all = clouds;
+        Observation { city, weather }
+    }
+
+    #[test]
+    fn extremes_pick_each_standout() {
+        assert!(extremes(&[]).is_none());
+
+        let observations = [
+            observation("Hot", 35.0, 2.0, 20, 0),
+            observation("Cold", -15.0, 5.0, 60, 40),
+            observation("Windy", 10.0, 20.0, 50, 10),
+            observation("Muggy", 25.0, 1.0, 95, 30),
+            observation("Grey", 12.0, 3.0, 70, 100),
+        ];
+        let extremes = extremes(&observations).unwrap();
+        assert_eq!(extremes.hottest.city.name, "Hot");
+        assert_eq!(extremes.coldest.city.name, "Cold");
+        assert_eq!(extremes.windiest.city.name, "Windy");
+        assert_eq!(extremes.most_humid.city.name, "Muggy");
+        assert_eq!(extremes.cloudiest.city.name, "Grey");
+    }
+
+    #[test]
+    fn one_city_is_every_extreme() {
+        let observations = [observation("Only", 15.0, 4.0, 50, 50)];
+        let extremes = extremes(&observations).unwrap();
+        assert_eq!(extremes.hottest.city.name, "Only");
+        assert_eq!(extremes.coldest.city.name, "Only");
+    }
+}
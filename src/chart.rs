@@ -0,0 +1,323 @@
+use crate::history::Series;
+use crate::units::{Precipitation, Temperature, UnitFormat, UnitSystem};
+use crate::weather::ForecastResponse;
+use crate::Error;
+use chrono::{TimeZone, Utc};
+use image::codecs::png::PngEncoder;
+use image::{ColorType, ImageEncoder};
+use plotters::prelude::*;
+use plotters::style::FontStyle;
+use std::sync::OnceLock;
+
+// Bundled so charts don't depend on the fonts installed where the bot runs
+const FONT: &[u8] = include_bytes!("../resources/DejaVuSans.ttf");
+
+// Image size in pixels
+const WIDTH: u32 = 1000;
+const HEIGHT: u32 = 500;
+
+// Forecast hours when none are given, and the allowed range (the API covers five days)
+pub const DEFAULT_HOURS: u32 = 48;
+pub const MIN_HOURS: u32 = 6;
+pub const MAX_HOURS: u32 = 120;
+
+const TEMPERATURE: RGBColor = RGBColor(214, 69, 65);
+const FEELS_LIKE: RGBColor = RGBColor(240, 160, 60);
+const PRECIPITATION: RGBColor = RGBColor(52, 120, 200);
+const CLOUDS: RGBColor = RGBColor(120, 120, 130);
+const SUNRISE: RGBColor = RGBColor(230, 180, 20);
+const SUNSET: RGBColor = RGBColor(120, 70, 160);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum Source {
+    Forecast,
+    #[name = "Recorded history"]
+    History,
+}
+
+// One moment on the chart. Anything missing is left off.
+pub struct Point {
+    pub at: i64,
+    pub temp: Temperature,
+    pub feels_like: Option<Temperature>,
+    // Falling between this point and the next
+    pub precipitation: Option<Precipitation>,
+    // Percent
+    pub clouds: Option<f64>,
+}
+
+pub struct Chart {
+    pub title: String,
+    // Offset from UTC in seconds, for the time labels
+    pub timezone: i32,
+    // Oldest first
+    pub points: Vec<Point>,
+    // Any one day's sunrise and sunset (unix seconds), repeated across the chart
+    pub sun: Option<(i64, i64)>,
+}
+
+impl Chart {
+    // The next `hours` of 3-hourly forecast entries
+    pub fn forecast(forecast: &ForecastResponse, hours: u32) -> Chart {
+        let points = forecast
+            .hourly(hours as usize)
+            .iter()
+            .map(|entry| {
+                let rain = entry.rain.as_ref().and_then(|r| r.rain_3h.or(r.rain_1h));
+                let snow = entry.snow.as_ref().and_then(|s| s.snow_3h.or(s.snow_1h));
+                let mm = rain.map(|p| p.mm()).unwrap_or(0.0) + snow.map(|p| p.mm()).unwrap_or(0.0);
+                Point {
+                    at: entry.dt,
+                    temp: entry.main.temp,
+                    feels_like: Some(entry.main.feels_like),
+                    precipitation: Some(Precipitation::from_mm(mm)),
+                    clouds: Some(entry.clouds.all as f64),
+                }
+            })
+            .collect();
+        let city = &forecast.city;
+        Chart {
+            title: format!("{} · next {} hours", city.name, hours),
+            timezone: city.timezone,
+            points,
+            sun: Some((city.sunrise as i64, city.sunset as i64)),
+        }
+    }
+
+    // Samples recorded between start and end
+    pub fn history(series: &Series, window: (i64, i64), label: &str) -> Chart {
+        let points = series
+            .within(window)
+            .iter()
+            .map(|s| Point {
+                at: s.at,
+                temp: Temperature::from_kelvin(s.temp),
+                feels_like: s.feels_like.map(Temperature::from_kelvin),
+                precipitation: s.precipitation.map(Precipitation::from_mm),
+                clouds: s.clouds,
+            })
+            .collect();
+        Chart {
+            title: format!("{} · {}", series.name, label),
+            timezone: series.timezone,
+            points,
+            sun: None,
+        }
+    }
+}
+
+// Every sunrise or sunset in the window, stepping a day from a known one
+fn daily(known: i64, (start, end): (i64, i64)) -> Vec<i64> {
+    let first = known - (known - start).div_euclid(86400) * 86400;
+    (0..)
+        .map(|day| first + day * 86400)
+        .take_while(|at| *at <= end)
+        .filter(|at| *at >= start)
+        .collect()
+}
+
+// Location-local time for an axis label, just the day on longer charts
+fn time_label(at: i64, timezone: i32, long: bool) -> String {
+    let pattern = if long { "%a %-d" } else { "%a %H:%M" };
+    match Utc.timestamp_opt(at + timezone as i64, 0).single() {
+        Some(time) => time.format(pattern).to_string(),
+        None => String::new(),
+    }
+}
+
+fn precipitation_value(p: Precipitation, system: UnitSystem) -> f64 {
+    match system {
+        UnitSystem::Imperial => p.inches(),
+        UnitSystem::Metric | UnitSystem::Scientific => p.mm(),
+    }
+}
+
+// Make the bundled font the one "sans-serif" draws with, the first time it's needed
+fn register_font() -> Result<(), Error> {
+    static REGISTERED: OnceLock<bool> = OnceLock::new();
+    let registered = REGISTERED.get_or_init(|| {
+        plotters::style::register_font("sans-serif", FontStyle::Normal, FONT).is_ok()
+    });
+    if *registered {
+        Ok(())
+    } else {
+        Err("the bundled chart font could not be loaded".into())
+    }
+}
+
+// Draw the chart as a PNG. None if there's nothing to plot.
+pub fn render(chart: &Chart, fmt: &UnitFormat) -> Result<Option<Vec<u8>>, Error> {
+    if chart.points.len() < 2 {
+        return Ok(None);
+    }
+    register_font()?;
+    let mut pixels = vec![0u8; (WIDTH * HEIGHT * 3) as usize];
+    draw(chart, fmt, &mut pixels).map_err(|e| e.to_string())?;
+
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png).write_image(&pixels, WIDTH, HEIGHT, ColorType::Rgb8)?;
+    Ok(Some(png))
+}
+
+fn draw(
+    chart: &Chart,
+    fmt: &UnitFormat,
+    pixels: &mut [u8],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let system = fmt.system;
+    let points = &chart.points;
+    let start = points[0].at;
+    let end = points[points.len() - 1].at;
+    // Times from here to the next point, the last one gets the same width as the one before
+    let step = end - points[points.len() - 2].at;
+    let spans: Vec<(i64, i64)> = points
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (p.at, points.get(i + 1).map(|n| n.at).unwrap_or(p.at + step)))
+        .collect();
+    let end = end + step;
+
+    let temps = points
+        .iter()
+        .flat_map(|p| std::iter::once(p.temp).chain(p.feels_like))
+        .map(|t| t.value(system));
+    let low = temps.clone().fold(f64::INFINITY, f64::min).floor() - 2.0;
+    let high = temps.fold(f64::NEG_INFINITY, f64::max).ceil() + 2.0;
+    // Keep a dry chart from scaling a trace of rain up to the top
+    let wettest = points
+        .iter()
+        .filter_map(|p| p.precipitation)
+        .map(|p| precipitation_value(p, system))
+        .fold(0.0, f64::max);
+    let floor = precipitation_value(Precipitation::from_mm(5.0), system);
+    let precipitation_max = (wettest * 1.2).max(floor);
+
+    let root = BitMapBackend::with_buffer(pixels, (WIDTH, HEIGHT)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let mut plot = ChartBuilder::on(&root)
+        .caption(&chart.title, ("sans-serif", 26))
+        .margin(16)
+        .x_label_area_size(40)
+        .y_label_area_size(56)
+        .right_y_label_area_size(64)
+        .build_cartesian_2d(start..end, low..high)?
+        .set_secondary_coord(start..end, 0.0..precipitation_max);
+
+    let long = end - start > 3 * 86400;
+    let timezone = chart.timezone;
+    let precision = if system == UnitSystem::Imperial { 2 } else { 1 };
+    let precipitation_unit = if system == UnitSystem::Imperial {
+        "in"
+    } else {
+        "mm"
+    };
+    plot.configure_mesh()
+        .x_labels(8)
+        .x_label_formatter(&|at| time_label(*at, timezone, long))
+        .y_label_formatter(&|v| fmt.number(*v, 0))
+        .y_desc(format!("Temperature ({})", Temperature::symbol(system)))
+        .draw()?;
+    plot.configure_secondary_axes()
+        .y_label_formatter(&|v| fmt.number(*v, precision))
+        .y_desc(format!("Precipitation ({})", precipitation_unit))
+        .draw()?;
+
+    // Cloud cover shades the background, darker when overcast
+    plot.draw_series(points.iter().zip(&spans).filter_map(|(p, (from, to))| {
+        let clouds = p.clouds?;
+        let shade = CLOUDS.mix(clouds / 100.0 * 0.3).filled();
+        Some(Rectangle::new([(*from, low), (*to, high)], shade))
+    }))?;
+
+    plot.draw_secondary_series(points.iter().zip(&spans).filter_map(|(p, (from, to))| {
+        let value = precipitation_value(p.precipitation?, system);
+        // Leave a gap between bars
+        let pad = (to - from) / 8;
+        let bar = PRECIPITATION.mix(0.6).filled();
+        (value > 0.0).then(|| Rectangle::new([(from + pad, 0.0), (to - pad, value)], bar))
+    }))?
+    .label("Precipitation")
+    .legend(|(x, y)| {
+        Rectangle::new(
+            [(x, y - 5), (x + 20, y + 5)],
+            PRECIPITATION.mix(0.6).filled(),
+        )
+    });
+
+    if let Some((sunrise, sunset)) = chart.sun {
+        for (known, color, label) in [(sunrise, SUNRISE, "Sunrise"), (sunset, SUNSET, "Sunset")] {
+            let times = daily(known, (start, end));
+            if times.is_empty() {
+                continue;
+            }
+            plot.draw_series(
+                times
+                    .into_iter()
+                    .map(|at| PathElement::new(vec![(at, low), (at, high)], color.stroke_width(2))),
+            )?
+            .label(label)
+            .legend(move |(x, y)| {
+                PathElement::new(vec![(x, y), (x + 20, y)], color.stroke_width(2))
+            });
+        }
+    }
+
+    // Feels-like under the real temperature so the latter stays on top where they meet
+    if points.iter().any(|p| p.feels_like.is_some()) {
+        let feels_like = points
+            .iter()
+            .filter_map(|p| Some((p.at, p.feels_like?.value(system))));
+        plot.draw_series(LineSeries::new(feels_like, FEELS_LIKE.stroke_width(2)))?
+            .label("Feels like")
+            .legend(|(x, y)| {
+                PathElement::new(vec![(x, y), (x + 20, y)], FEELS_LIKE.stroke_width(2))
+            });
+    }
+    let temps = points.iter().map(|p| (p.at, p.temp.value(system)));
+    plot.draw_series(LineSeries::new(temps, TEMPERATURE.stroke_width(3)))?
+        .label("Temperature")
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], TEMPERATURE.stroke_width(3)));
+
+    plot.configure_series_labels()
+        .position(SeriesLabelPosition::UpperRight)
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+    root.present()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chart(points: usize) -> Chart {
+        Chart {
+            title: "Paris · next 24 hours".to_string(),
+            timezone: 3600,
+            points: (0..points)
+                .map(|i| Point {
+                    at: 1717243200 + i as i64 * 10800,
+                    temp: Temperature::from_celsius(15.0 + i as f64),
+                    feels_like: Some(Temperature::from_celsius(14.0 + i as f64)),
+                    precipitation: Some(Precipitation::from_mm(i as f64 * 0.5)),
+                    clouds: Some(50.0),
+                })
+                .collect(),
+            sun: Some((1717213500, 1717271100)),
+        }
+    }
+
+    #[test]
+    fn renders_with_the_bundled_font() {
+        let fmt = UnitFormat::new(UnitSystem::Metric, None);
+        let png = render(&chart(8), &fmt).unwrap().unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+    }
+
+    #[test]
+    fn one_point_is_not_a_chart() {
+        let fmt = UnitFormat::new(UnitSystem::Imperial, None);
+        assert!(render(&chart(1), &fmt).unwrap().is_none());
+    }
+}
@@ -1,71 +1,1511 @@
-use dotenv::dotenv;
+use crate::moderation::{self, strip_mentions, Verdict};
+use crate::{Context, Error};
+use futures::channel::mpsc;
+use futures::stream::{self, Stream, StreamExt};
 use poise::serenity_prelude as serenity;
-use reqwest::Client;
-use serde::Deserialize;
+use poise::CreateReply;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
 use serenity::async_trait;
-use serenity::client::{Context as SContext, EventHandler};
-use poise::Context as PoiseContext;
-use serenity::model::gateway::Ready;
-use tokio;
+use std::fmt;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
 
-#[derive(Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub role: String,
+    // Assistant messages that only call tools come back with "content": null
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+fn null_as_empty<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+impl Message {
+    fn new(role: &str, content: impl Into<String>) -> Message {
+        Message {
+            role: role.to_string(),
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+
+    pub fn system(content: impl Into<String>) -> Message {
+        Message::new("system", content)
+    }
+
+    pub fn user(content: impl Into<String>) -> Message {
+        Message::new("user", content)
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Message {
+        Message::new("assistant", content)
+    }
+
+    // The result of running a tool the model asked for
+    pub fn tool(tool_call_id: &str, content: impl Into<String>) -> Message {
+        Message {
+            tool_call_id: Some(tool_call_id.to_string()),
+            ..Message::new("tool", content)
+        }
+    }
+}
+
+// A function the model asked us to run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type", default = "function_type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    // JSON encoded arguments, as a string
+    #[serde(default)]
+    pub arguments: String,
+}
+
+fn function_type() -> String {
+    "function".to_string()
+}
+
+// A function the model is allowed to call
+#[derive(Debug, Clone, Serialize)]
+pub struct Tool {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionDefinition,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FunctionDefinition {
+    pub name: String,
+    pub description: String,
+    // JSON schema of the arguments
+    pub parameters: serde_json::Value,
+}
+
+impl Tool {
+    pub fn function(name: &str, description: &str, parameters: serde_json::Value) -> Tool {
+        Tool {
+            kind: function_type(),
+            function: FunctionDefinition {
+                name: name.to_string(),
+                description: description.to_string(),
+                parameters,
+            },
+        }
+    }
+}
+
+// Body of a /chat/completions request
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatRequest {
+    pub model: String,
+    pub messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<Tool>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamOptions {
+    // Ask for a last chunk with the token counts, streams have none otherwise
+    pub include_usage: bool,
+}
+
+impl ChatRequest {
+    // A request for the client's default model; `model` is filled in on send if empty
+    pub fn new(messages: Vec<Message>) -> ChatRequest {
+        ChatRequest {
+            model: String::new(),
+            messages,
+            max_tokens: None,
+            temperature: None,
+            tools: Vec::new(),
+            stream: false,
+            stream_options: None,
+        }
+    }
+
+    pub fn max_tokens(mut self, max_tokens: u32) -> ChatRequest {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn tools(mut self, tools: Vec<Tool>) -> ChatRequest {
+        self.tools = tools;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct OpenAIResponse {
-    id: String,
-    object: String,
-    created: u64,
-    model: String,
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub model: String,
     pub choices: Vec<Choice>,
+    #[serde(default)]
+    pub usage: Option<Usage>,
+}
+
+impl OpenAIResponse {
+    // Text of the first choice, if the model returned any
+    pub fn text(&self) -> Option<String> {
+        self.choices
+            .first()
+            .map(|c| c.message.content.trim().to_string())
+            .filter(|text| !text.is_empty())
+    }
 }
 
-#[derive(Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Choice {
-    index: u64,
+    #[serde(default)]
+    pub index: u64,
     pub message: Message,
-    finish_reason: String,
+    #[serde(default)]
+    pub finish_reason: Option<String>,
 }
 
-#[derive(Deserialize)]
-pub struct Message {
-    role: String,
-    pub content: String,
+// Error body OpenAI sends back with a non-2xx status
+#[derive(Debug, Deserialize)]
+struct ApiErrorBody {
+    error: ApiErrorDetail,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiErrorDetail {
+    message: String,
+    #[serde(rename = "type", default)]
+    kind: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum LlmError {
+    // Missing or invalid configuration
+    Config(String),
+    // Couldn't reach the server (includes timeouts)
+    Http(reqwest::Error),
+    // The server answered with an error status
+    Api { status: StatusCode, message: String },
+    // The body wasn't the JSON we expected
+    Parse(serde_json::Error),
+    // The model answered with nothing
+    Empty,
+    // The model kept calling tools without ever answering
+    TooManyToolCalls,
+}
+
+impl fmt::Display for LlmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LlmError::Config(message) => write!(f, "LLM is not configured: {}", message),
+            LlmError::Http(e) => write!(f, "could not reach the LLM server: {}", e),
+            LlmError::Api { status, message } => {
+                write!(f, "LLM request failed ({}): {}", status, message)
+            }
+            LlmError::Parse(e) => write!(f, "unexpected LLM response: {}", e),
+            LlmError::Empty => write!(f, "the LLM returned an empty response"),
+            LlmError::TooManyToolCalls => write!(f, "the LLM kept calling tools without answering"),
+        }
+    }
+}
+
+impl std::error::Error for LlmError {}
+
+impl LlmError {
+    // Worth trying the same request again
+    fn is_retryable(&self) -> bool {
+        match self {
+            LlmError::Http(e) => e.is_timeout() || e.is_connect(),
+            LlmError::Api { status, .. } => {
+                *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+            _ => false,
+        }
+    }
+}
+
+// Something a streamed completion sends back
+#[derive(Debug, Clone)]
+pub enum StreamEvent {
+    // A piece of the reply text
+    Text(String),
+    // The server's token count, at the end if it sends one
+    Usage(Usage),
+}
+
+// Reply text as it arrives from the server
+pub type TextStream = Pin<Box<dyn Stream<Item = Result<StreamEvent, LlmError>> + Send>>;
+
+// A finished completion as a stream: all the text at once, then its usage
+fn whole(response: OpenAIResponse) -> Result<TextStream, LlmError> {
+    let text = response.text().ok_or(LlmError::Empty)?;
+    let events = std::iter::once(StreamEvent::Text(text))
+        .chain(response.usage.map(StreamEvent::Usage))
+        .map(Ok);
+    Ok(Box::pin(stream::iter(events.collect::<Vec<_>>())))
+}
+
+// Anything that can answer a chat completion. Commands go through this so a
+// fake can stand in for the real server.
+#[async_trait]
+pub trait Llm: Send + Sync {
+    async fn complete(&self, request: ChatRequest) -> Result<OpenAIResponse, LlmError>;
+
+    // Stream the reply text as it's generated. The default waits for the
+    // whole completion and yields it as a single piece.
+    async fn stream(&self, request: ChatRequest) -> Result<TextStream, LlmError> {
+        whole(self.complete(request).await?)
+    }
+
+    // What the backend supports; fakes get everything
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    // Single-turn helper: send one user prompt and return the reply text
+    async fn ask(&self, prompt: &str, max_tokens: u32) -> Result<String, LlmError> {
+        let request = ChatRequest::new(vec![Message::user(prompt)]).max_tokens(max_tokens);
+        self.complete(request).await?.text().ok_or(LlmError::Empty)
+    }
+}
+
+// Which kind of server the client talks to. They all speak the OpenAI
+// /v1/chat/completions protocol but differ in defaults and features.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    OpenAi,
+    LlamaCpp,
+    Ollama,
+    Vllm,
+    // Any other OpenAI compatible server, LLM_BASE_URL is required
+    Custom,
+}
+
+impl Backend {
+    pub fn parse(name: &str) -> Option<Backend> {
+        match name.trim().to_lowercase().as_str() {
+            "openai" => Some(Backend::OpenAi),
+            "llamacpp" | "llama.cpp" | "llama-cpp" => Some(Backend::LlamaCpp),
+            "ollama" => Some(Backend::Ollama),
+            "vllm" => Some(Backend::Vllm),
+            "custom" => Some(Backend::Custom),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::OpenAi => "openai",
+            Backend::LlamaCpp => "llama.cpp",
+            Backend::Ollama => "ollama",
+            Backend::Vllm => "vllm",
+            Backend::Custom => "custom",
+        }
+    }
+
+    fn default_base_url(self) -> Option<&'static str> {
+        match self {
+            Backend::OpenAi => Some("https://api.openai.com/v1"),
+            Backend::LlamaCpp => Some("http://localhost:8080/v1"),
+            Backend::Ollama => Some("http://localhost:11434/v1"),
+            Backend::Vllm => Some("http://localhost:8000/v1"),
+            Backend::Custom => None,
+        }
+    }
+
+    fn default_model(self) -> &'static str {
+        match self {
+            Backend::OpenAi => "gpt-3.5-turbo",
+            Backend::Ollama => "llama3.1",
+            // llama.cpp serves whatever model it was started with and ignores the name
+            Backend::LlamaCpp | Backend::Vllm | Backend::Custom => "default",
+        }
+    }
+
+    // Env var holding this backend's model name, e.g. OLLAMA_MODEL
+    fn model_env(self) -> &'static str {
+        match self {
+            Backend::OpenAi => "OPENAI_MODEL",
+            Backend::LlamaCpp => "LLAMACPP_MODEL",
+            Backend::Ollama => "OLLAMA_MODEL",
+            Backend::Vllm => "VLLM_MODEL",
+            Backend::Custom => "LLM_MODEL",
+        }
+    }
+
+    fn requires_api_key(self) -> bool {
+        self == Backend::OpenAi
+    }
+}
+
+// What the configured server/model can do
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    // Models the server reported from /models (empty if it doesn't say)
+    pub models: Vec<String>,
+    pub streaming: bool,
+    pub tools: bool,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities {
+            models: Vec::new(),
+            streaming: true,
+            tools: true,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelList {
+    data: Vec<ModelEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelEntry {
+    id: String,
+}
+
+// Settings for LlmClient, normally read from the environment
+#[derive(Debug, Clone)]
+pub struct LlmConfig {
+    pub backend: Backend,
+    pub base_url: String,
+    pub model: String,
+    pub api_key: Option<String>,
+    pub timeout: Duration,
+    pub max_retries: u32,
+}
+
+impl LlmConfig {
+    // LLM_BACKEND picks the server type (openai by default). LLM_BASE_URL,
+    // LLM_MODEL/<BACKEND>_MODEL and LLM_API_KEY override its defaults;
+    // OPENAI_BASE_URL and OPENAI_API_KEY are still honoured.
+    pub fn from_env() -> Result<LlmConfig, LlmError> {
+        let backend = match std::env::var("LLM_BACKEND") {
+            Ok(name) => Backend::parse(&name)
+                .ok_or_else(|| LlmError::Config(format!("unknown LLM_BACKEND '{}'", name)))?,
+            Err(_) => Backend::OpenAi,
+        };
+
+        let base_url = std::env::var("LLM_BASE_URL")
+            .or_else(|_| std::env::var("OPENAI_BASE_URL"))
+            .ok()
+            .or_else(|| backend.default_base_url().map(String::from))
+            .ok_or_else(|| {
+                LlmError::Config("LLM_BASE_URL is required for a custom backend".to_string())
+            })?;
+
+        let model = std::env::var("LLM_MODEL")
+            .or_else(|_| std::env::var(backend.model_env()))
+            .unwrap_or_else(|_| backend.default_model().to_string());
+
+        // Local servers usually don't need a key, so only OpenAI falls back to OPENAI_API_KEY
+        let api_key = std::env::var("LLM_API_KEY")
+            .ok()
+            .or_else(|| {
+                if backend == Backend::OpenAi {
+                    std::env::var("OPENAI_API_KEY").ok()
+                } else {
+                    None
+                }
+            })
+            .filter(|k| !k.is_empty());
+
+        Ok(LlmConfig {
+            backend,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            model,
+            api_key,
+            timeout: Duration::from_secs(env_or("LLM_TIMEOUT_SECS", 30)),
+            max_retries: env_or("LLM_MAX_RETRIES", 2),
+        })
+    }
+}
+
+// Client for an OpenAI style /chat/completions endpoint
+pub struct LlmClient {
+    http: Client,
+    config: LlmConfig,
+    capabilities: Capabilities,
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+impl LlmClient {
+    pub fn new(config: LlmConfig) -> Result<LlmClient, LlmError> {
+        // No overall timeout, a long streamed reply can take minutes. Give up on
+        // connecting, or on a server that goes quiet mid-reply, instead.
+        let http = Client::builder()
+            .connect_timeout(config.timeout)
+            .read_timeout(config.timeout)
+            .build()
+            .map_err(LlmError::Http)?;
+
+        Ok(LlmClient {
+            http,
+            config,
+            capabilities: Capabilities::default(),
+        })
+    }
+
+    pub fn from_env() -> Result<LlmClient, LlmError> {
+        LlmClient::new(LlmConfig::from_env()?)
+    }
+
+    pub fn config(&self) -> &LlmConfig {
+        &self.config
+    }
+
+    // Add the bearer token if one is configured
+    fn authorize(&self, builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.config.api_key {
+            Some(key) => builder.bearer_auth(key),
+            None => builder,
+        }
+    }
+
+    // Ask the server which models it serves and work out what it supports.
+    // Failures are logged and the defaults kept, the bot still starts.
+    pub async fn detect_capabilities(&mut self) {
+        let request = self.http.get(format!("{}/models", self.config.base_url));
+        let models = match self.authorize(request).send().await {
+            Ok(res) if res.status().is_success() => match res.json::<ModelList>().await {
+                Ok(list) => list.data.into_iter().map(|m| m.id).collect(),
+                Err(e) => {
+                    println!(
+                        "Could not parse model list from {}: {}",
+                        self.config.base_url, e
+                    );
+                    Vec::new()
+                }
+            },
+            Ok(res) => {
+                println!(
+                    "{} /models returned {}",
+                    self.config.backend.name(),
+                    res.status()
+                );
+                Vec::new()
+            }
+            Err(e) => {
+                println!(
+                    "Could not reach {} at {}: {}",
+                    self.config.backend.name(),
+                    self.config.base_url,
+                    e
+                );
+                Vec::new()
+            }
+        };
+
+        // A single-model server (llama.cpp) answers whatever name we send, so use its own
+        if models.len() == 1 && !models.contains(&self.config.model) {
+            if self.config.backend != Backend::OpenAi {
+                self.config.model = models[0].clone();
+            }
+        } else if !models.is_empty() && !models.contains(&self.config.model) {
+            println!(
+                "Warning: model '{}' is not served by {} (available: {})",
+                self.config.model,
+                self.config.backend.name(),
+                models.join(", ")
+            );
+        }
+
+        // Tool calling is off unless the backend is known to support it; LLM_TOOLS overrides
+        let tools = match self.config.backend {
+            Backend::OpenAi | Backend::Vllm | Backend::Ollama => true,
+            Backend::LlamaCpp | Backend::Custom => false,
+        };
+        self.capabilities = Capabilities {
+            models,
+            streaming: env_or("LLM_STREAMING", true),
+            tools: env_or("LLM_TOOLS", tools),
+        };
+        println!(
+            "Using {} backend at {} with model '{}'",
+            self.config.backend.name(),
+            self.config.base_url,
+            self.config.model
+        );
+    }
+
+    // POST a completion request, turning error statuses into LlmError::Api
+    async fn post(&self, request: &ChatRequest) -> Result<reqwest::Response, LlmError> {
+        // Checked per request so the rest of the bot still runs without a key
+        if self.config.backend.requires_api_key() && self.config.api_key.is_none() {
+            return Err(LlmError::Config("missing OPENAI_API_KEY".to_string()));
+        }
+
+        let request = self
+            .http
+            .post(format!("{}/chat/completions", self.config.base_url))
+            .json(request);
+        let res = self
+            .authorize(request)
+            .send()
+            .await
+            .map_err(LlmError::Http)?;
+
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await.map_err(LlmError::Http)?;
+            // OpenAI (and most compatible servers) explain what went wrong in {"error": {"message": ...}}
+            let message = match serde_json::from_str::<ApiErrorBody>(&body) {
+                Ok(parsed) => match parsed.error.kind {
+                    Some(kind) => format!("{} ({})", parsed.error.message, kind),
+                    None => parsed.error.message,
+                },
+                Err(_) => body,
+            };
+            return Err(LlmError::Api { status, message });
+        }
+        Ok(res)
+    }
+
+    async fn send_once(&self, request: &ChatRequest) -> Result<OpenAIResponse, LlmError> {
+        let res = self.post(request).await?;
+        let body = res.text().await.map_err(LlmError::Http)?;
+        serde_json::from_str(&body).map_err(LlmError::Parse)
+    }
+}
+
+// One server-sent event of a streamed completion
+#[derive(Debug, Deserialize)]
+struct StreamChunk {
+    #[serde(default)]
+    choices: Vec<StreamChoice>,
+    // Only on the last chunk, when include_usage was asked for
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamChoice {
+    #[serde(default)]
+    delta: StreamDelta,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct StreamDelta {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+// Read the SSE body and forward each piece of text and the usage. Stops
+// (closing the connection) as soon as the receiving side is dropped.
+async fn forward_stream(
+    res: reqwest::Response,
+    tx: mpsc::UnboundedSender<Result<StreamEvent, LlmError>>,
+) {
+    let mut body = res.bytes_stream();
+    let mut buffer: Vec<u8> = Vec::new();
+
+    while let Some(chunk) = body.next().await {
+        match chunk {
+            Ok(bytes) => buffer.extend_from_slice(&bytes),
+            Err(e) => {
+                let _ = tx.unbounded_send(Err(LlmError::Http(e)));
+                return;
+            }
+        }
+
+        // Events are separated by newlines; keep any partial line for the next chunk
+        while let Some(end) = buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let data = match line.trim().strip_prefix("data:") {
+                Some(data) => data.trim().to_string(),
+                None => continue,
+            };
+            if data == "[DONE]" {
+                return;
+            }
+
+            let parsed = match serde_json::from_str::<StreamChunk>(&data) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    let _ = tx.unbounded_send(Err(LlmError::Parse(e)));
+                    return;
+                }
+            };
+            let text = parsed
+                .choices
+                .into_iter()
+                .next()
+                .and_then(|c| c.delta.content)
+                .map(StreamEvent::Text);
+            for event in text.into_iter().chain(parsed.usage.map(StreamEvent::Usage)) {
+                if tx.unbounded_send(Ok(event)).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Llm for LlmClient {
+    async fn complete(&self, mut request: ChatRequest) -> Result<OpenAIResponse, LlmError> {
+        if request.model.is_empty() {
+            request.model = self.config.model.clone();
+        }
+
+        let mut attempt = 0;
+        loop {
+            match self.send_once(&request).await {
+                Err(e) if e.is_retryable() && attempt < self.config.max_retries => {
+                    // Back off 1s, 2s, 4s, ... between attempts
+                    let delay = Duration::from_secs(1 << attempt);
+                    println!("LLM request failed, retrying in {:?}: {}", delay, e);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn stream(&self, mut request: ChatRequest) -> Result<TextStream, LlmError> {
+        if !self.capabilities.streaming {
+            return whole(self.complete(request).await?);
+        }
+        if request.model.is_empty() {
+            request.model = self.config.model.clone();
+        }
+        request.stream = true;
+        request.stream_options = Some(StreamOptions {
+            include_usage: true,
+        });
+
+        let res = self.post(&request).await?;
+        let (tx, rx) = mpsc::unbounded();
+        tokio::spawn(forward_stream(res, tx));
+        Ok(Box::pin(rx))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities.clone()
+    }
+}
+
+// Discord's message length limit, in characters
+pub const DISCORD_MESSAGE_LIMIT: usize = 2000;
+
+// How often a streaming reply is edited, Discord rate limits edits
+const EDIT_INTERVAL: Duration = Duration::from_millis(1200);
+
+// Cut text down to what fits in a Discord message
+pub fn clamp_message(text: &str) -> String {
+    if text.chars().count() <= DISCORD_MESSAGE_LIMIT {
+        return text.to_string();
+    }
+    let mut clamped: String = text.chars().take(DISCORD_MESSAGE_LIMIT - 1).collect();
+    clamped.push('…');
+    clamped
+}
+
+// Where a streamed reply shows up. Commands use the Discord reply, tests a
+// recorder, so the streaming logic can be checked without a gateway.
+#[async_trait]
+pub trait ReplyTarget: Send {
+    // Post the text the first time, edit the posted message after that. An
+    // error usually means the message was deleted.
+    async fn show(&mut self, text: &str) -> Result<(), Error>;
+
+    // Local filter for partial text, cheap enough to run before every edit
+    fn quick_check(&self, text: &str) -> Verdict;
+
+    // Every moderation stage, for the first preview and the finished text.
+    // Returns it with mentions defused, or None (logged) if it shouldn't be posted.
+    async fn screen(&mut self, text: &str) -> Option<String>;
+
+    // Log partial text the quick check stopped
+    async fn record_blocked(&mut self, reason: &str, text: &str);
+}
+
+// The reply to a command, posted on the first show and edited after
+struct DiscordReply<'a> {
+    ctx: Context<'a>,
+    handle: Option<poise::ReplyHandle<'a>>,
+}
+
+#[async_trait]
+impl<'a> ReplyTarget for DiscordReply<'a> {
+    async fn show(&mut self, text: &str) -> Result<(), Error> {
+        match &self.handle {
+            None => self.handle = Some(self.ctx.say(text).await?),
+            Some(reply) => {
+                reply
+                    .edit(self.ctx, CreateReply::default().content(text))
+                    .await?
+            }
+        }
+        Ok(())
+    }
+
+    fn quick_check(&self, text: &str) -> Verdict {
+        self.ctx.data().moderation.quick_check(text)
+    }
+
+    async fn screen(&mut self, text: &str) -> Option<String> {
+        moderation::screen(self.ctx, text).await
+    }
+
+    async fn record_blocked(&mut self, reason: &str, text: &str) {
+        self.ctx
+            .data()
+            .moderation
+            .record(self.ctx, reason, text)
+            .await;
+    }
+}
+
+// Defer the interaction, then stream the completion into a reply. With `live`
+// the reply is edited as text arrives, otherwise it's posted once finished.
+// If the reply is deleted part way through we stop generating. `fallback` is
+// posted if nothing usable comes back, the stream breaks off or moderation
+// blocks the reply, and `finish` gets a last look at the full text before it's
+// shown (e.g. to swap out a repeat). Returns the text that ended up in the
+// reply, or None if the reply couldn't be shown.
+pub async fn stream_reply(
+    ctx: Context<'_>,
+    llm: &dyn Llm,
+    request: ChatRequest,
+    fallback: &str,
+    live: bool,
+    finish: impl FnOnce(String) -> String + Send,
+) -> Result<Option<String>, Error> {
+    ctx.defer().await?;
+    let mut target = DiscordReply { ctx, handle: None };
+    relay(&mut target, llm, request, fallback, live, finish).await
+}
+
+// Everything stream_reply does after deferring
+async fn relay(
+    target: &mut dyn ReplyTarget,
+    llm: &dyn Llm,
+    request: ChatRequest,
+    fallback: &str,
+    live: bool,
+    finish: impl FnOnce(String) -> String,
+) -> Result<Option<String>, Error> {
+    let mut pieces = match llm.stream(request).await {
+        Ok(pieces) => pieces,
+        Err(e) => {
+            println!("Error: {}", e);
+            target.show(fallback).await?;
+            return Ok(Some(fallback.to_string()));
+        }
+    };
+
+    let mut text = String::new();
+    let mut last_edit = Instant::now();
+    let mut blocked = false;
+    let mut broken = false;
+    let mut previewed = false;
+
+    while let Some(piece) = pieces.next().await {
+        match piece {
+            Ok(StreamEvent::Text(piece)) => text.push_str(&piece),
+            Ok(StreamEvent::Usage(_)) => continue,
+            Err(e) => {
+                // Half a reply isn't worth keeping
+                println!("Error: {}", e);
+                broken = true;
+                break;
+            }
+        }
+        if !live || text.trim().is_empty() || last_edit.elapsed() < EDIT_INTERVAL {
+            continue;
+        }
+
+        // Don't show anything the local filter objects to, even briefly
+        if let Verdict::Blocked(reason) = target.quick_check(&text) {
+            target.record_blocked(&reason, &text).await;
+            blocked = true;
+            break;
+        }
+        // Nothing is visible until the first preview, so that one goes
+        // through every stage. Later edits only get the local filter.
+        if !previewed && target.screen(&text).await.is_none() {
+            blocked = true;
+            break;
+        }
+
+        // Show what we have so far with a cursor on the end
+        let preview = clamp_message(&strip_mentions(&format!("{} ▌", text.trim_end())));
+        if let Err(e) = target.show(&preview).await {
+            // Most likely the message was deleted, dropping the stream cancels the request
+            println!("Stopping generation, could not edit reply: {:?}", e);
+            return Ok(None);
+        }
+        last_edit = Instant::now();
+        previewed = true;
+
+        // Nothing more would fit in the message
+        if text.chars().count() >= DISCORD_MESSAGE_LIMIT {
+            break;
+        }
+    }
+
+    let final_text = if blocked || broken || text.trim().is_empty() {
+        fallback.to_string()
+    } else {
+        match target.screen(text.trim()).await {
+            Some(text) => clamp_message(&finish(text)),
+            None => fallback.to_string(),
+        }
+    };
+    if let Err(e) = target.show(&final_text).await {
+        println!("Could not finish reply: {:?}", e);
+        return Ok(None);
+    }
+    Ok(Some(final_text))
+}
+
+// A scripted Llm and reply target for tests
+#[cfg(test)]
+pub mod testing {
+    use super::*;
+    use std::sync::Mutex;
+
+    pub fn response(text: &str, usage: Option<Usage>) -> OpenAIResponse {
+        OpenAIResponse {
+            id: "test".to_string(),
+            model: "fake".to_string(),
+            choices: vec![Choice {
+                index: 0,
+                message: Message::assistant(text),
+                finish_reason: Some("stop".to_string()),
+            }],
+            usage,
+        }
+    }
+
+    // Answers every request with the same reply. Without `pieces` it streams
+    // the whole completion at once, like the trait's default. `usage` comes
+    // with both.
+    pub struct FakeLlm {
+        pub reply: Result<String, ()>,
+        pub usage: Option<Usage>,
+        pub pieces: Option<Vec<String>>,
+        // Completions handed out in order before falling back to `reply`
+        pub script: Mutex<Vec<OpenAIResponse>>,
+        // Every request it was sent
+        pub requests: Mutex<Vec<ChatRequest>>,
+    }
+
+    impl FakeLlm {
+        pub fn replying(text: &str) -> FakeLlm {
+            FakeLlm {
+                reply: Ok(text.to_string()),
+                usage: None,
+                pieces: None,
+                script: Mutex::new(Vec::new()),
+                requests: Mutex::new(Vec::new()),
+            }
+        }
+
+        // Each completion in turn, e.g. tool calls and then an answer
+        pub fn scripted(responses: Vec<OpenAIResponse>) -> FakeLlm {
+            FakeLlm {
+                script: Mutex::new(responses),
+                ..FakeLlm::replying("")
+            }
+        }
+
+        pub fn failing() -> FakeLlm {
+            FakeLlm {
+                reply: Err(()),
+                ..FakeLlm::replying("")
+            }
+        }
+
+        pub fn streaming(pieces: &[&str]) -> FakeLlm {
+            FakeLlm {
+                pieces: Some(pieces.iter().map(|p| p.to_string()).collect()),
+                ..FakeLlm::replying(&pieces.concat())
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Llm for FakeLlm {
+        async fn complete(&self, request: ChatRequest) -> Result<OpenAIResponse, LlmError> {
+            self.requests.lock().unwrap().push(request);
+            let mut script = self.script.lock().unwrap();
+            if !script.is_empty() {
+                return Ok(script.remove(0));
+            }
+            match &self.reply {
+                Ok(text) => Ok(response(text, self.usage.clone())),
+                Err(()) => Err(LlmError::Config("fake failure".to_string())),
+            }
+        }
+
+        async fn stream(&self, request: ChatRequest) -> Result<TextStream, LlmError> {
+            let pieces = match &self.pieces {
+                Some(pieces) => pieces.clone(),
+                None => return whole(self.complete(request).await?),
+            };
+            let events = pieces
+                .into_iter()
+                .map(StreamEvent::Text)
+                .chain(self.usage.clone().map(StreamEvent::Usage))
+                .map(Ok);
+            Ok(Box::pin(stream::iter(events.collect::<Vec<_>>())))
+        }
+    }
+
+    // Remembers everything shown. Text containing `banned` fails moderation,
+    // `flagged` only fails the full screen (like the moderation API), and with
+    // `deleted` every show fails like an edit to a deleted message.
+    #[derive(Default)]
+    pub struct Recorder {
+        pub shown: Vec<String>,
+        pub blocked: Vec<String>,
+        pub banned: Option<&'static str>,
+        pub flagged: Option<&'static str>,
+        pub deleted: bool,
+    }
+
+    #[async_trait]
+    impl ReplyTarget for Recorder {
+        async fn show(&mut self, text: &str) -> Result<(), Error> {
+            if self.deleted {
+                return Err("Unknown Message".into());
+            }
+            self.shown.push(text.to_string());
+            Ok(())
+        }
+
+        fn quick_check(&self, text: &str) -> Verdict {
+            match self.banned {
+                Some(word) if text.contains(word) => Verdict::Blocked(word.to_string()),
+                _ => Verdict::Allowed,
+            }
+        }
+
+        async fn screen(&mut self, text: &str) -> Option<String> {
+            let verdict = match self.flagged {
+                Some(word) if text.contains(word) => Verdict::Blocked(word.to_string()),
+                _ => self.quick_check(text),
+            };
+            match verdict {
+                Verdict::Allowed => Some(strip_mentions(text)),
+                Verdict::Blocked(reason) => {
+                    self.record_blocked(&reason, text).await;
+                    None
+                }
+            }
+        }
+
+        async fn record_blocked(&mut self, _reason: &str, text: &str) {
+            self.blocked.push(text.to_string());
+        }
+    }
+
+    // What the stub answers /chat/completions with
+    #[derive(Clone)]
+    pub enum StubReply {
+        Text(&'static str),
+        Pieces(Vec<&'static str>),
+        // Streamed with a pause before each event, like a slow local model
+        Slow(Vec<&'static str>, Duration),
+        Error(u16, &'static str),
+    }
+
+    // A tiny OpenAI-compatible server on localhost, like llama.cpp or Ollama
+    // would be. Streams when the request asks for it.
+    pub struct StubServer {
+        pub base_url: String,
+        // Request bodies it received
+        pub bodies: std::sync::Arc<Mutex<Vec<String>>>,
+    }
+
+    impl StubServer {
+        pub async fn start(models: &[&'static str], reply: StubReply) -> StubServer {
+            use tokio::net::TcpListener;
+
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+            let bodies = std::sync::Arc::new(Mutex::new(Vec::new()));
+            let models: Vec<&'static str> = models.to_vec();
+            let received = bodies.clone();
+            tokio::spawn(async move {
+                while let Ok((socket, _)) = listener.accept().await {
+                    let (models, reply, received) =
+                        (models.clone(), reply.clone(), received.clone());
+                    tokio::spawn(async move {
+                        if let Err(e) = serve(socket, &models, &reply, &received).await {
+                            println!("Stub server error: {}", e);
+                        }
+                    });
+                }
+            });
+            StubServer { base_url, bodies }
+        }
+
+        pub fn config(&self, backend: Backend) -> LlmConfig {
+            LlmConfig {
+                backend,
+                base_url: self.base_url.clone(),
+                model: "configured".to_string(),
+                api_key: Some("test-key".to_string()),
+                timeout: Duration::from_secs(5),
+                max_retries: 0,
+            }
+        }
+    }
+
+    // Answer one request and close the connection
+    async fn serve(
+        mut socket: tokio::net::TcpStream,
+        models: &[&str],
+        reply: &StubReply,
+        received: &Mutex<Vec<String>>,
+    ) -> std::io::Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Headers, then however much body Content-Length says
+        let mut raw = Vec::new();
+        let mut buf = [0u8; 4096];
+        let header_end = loop {
+            let n = socket.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            raw.extend_from_slice(&buf[..n]);
+            if let Some(end) = raw.windows(4).position(|w| w == b"\r\n\r\n") {
+                break end + 4;
+            }
+        };
+        let head = String::from_utf8_lossy(&raw[..header_end]).to_string();
+        let length = head
+            .lines()
+            .find_map(|l| {
+                let (name, value) = l.split_once(':')?;
+                name.eq_ignore_ascii_case("content-length")
+                    .then(|| value.trim().parse::<usize>().ok())?
+            })
+            .unwrap_or(0);
+        while raw.len() < header_end + length {
+            let n = socket.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            raw.extend_from_slice(&buf[..n]);
+        }
+        let body = String::from_utf8_lossy(&raw[header_end..]).to_string();
+        let path = head.split_whitespace().nth(1).unwrap_or("").to_string();
+
+        let (status, content_type, payload) = if path.ends_with("/models") {
+            let data: Vec<_> = models
+                .iter()
+                .map(|id| serde_json::json!({ "id": id }))
+                .collect();
+            (
+                200,
+                "application/json",
+                serde_json::json!({ "data": data }).to_string(),
+            )
+        } else {
+            received.lock().unwrap().push(body.clone());
+            let sent = serde_json::from_str::<serde_json::Value>(&body).unwrap_or_default();
+            let streaming = sent["stream"] == true;
+            let usage = sent["stream_options"]["include_usage"] == true;
+            match reply {
+                StubReply::Error(status, message) => (
+                    *status,
+                    "application/json",
+                    serde_json::json!({ "error": { "message": message, "type": "invalid_request_error" } })
+                        .to_string(),
+                ),
+                StubReply::Text(text) if !streaming => (
+                    200,
+                    "application/json",
+                    serde_json::json!({
+                        "id": "stub",
+                        "model": "stub-model",
+                        "choices": [{ "index": 0, "message": { "role": "assistant", "content": text } }],
+                        "usage": { "prompt_tokens": 5, "completion_tokens": 7, "total_tokens": 12 },
+                    })
+                    .to_string(),
+                ),
+                StubReply::Text(text) => (200, "text/event-stream", events(&[text], usage)),
+                StubReply::Pieces(pieces) | StubReply::Slow(pieces, _) => {
+                    (200, "text/event-stream", events(pieces, usage))
+                }
+            }
+        };
+
+        if let (StubReply::Slow(_, pause), "text/event-stream") = (reply, content_type) {
+            // No length, the body ends when the connection closes
+            let head =
+                "HTTP/1.1 200 Stub\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\n";
+            socket.write_all(head.as_bytes()).await?;
+            for event in payload.split_inclusive("\n\n") {
+                tokio::time::sleep(*pause).await;
+                socket.write_all(event.as_bytes()).await?;
+                socket.flush().await?;
+            }
+            return socket.shutdown().await;
+        }
+
+        let response = format!(
+            "HTTP/1.1 {} Stub\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            payload.len(),
+            payload
+        );
+        socket.write_all(response.as_bytes()).await?;
+        socket.shutdown().await
+    }
+
+    // A server-sent event per piece, the usage if asked for, then the end marker
+    fn events(pieces: &[&str], usage: bool) -> String {
+        let mut out = String::new();
+        for piece in pieces {
+            let chunk = serde_json::json!({ "choices": [{ "delta": { "content": piece } }] });
+            out.push_str(&format!("data: {}\n\n", chunk));
+        }
+        if usage {
+            // OpenAI sends it in a chunk of its own with no choices
+            let chunk = serde_json::json!({
+                "choices": [],
+                "usage": { "prompt_tokens": 9, "completion_tokens": 3, "total_tokens": 12 },
+            });
+            out.push_str(&format!("data: {}\n\n", chunk));
+        }
+        out.push_str("data: [DONE]\n\n");
+        out
+    }
 }
 
-type Data = ();
-pub type Error = Box<dyn std::error::Error + Send + Sync>;
-pub type Context<'a> = PoiseContext<'a, Data, Error>;
-
-#[poise::command(slash_command, prefix_command)]
-pub async fn weather_joke(ctx: Context<'_>) -> Result<(), Error> {
-    let http_client = Client::new();
-    let api = std::env::var("OPENAI_API_KEY").expect("missing OPENAI_API_KEY");
-    let request_body = serde_json::json!({
-        "model": "gpt-3.5-turbo",
-        "messages": [
-            {
-                "role": "user",
-                "content": "tell me a joke about the weather or jokes about meteorologists"
-            }
-        ],
-        "max_tokens": 50,
-    });
-
-    let res = http_client
-        .post("https://api.openai.com/v1/chat/completions")
-        .bearer_auth(api)
-        .json(&request_body)
-        .send()
-        .await?;
-
-    let response_text = res.text().await?;
-    let response: OpenAIResponse = serde_json::from_str(&response_text)?;
-
-    let response_text = response
-        .choices
-        .get(0)
-        .and_then(|c| Some(c.message.content.clone()))
-        .unwrap_or_else(|| String::from("No response"));
-
-    poise::say_reply(ctx, response_text).await?;
-
-    Ok(())
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::testing::{FakeLlm, Recorder, StubReply, StubServer};
+    use super::*;
+
+    fn request() -> ChatRequest {
+        ChatRequest::new(vec![Message::user("hello")])
+    }
+
+    #[tokio::test]
+    async fn default_stream_yields_the_whole_completion() {
+        // Only `complete` is implemented, like a backend without streaming
+        struct Plain;
+        #[async_trait]
+        impl Llm for Plain {
+            async fn complete(&self, _: ChatRequest) -> Result<OpenAIResponse, LlmError> {
+                Ok(testing::response("  all at once  ", None))
+            }
+        }
+        let pieces: Vec<_> = Plain.stream(request()).await.unwrap().collect().await;
+        assert_eq!(pieces.len(), 1);
+        assert!(matches!(&pieces[0], Ok(StreamEvent::Text(t)) if t == "all at once"));
+    }
+
+    #[tokio::test]
+    async fn default_stream_of_an_empty_reply_is_an_error() {
+        struct Silent;
+        #[async_trait]
+        impl Llm for Silent {
+            async fn complete(&self, _: ChatRequest) -> Result<OpenAIResponse, LlmError> {
+                Ok(testing::response("   ", None))
+            }
+        }
+        assert!(matches!(
+            Silent.stream(request()).await,
+            Err(LlmError::Empty)
+        ));
+    }
+
+    #[tokio::test]
+    async fn relay_posts_the_finished_text() {
+        let llm = FakeLlm::streaming(&["It's ", "sunny ", "@everyone"]);
+        let mut target = Recorder::default();
+        let posted = relay(&mut target, &llm, request(), "fallback", true, |t| t)
+            .await
+            .unwrap();
+        assert_eq!(posted.as_deref().unwrap(), "It's sunny @\u{200B}everyone");
+        assert_eq!(target.shown.last(), posted.as_ref());
+    }
+
+    #[tokio::test]
+    async fn relay_falls_back_when_the_llm_fails() {
+        let llm = FakeLlm::failing();
+        let mut target = Recorder::default();
+        let posted = relay(&mut target, &llm, request(), "fallback", true, |t| t)
+            .await
+            .unwrap();
+        assert_eq!(posted.as_deref().unwrap(), "fallback");
+        assert_eq!(target.shown, vec!["fallback"]);
+    }
+
+    #[tokio::test]
+    async fn relay_falls_back_on_an_empty_reply() {
+        let llm = FakeLlm::streaming(&["", "  "]);
+        let mut target = Recorder::default();
+        let posted = relay(&mut target, &llm, request(), "fallback", true, |t| t)
+            .await
+            .unwrap();
+        assert_eq!(posted.as_deref().unwrap(), "fallback");
+    }
+
+    #[tokio::test]
+    async fn relay_falls_back_when_moderation_blocks() {
+        let llm = FakeLlm::streaming(&["something ", "rude"]);
+        let mut target = Recorder {
+            banned: Some("rude"),
+            ..Default::default()
+        };
+        let posted = relay(&mut target, &llm, request(), "fallback", true, |t| t)
+            .await
+            .unwrap();
+        assert_eq!(posted.as_deref().unwrap(), "fallback");
+        assert_eq!(target.blocked, vec!["something rude"]);
+        assert!(target.shown.iter().all(|s| !s.contains("rude")));
+    }
+
+    #[tokio::test]
+    async fn relay_lets_finish_swap_the_text() {
+        let llm = FakeLlm::replying("a repeat");
+        let mut target = Recorder::default();
+        let posted = relay(&mut target, &llm, request(), "fallback", true, |_| {
+            "something new".to_string()
+        })
+        .await
+        .unwrap();
+        assert_eq!(posted.as_deref().unwrap(), "something new");
+    }
+
+    // Sends its pieces slowly enough for every one to get a preview
+    struct Paced(Vec<Result<&'static str, ()>>);
+
+    #[async_trait]
+    impl Llm for Paced {
+        // Only streams
+        async fn complete(&self, _: ChatRequest) -> Result<OpenAIResponse, LlmError> {
+            Err(LlmError::Empty)
+        }
+
+        async fn stream(&self, _: ChatRequest) -> Result<TextStream, LlmError> {
+            let pieces = self.0.clone();
+            Ok(Box::pin(stream::iter(pieces).then(|piece| async move {
+                tokio::time::sleep(EDIT_INTERVAL + Duration::from_millis(50)).await;
+                piece
+                    .map(|text| StreamEvent::Text(text.to_string()))
+                    .map_err(|()| LlmError::Empty)
+            })))
+        }
+    }
+
+    #[tokio::test]
+    async fn relay_previews_live_replies_only() {
+        let llm = Paced(vec![Ok("Warm "), Ok("today")]);
+        let mut live = Recorder::default();
+        relay(&mut live, &llm, request(), "fallback", true, |t| t)
+            .await
+            .unwrap();
+        assert_eq!(live.shown, vec!["Warm ▌", "Warm today ▌", "Warm today"]);
+
+        let mut buffered = Recorder::default();
+        relay(&mut buffered, &llm, request(), "fallback", false, |t| t)
+            .await
+            .unwrap();
+        assert_eq!(buffered.shown, vec!["Warm today"]);
+    }
+
+    #[tokio::test]
+    async fn relay_screens_fully_before_the_first_preview() {
+        let llm = Paced(vec![Ok("Something nasty "), Ok("and more")]);
+        let mut target = Recorder {
+            flagged: Some("nasty"),
+            ..Default::default()
+        };
+        let posted = relay(&mut target, &llm, request(), "fallback", true, |t| t)
+            .await
+            .unwrap();
+        assert_eq!(posted.as_deref().unwrap(), "fallback");
+        assert_eq!(target.shown, vec!["fallback"]);
+        assert_eq!(target.blocked, vec!["Something nasty "]);
+    }
+
+    #[tokio::test]
+    async fn relay_falls_back_when_the_stream_breaks_off() {
+        let llm = Paced(vec![Ok("Warm "), Err(())]);
+        let mut target = Recorder::default();
+        let posted = relay(&mut target, &llm, request(), "fallback", true, |t| t)
+            .await
+            .unwrap();
+        assert_eq!(posted.as_deref().unwrap(), "fallback");
+    }
+
+    #[tokio::test]
+    async fn relay_reports_nothing_posted_when_the_reply_is_gone() {
+        let llm = FakeLlm::replying("Sunny");
+        let mut target = Recorder {
+            deleted: true,
+            ..Default::default()
+        };
+        let posted = relay(&mut target, &llm, request(), "fallback", false, |t| t)
+            .await
+            .unwrap();
+        assert!(posted.is_none());
+    }
+
+    #[tokio::test]
+    async fn client_completes_against_a_local_server() {
+        let server = StubServer::start(&[], StubReply::Text("Mild and dry")).await;
+        let client = LlmClient::new(server.config(Backend::Custom)).unwrap();
+        let response = client.complete(request()).await.unwrap();
+        assert_eq!(response.text().unwrap(), "Mild and dry");
+        assert_eq!(response.usage.unwrap().total_tokens, 12);
+
+        // The configured model fills in an empty one
+        let sent: serde_json::Value =
+            serde_json::from_str(&server.bodies.lock().unwrap()[0]).unwrap();
+        assert_eq!(sent["model"], "configured");
+        assert!(sent.get("stream").is_none());
+    }
+
+    #[tokio::test]
+    async fn client_streams_server_sent_events() {
+        let reply = StubReply::Pieces(vec!["Light ", "rain ", "later"]);
+        let server = StubServer::start(&[], reply).await;
+        let client = LlmClient::new(server.config(Backend::Custom)).unwrap();
+        let mut pieces = Vec::new();
+        let mut usage = None;
+        let mut events = client.stream(request()).await.unwrap();
+        while let Some(event) = events.next().await {
+            match event.unwrap() {
+                StreamEvent::Text(text) => pieces.push(text),
+                StreamEvent::Usage(u) => usage = Some(u),
+            }
+        }
+        assert_eq!(pieces, vec!["Light ", "rain ", "later"]);
+        // The count from the last chunk comes through
+        assert_eq!(usage.unwrap().total_tokens, 12);
+
+        let sent: serde_json::Value =
+            serde_json::from_str(&server.bodies.lock().unwrap()[0]).unwrap();
+        assert_eq!(sent["stream"], true);
+        assert_eq!(sent["stream_options"]["include_usage"], true);
+    }
+
+    #[tokio::test]
+    async fn slow_streams_outlast_the_timeout() {
+        // Each pause is under the timeout, all of them together are well over
+        let reply = StubReply::Slow(
+            vec!["Fog ", "lifting ", "by ", "noon"],
+            Duration::from_millis(150),
+        );
+        let server = StubServer::start(&[], reply).await;
+        let mut config = server.config(Backend::Custom);
+        config.timeout = Duration::from_millis(400);
+        let client = LlmClient::new(config).unwrap();
+
+        let started = std::time::Instant::now();
+        let mut text = String::new();
+        let mut events = client.stream(request()).await.unwrap();
+        while let Some(event) = events.next().await {
+            if let StreamEvent::Text(piece) = event.unwrap() {
+                text.push_str(&piece);
+            }
+        }
+        assert_eq!(text, "Fog lifting by noon");
+        assert!(started.elapsed() > Duration::from_millis(600));
+    }
+
+    #[tokio::test]
+    async fn client_reports_api_errors() {
+        let reply = StubReply::Error(400, "model not found");
+        let server = StubServer::start(&[], reply).await;
+        let client = LlmClient::new(server.config(Backend::Custom)).unwrap();
+        match client.complete(request()).await {
+            Err(LlmError::Api { status, message }) => {
+                assert_eq!(status, StatusCode::BAD_REQUEST);
+                assert_eq!(message, "model not found (invalid_request_error)");
+            }
+            other => panic!("expected an API error, got {:?}", other.map(|r| r.text())),
+        }
+    }
+
+    #[tokio::test]
+    async fn single_model_servers_use_their_own_model() {
+        let server = StubServer::start(&["qwen2.5-7b"], StubReply::Text("ok")).await;
+        let mut client = LlmClient::new(server.config(Backend::LlamaCpp)).unwrap();
+        client.detect_capabilities().await;
+        assert_eq!(client.config().model, "qwen2.5-7b");
+        assert_eq!(client.capabilities().models, vec!["qwen2.5-7b"]);
+        // llama.cpp isn't assumed to call tools
+        assert!(!client.capabilities().tools);
+    }
+
+    #[tokio::test]
+    async fn openai_keeps_the_configured_model() {
+        let server = StubServer::start(&["gpt-4o", "gpt-4o-mini"], StubReply::Text("ok")).await;
+        let mut client = LlmClient::new(server.config(Backend::OpenAi)).unwrap();
+        client.detect_capabilities().await;
+        assert_eq!(client.config().model, "configured");
+        assert_eq!(client.capabilities().models.len(), 2);
+        assert!(client.capabilities().tools);
+    }
+
+    #[tokio::test]
+    async fn unreachable_servers_keep_the_defaults() {
+        // Nothing listens on port 9 on localhost
+        let mut client = LlmClient::new(LlmConfig {
+            backend: Backend::Ollama,
+            base_url: "http://127.0.0.1:9/v1".to_string(),
+            model: "configured".to_string(),
+            api_key: None,
+            timeout: Duration::from_secs(5),
+            max_retries: 0,
+        })
+        .unwrap();
+        client.detect_capabilities().await;
+        assert_eq!(client.config().model, "configured");
+        assert!(client.capabilities().models.is_empty());
+        assert!(client.capabilities().streaming);
+    }
+}
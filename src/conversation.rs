@@ -0,0 +1,354 @@
+use crate::chatbot::{ChatRequest, Llm, Message};
+use crate::storage::JsonStore;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Messages kept word for word; older ones are folded into the summary
+const KEEP_MESSAGES: usize = 20;
+// Summarize early if the history gets this long, whatever the message count
+const SUMMARIZE_OVER_CHARS: usize = 6_000;
+
+const CHAT_PROMPT: &str = "You're chatting with people in a Discord channel. \
+Each user message starts with the sender's name. Keep replies under 150 words.";
+
+// What the bot remembers about one channel or thread
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Conversation {
+    // Running summary of everything older than `messages`
+    pub summary: String,
+    pub messages: Vec<Message>,
+    pub updated: i64,
+    // Which conversation in the channel this is, so one started after a reset
+    // isn't mistaken for the one it replaced
+    pub generation: u64,
+}
+
+impl Conversation {
+    fn chars(&self) -> usize {
+        self.messages.iter().map(|m| m.content.len()).sum()
+    }
+
+    fn needs_summary(&self) -> bool {
+        self.messages.len() > KEEP_MESSAGES || self.chars() > SUMMARIZE_OVER_CHARS
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConversationData {
+    // Keyed by channel (threads have their own ids)
+    pub channels: HashMap<u64, Conversation>,
+    // City whose weather is given to the model, per guild
+    pub default_cities: HashMap<u64, String>,
+    // Conversations started so far, across all channels
+    pub generations: u64,
+}
+
+pub struct Conversations {
+    store: JsonStore<ConversationData>,
+}
+
+impl Conversations {
+    pub fn new() -> Conversations {
+        Conversations {
+            store: JsonStore::open("conversations"),
+        }
+    }
+
+    pub async fn get(&self, channel: u64) -> Conversation {
+        self.store
+            .read(|data| data.channels.get(&channel).cloned())
+            .await
+            .unwrap_or_default()
+    }
+
+    // Add a finished exchange to the history
+    pub async fn push(&self, channel: u64, user: Message, reply: Message) {
+        self.store
+            .update(|data| {
+                if !data.channels.contains_key(&channel) {
+                    data.generations += 1;
+                }
+                let generation = data.generations;
+                let conversation = data
+                    .channels
+                    .entry(channel)
+                    .or_insert_with(|| Conversation {
+                        generation,
+                        ..Conversation::default()
+                    });
+                conversation.messages.push(user);
+                conversation.messages.push(reply);
+                conversation.updated = Utc::now().timestamp();
+            })
+            .await;
+    }
+
+    // Forget a channel's conversation, returns whether there was one
+    pub async fn reset(&self, channel: u64) -> bool {
+        self.store
+            .update(|data| data.channels.remove(&channel).is_some())
+            .await
+    }
+
+    // The guild's chosen city, else DEFAULT_CITY, else Charlotte
+    pub async fn default_city(&self, guild: Option<u64>) -> String {
+        let chosen = match guild {
+            Some(guild) => {
+                self.store
+                    .read(|data| data.default_cities.get(&guild).cloned())
+                    .await
+            }
+            None => None,
+        };
+        chosen
+            .or_else(|| std::env::var("DEFAULT_CITY").ok())
+            .unwrap_or_else(|| "Charlotte".to_string())
+    }
+
+    pub async fn set_default_city(&self, guild: u64, city: &str) {
+        self.store
+            .update(|data| {
+                data.default_cities.insert(guild, city.to_string());
+            })
+            .await;
+    }
+
+    // Fold the oldest messages into the summary once the history gets long.
+    // If the model can't summarize them they're dropped, so memory stays bounded.
+    pub async fn compact(&self, channel: u64, llm: &dyn Llm) {
+        let conversation = self.get(channel).await;
+        if !conversation.needs_summary() {
+            return;
+        }
+
+        // Keep the newest half of the window verbatim, or half of a few very long messages
+        let count = conversation.messages.len();
+        let fold = if count > KEEP_MESSAGES / 2 {
+            count - KEEP_MESSAGES / 2
+        } else {
+            count / 2
+        };
+        let transcript: Vec<String> = conversation.messages[..fold]
+            .iter()
+            .map(|m| match m.role.as_str() {
+                "assistant" => format!("Bot: {}", m.content),
+                _ => m.content.clone(),
+            })
+            .collect();
+        let prompt = format!(
+            "Summarize this Discord conversation in under 120 words. Keep names, cities, \
+             preferences and anything people asked you to remember.\n\n\
+             Earlier summary: {}\n\n{}",
+            if conversation.summary.is_empty() {
+                "(none)"
+            } else {
+                conversation.summary.as_str()
+            },
+            transcript.join("\n")
+        );
+        let summary = match llm.ask(&prompt, 200).await {
+            Ok(summary) => Some(summary),
+            Err(e) => {
+                println!("Error: {}", e);
+                None
+            }
+        };
+
+        self.store
+            .update(|data| {
+                // The channel may have been reset while we were summarizing,
+                // and maybe started over. Either way there's nothing to fold.
+                let conversation = match data.channels.get_mut(&channel) {
+                    Some(c) if c.generation == conversation.generation => c,
+                    _ => return,
+                };
+                conversation.messages.drain(..fold);
+                if let Some(summary) = summary {
+                    conversation.summary = summary;
+                }
+            })
+            .await;
+    }
+}
+
+impl Default for Conversations {
+    fn default() -> Self {
+        Conversations::new()
+    }
+}
+
+// The next request in a conversation: persona, weather and summary as system
+// context, then the remembered messages and the new one
+pub fn request(
+    persona: &str,
+    weather: Option<String>,
+    conversation: &Conversation,
+    user: &Message,
+) -> ChatRequest {
+    let mut system = format!("{}\n{}", persona, CHAT_PROMPT);
+    if let Some(weather) = weather {
+        system.push_str(&format!("\n\n{}", weather));
+    }
+    if !conversation.summary.is_empty() {
+        system.push_str(&format!(
+            "\n\nSummary of the conversation so far: {}",
+            conversation.summary
+        ));
+    }
+
+    let mut messages = vec![Message::system(system)];
+    messages.extend(conversation.messages.iter().cloned());
+    messages.push(user.clone());
+    ChatRequest::new(messages).max_tokens(300)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chatbot::testing::FakeLlm;
+    use crate::chatbot::{LlmError, OpenAIResponse};
+    use async_trait::async_trait;
+
+    fn conversations(name: &str) -> Conversations {
+        Conversations {
+            store: JsonStore::scratch(name),
+        }
+    }
+
+    // Enough back and forth to need a summary
+    async fn chat(conversations: &Conversations, channel: u64, exchanges: usize) {
+        for i in 0..exchanges {
+            let user = Message::user(format!("Sam: question {}", i));
+            let reply = Message::assistant(format!("answer {}", i));
+            conversations.push(channel, user, reply).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn long_histories_fold_into_the_summary() {
+        let conversations = conversations("compact");
+        chat(&conversations, 1, 11).await;
+        let llm = FakeLlm::replying("Sam asked eleven questions.");
+        conversations.compact(1, &llm).await;
+
+        let conversation = conversations.get(1).await;
+        assert_eq!(conversation.summary, "Sam asked eleven questions.");
+        assert_eq!(conversation.messages.len(), KEEP_MESSAGES / 2);
+        assert_eq!(conversation.messages[0].content, "Sam: question 6");
+
+        // The oldest messages went to the model, the kept ones didn't
+        let prompt = llm.requests.lock().unwrap()[0].messages[0].content.clone();
+        assert!(prompt.contains("Earlier summary: (none)"));
+        assert!(prompt.contains("Sam: question 0\nBot: answer 0"));
+        assert!(!prompt.contains("question 6"));
+    }
+
+    #[tokio::test]
+    async fn short_histories_are_left_alone() {
+        let conversations = conversations("short");
+        chat(&conversations, 1, 3).await;
+        let llm = FakeLlm::replying("unused");
+        conversations.compact(1, &llm).await;
+
+        assert_eq!(conversations.get(1).await.messages.len(), 6);
+        assert!(llm.requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_summaries_still_drop_old_messages() {
+        let conversations = conversations("failed");
+        chat(&conversations, 1, 11).await;
+        conversations.compact(1, &FakeLlm::failing()).await;
+
+        let conversation = conversations.get(1).await;
+        assert_eq!(conversation.summary, "");
+        assert_eq!(conversation.messages.len(), KEEP_MESSAGES / 2);
+    }
+
+    // Resets the channel while it's summarizing, and maybe starts it over
+    struct Resetting<'a> {
+        conversations: &'a Conversations,
+        restart: bool,
+    }
+
+    #[async_trait]
+    impl Llm for Resetting<'_> {
+        async fn complete(&self, _request: ChatRequest) -> Result<OpenAIResponse, LlmError> {
+            self.conversations.reset(1).await;
+            if self.restart {
+                chat(self.conversations, 1, 1).await;
+            }
+            Ok(crate::chatbot::testing::response("summary", None))
+        }
+    }
+
+    #[tokio::test]
+    async fn resets_during_a_summary_stick() {
+        let conversations = conversations("reset");
+        chat(&conversations, 1, 11).await;
+        let llm = Resetting {
+            conversations: &conversations,
+            restart: false,
+        };
+        conversations.compact(1, &llm).await;
+        assert!(
+            conversations
+                .store
+                .read(|data| data.channels.is_empty())
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn restarted_conversations_are_not_folded() {
+        let conversations = conversations("restart");
+        chat(&conversations, 1, 11).await;
+        let llm = Resetting {
+            conversations: &conversations,
+            restart: true,
+        };
+        conversations.compact(1, &llm).await;
+
+        let conversation = conversations.get(1).await;
+        assert_eq!(conversation.summary, "");
+        assert_eq!(conversation.messages.len(), 2);
+        assert_eq!(conversation.messages[0].content, "Sam: question 0");
+    }
+
+    #[test]
+    fn requests_put_context_first_and_the_new_message_last() {
+        let conversation = Conversation {
+            summary: "Sam likes rain.".to_string(),
+            messages: vec![Message::user("Sam: hi"), Message::assistant("Hello!")],
+            ..Conversation::default()
+        };
+        let user = Message::user("Sam: is it raining?");
+        let request = request(
+            "You are Stormy.",
+            Some("Paris: 12°C, rain".to_string()),
+            &conversation,
+            &user,
+        );
+
+        let roles: Vec<&str> = request.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["system", "user", "assistant", "user"]);
+        let system = &request.messages[0].content;
+        assert!(system.starts_with("You are Stormy.\n"));
+        assert!(system.contains("Paris: 12°C, rain"));
+        assert!(system.ends_with("Summary of the conversation so far: Sam likes rain."));
+        assert_eq!(request.messages[3].content, "Sam: is it raining?");
+        assert_eq!(request.max_tokens, Some(300));
+    }
+
+    #[test]
+    fn requests_skip_missing_context() {
+        let user = Message::user("Sam: hi");
+        let request = request("You are Stormy.", None, &Conversation::default(), &user);
+        assert_eq!(request.messages.len(), 2);
+        assert!(!request.messages[0].content.contains("Summary"));
+        assert!(request.messages[0].content.ends_with("under 150 words."));
+    }
+}
@@ -0,0 +1,284 @@
+use crate::storage::JsonStore;
+use crate::Context;
+use chrono::Utc;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+// How many facts/jokes we remember per city or guild
+const KEEP: usize = 50;
+// How many of the most recent ones we avoid repeating
+const RECENT: usize = 20;
+// Word overlap above which two texts count as the same fact
+const SIMILARITY_THRESHOLD: f64 = 0.5;
+
+// Hand-picked facts and jokes for when the LLM is down or only has repeats
+const FALLBACK_FACTS: &[&str] = &[
+    "The hottest air temperature ever recorded is 134°F (56.7°C), at Furnace Creek in Death Valley on July 10, 1913.",
+    "The coldest temperature ever measured on Earth's surface is -128.6°F (-89.2°C), at Vostok Station in Antarctica on July 21, 1983.",
+    "Cyclone Olivia produced a 253 mph gust at Barrow Island, Australia, in April 1996, the strongest wind gust recorded outside a tornado.",
+    "Before 1996 the wind gust record belonged to Mount Washington, New Hampshire, which clocked 231 mph on April 12, 1934.",
+    "The largest hailstone on record in the US fell in Vivian, South Dakota, on July 23, 2010: 8 inches across and nearly 2 pounds.",
+    "On January 22, 1943, the temperature in Spearfish, South Dakota, rose from -4°F to 45°F in just two minutes.",
+    "Unionville, Maryland, got 1.23 inches of rain in a single minute on July 4, 1956.",
+    "Cherrapunji, India, received about 1,042 inches of rain between August 1860 and July 1861.",
+    "Silver Lake, Colorado, was buried under 75.8 inches of snow in 24 hours on April 14-15, 1921.",
+    "The Tri-State Tornado of March 18, 1925, tracked about 219 miles across Missouri, Illinois and Indiana, the deadliest tornado in US history.",
+    "The 1815 eruption of Mount Tambora led to 1816's \"Year Without a Summer\", with snow falling in New England in June.",
+    "The Galveston hurricane of 1900 killed somewhere between 6,000 and 12,000 people, still the deadliest natural disaster in US history.",
+    "Park ranger Roy Sullivan was struck by lightning seven times between 1942 and 1977 and survived every one.",
+    "Lightning strikes somewhere on Earth roughly 100 times every second.",
+    "Some weather stations in Chile's Atacama Desert have never recorded a drop of rain.",
+];
+
+const FALLBACK_JOKES: &[&str] = &[
+    "What did one raindrop say to the other? Two's company, three's a cloud.",
+    "Why did the meteorologist bring a bar of soap to work? They were expecting showers.",
+    "What's the difference between weather and climate? You can't weather a tree, but you can climate.",
+    "How do hurricanes see? With one eye.",
+    "What does a cloud wear under its raincoat? Thunderwear.",
+    "Why don't meteorologists ever win at poker? Everyone can read their forecast.",
+    "What do you call a month's worth of rain in England? June.",
+    "Why did the cloud start dating the fog? It was so down to earth.",
+    "What did the tornado say to the sports car? Want to go for a spin?",
+    "What's a tornado's favorite game? Twister.",
+    "How does the moon cut its hair? Eclipse it.",
+    "I'd tell you a joke about fog, but you wouldn't see where it's going.",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Fact,
+    Joke,
+}
+
+// Something we've posted before
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub text: String,
+    // Unix time it was posted
+    pub at: i64,
+    // False when it came from the fallback corpus
+    pub generated: bool,
+}
+
+// Facts are kept per guild and city ("<guild>/<city>"), jokes per guild
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FactData {
+    pub facts: HashMap<String, Vec<Entry>>,
+    pub jokes: HashMap<String, Vec<Entry>>,
+}
+
+impl FactData {
+    fn entries(&self, kind: Kind) -> &HashMap<String, Vec<Entry>> {
+        match kind {
+            Kind::Fact => &self.facts,
+            Kind::Joke => &self.jokes,
+        }
+    }
+
+    fn entries_mut(&mut self, kind: Kind) -> &mut HashMap<String, Vec<Entry>> {
+        match kind {
+            Kind::Fact => &mut self.facts,
+            Kind::Joke => &mut self.jokes,
+        }
+    }
+}
+
+// Storage key for a guild (or DM user) and, for facts, a city
+fn key(kind: Kind, scope: &str, city: &str) -> String {
+    match kind {
+        Kind::Fact => format!("{}/{}", scope, city.trim().to_lowercase()),
+        Kind::Joke => scope.to_string(),
+    }
+}
+
+// Remembers what's been posted where so we can avoid repeating ourselves
+pub struct FactHistory {
+    store: JsonStore<FactData>,
+}
+
+impl FactHistory {
+    pub fn new() -> FactHistory {
+        FactHistory {
+            store: JsonStore::open("facts"),
+        }
+    }
+
+    // The most recent texts, newest first
+    pub async fn recent(&self, kind: Kind, scope: &str, city: &str) -> Vec<String> {
+        self.list(kind, scope, city, RECENT)
+            .await
+            .into_iter()
+            .map(|e| e.text)
+            .collect()
+    }
+
+    // Up to `limit` entries, newest first
+    pub async fn list(&self, kind: Kind, scope: &str, city: &str, limit: usize) -> Vec<Entry> {
+        let key = key(kind, scope, city);
+        self.store
+            .read(|data| {
+                data.entries(kind)
+                    .get(&key)
+                    .map(|entries| entries.iter().rev().take(limit).cloned().collect())
+                    .unwrap_or_default()
+            })
+            .await
+    }
+
+    pub async fn record(&self, kind: Kind, scope: &str, city: &str, text: &str, generated: bool) {
+        let key = key(kind, scope, city);
+        let entry = Entry {
+            text: text.to_string(),
+            at: Utc::now().timestamp(),
+            generated,
+        };
+        self.store
+            .update(|data| {
+                let entries = data.entries_mut(kind).entry(key).or_default();
+                entries.push(entry);
+                if entries.len() > KEEP {
+                    let extra = entries.len() - KEEP;
+                    entries.drain(..extra);
+                }
+            })
+            .await;
+    }
+}
+
+impl Default for FactHistory {
+    fn default() -> Self {
+        FactHistory::new()
+    }
+}
+
+// Lowercase words with punctuation and the filler words stripped
+fn words(text: &str) -> HashSet<String> {
+    const FILLER: &[&str] = &[
+        "a", "an", "and", "the", "of", "in", "on", "at", "to", "was", "is", "it", "for", "with",
+        "that", "this", "by", "as",
+    ];
+    text.split(|c: char| !c.is_alphanumeric())
+        .map(|w| w.to_lowercase())
+        .filter(|w| !w.is_empty() && !FILLER.contains(&w.as_str()))
+        .collect()
+}
+
+// Jaccard similarity of the word sets, 0 (nothing shared) to 1 (same words)
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (words(a), words(b));
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(&b).count() as f64 / union as f64
+}
+
+// Whether `text` is close enough to something recent to count as a repeat
+pub fn is_repeat(text: &str, recent: &[String]) -> bool {
+    recent
+        .iter()
+        .any(|old| similarity(text, old) >= SIMILARITY_THRESHOLD)
+}
+
+// A corpus entry that hasn't been posted recently (any of them once all have been)
+pub fn fallback(kind: Kind, recent: &[String]) -> String {
+    let corpus = match kind {
+        Kind::Fact => FALLBACK_FACTS,
+        Kind::Joke => FALLBACK_JOKES,
+    };
+    let fresh: Vec<&&str> = corpus
+        .iter()
+        .filter(|text| !is_repeat(text, recent))
+        .collect();
+    let mut rng = rand::thread_rng();
+    let pick = fresh
+        .choose(&mut rng)
+        .copied()
+        .or_else(|| corpus.choose(&mut rng))
+        .copied()
+        .unwrap_or_default();
+    pick.to_string()
+}
+
+// Prompt suffix listing what not to say again
+pub fn avoid_prompt(recent: &[String]) -> String {
+    if recent.is_empty() {
+        return String::new();
+    }
+    let list: Vec<String> = recent.iter().take(10).map(|t| format!("- {}", t)).collect();
+    format!(
+        "\n\nDon't repeat or reword any of these, they've already been posted:\n{}",
+        list.join("\n")
+    )
+}
+
+// Who the history belongs to: the guild, or the user in DMs
+pub fn scope(ctx: Context<'_>) -> String {
+    match ctx.guild_id() {
+        Some(guild) => guild.get().to_string(),
+        None => format!("dm-{}", ctx.author().id.get()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(texts: &[&str]) -> Vec<String> {
+        texts.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn similarity_ignores_case_punctuation_and_filler() {
+        assert_eq!(similarity("The hail was HUGE!", "hail huge"), 1.0);
+        assert_eq!(similarity("Hail in Texas", "Snow over Maine"), 0.0);
+        // Two of four words shared
+        assert_eq!(similarity("big hail fell", "big hail melted"), 0.5);
+        assert_eq!(similarity("", "the a of"), 0.0);
+    }
+
+    #[test]
+    fn repeats_are_close_to_something_recent() {
+        let recent = texts(&[
+            "Lightning strikes Earth about 100 times a second.",
+            "Fog is a cloud on the ground.",
+        ]);
+        assert!(is_repeat(
+            "Lightning strikes the Earth roughly 100 times each second.",
+            &recent
+        ));
+        assert!(!is_repeat("Hailstones can fall at 100 mph.", &recent));
+        assert!(!is_repeat("Fog is a cloud on the ground.", &[]));
+    }
+
+    #[test]
+    fn fallback_skips_what_was_posted() {
+        // Everything but the last joke has been posted
+        let (last, posted) = FALLBACK_JOKES.split_last().unwrap();
+        let recent: Vec<String> = posted.iter().map(|t| t.to_string()).collect();
+        for _ in 0..20 {
+            assert_eq!(fallback(Kind::Joke, &recent), *last);
+        }
+    }
+
+    #[test]
+    fn fallback_repeats_once_everything_was_posted() {
+        let recent: Vec<String> = FALLBACK_FACTS.iter().map(|t| t.to_string()).collect();
+        assert!(FALLBACK_FACTS.contains(&fallback(Kind::Fact, &recent).as_str()));
+    }
+
+    #[test]
+    fn avoid_prompt_lists_the_newest_ten() {
+        assert_eq!(avoid_prompt(&[]), "");
+
+        let recent: Vec<String> = (0..12).map(|i| format!("fact {}", i)).collect();
+        let prompt = avoid_prompt(&recent);
+        assert!(prompt.starts_with("\n\nDon't repeat or reword"));
+        assert!(prompt.contains("\n- fact 0\n"));
+        assert!(prompt.ends_with("- fact 9"));
+        assert!(!prompt.contains("fact 10"));
+    }
+}
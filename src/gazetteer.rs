@@ -0,0 +1,206 @@
+use crate::weather::{get_weather_cached, Coord};
+use chrono::{Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Deserializer};
+use std::sync::OnceLock;
+
+// Cities we know the position and time zone of without asking the weather API
+const GAZETTEER: &str = include_str!("../resources/gazetteer.json");
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct City {
+    pub name: String,
+    // ISO 3166 alpha-2 code, same as the weather API uses
+    pub country: String,
+    pub lat: f64,
+    pub lon: f64,
+    // IANA name in the file, e.g. "America/New_York"
+    #[serde(deserialize_with = "time_zone")]
+    pub timezone: Tz,
+}
+
+// An unknown zone fails the whole file rather than quietly becoming UTC
+fn time_zone<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Tz, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    name.parse()
+        .map_err(|_| serde::de::Error::custom(format!("unknown time zone '{}'", name)))
+}
+
+static CITIES: OnceLock<Vec<City>> = OnceLock::new();
+
+fn parse(text: &str) -> Result<Vec<City>, String> {
+    let cities: Vec<City> = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    for city in &cities {
+        if city.name.trim().is_empty() || city.country.trim().is_empty() {
+            return Err("every city needs a name and a country".to_string());
+        }
+        if !(-90.0..=90.0).contains(&city.lat) || !(-180.0..=180.0).contains(&city.lon) {
+            return Err(format!("{} has coordinates off the map", city.name));
+        }
+    }
+    Ok(cities)
+}
+
+// Check the built-in gazetteer. Call once at startup so a bad entry stops the
+// bot instead of the first /distance or /trip.
+pub fn load() -> Result<(), String> {
+    let cities = parse(GAZETTEER).map_err(|e| format!("built-in gazetteer.json: {}", e))?;
+    let _ = CITIES.set(cities);
+    Ok(())
+}
+
+pub fn cities() -> &'static [City] {
+    CITIES.get_or_init(|| parse(GAZETTEER).expect("resources/gazetteer.json should be valid"))
+}
+
+// Lowercase with accents dropped, so "reykjavik" finds "Reykjavík"
+fn fold(text: &str) -> String {
+    text.trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'á' | 'à' | 'â' | 'ä' | 'ã' | 'å' => 'a',
+            'é' | 'è' | 'ê' | 'ë' => 'e',
+            'í' | 'ì' | 'î' | 'ï' => 'i',
+            'ó' | 'ò' | 'ô' | 'ö' | 'õ' | 'ø' => 'o',
+            'ú' | 'ù' | 'û' | 'ü' => 'u',
+            'ñ' => 'n',
+            'ç' => 'c',
+            other => other,
+        })
+        .collect()
+}
+
+// Look up "Paris" or "Paris, FR"
+pub fn find(query: &str) -> Option<&'static City> {
+    let (name, country) = match query.split_once(',') {
+        Some((name, country)) => (fold(name), Some(fold(country))),
+        None => (fold(query), None),
+    };
+    cities().iter().find(|city| {
+        let country_matches = match &country {
+            Some(country) => fold(&city.country) == *country,
+            None => true,
+        };
+        fold(&city.name) == name && country_matches
+    })
+}
+
+// Split a list of places typed by a user. Commas, "to" or arrows separate
+// places if present, otherwise words are grouped into the longest names the
+// gazetteer knows ("New York Boston" is two places, not three).
+pub fn split_places(text: &str) -> Vec<String> {
+    let separated = text
+        .replace("->", ",")
+        .replace('→', ",")
+        .replace(" to ", ",");
+    if separated.contains(',') {
+        return separated
+            .split(',')
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty())
+            .collect();
+    }
+
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let mut places = Vec::new();
+    let mut i = 0;
+    while i < words.len() {
+        // Longest known name starting here, or just the one word
+        let end = (i + 2..=(i + 3).min(words.len()))
+            .rev()
+            .find(|&end| find(&words[i..end].join(" ")).is_some())
+            .unwrap_or(i + 1);
+        places.push(words[i..end].join(" "));
+        i = end;
+    }
+    places
+}
+
+// A resolved location, from the gazetteer or failing that the weather API
+#[derive(Debug, Clone)]
+pub struct Place {
+    pub name: String,
+    pub country: String,
+    pub coord: Coord,
+    timezone: Option<Tz>,
+    // Offset the weather API reported, used when we don't know the zone
+    fixed_offset: i32,
+}
+
+impl Place {
+    // Seconds east of UTC at a given time, following daylight saving when we can
+    pub fn utc_offset_at(&self, timestamp: i64) -> i32 {
+        match (self.timezone, Utc.timestamp_opt(timestamp, 0).single()) {
+            (Some(tz), Some(time)) => tz
+                .offset_from_utc_datetime(&time.naive_utc())
+                .fix()
+                .local_minus_utc(),
+            _ => self.fixed_offset,
+        }
+    }
+
+    pub fn utc_offset(&self) -> i32 {
+        self.utc_offset_at(Utc::now().timestamp())
+    }
+
+    pub fn label(&self) -> String {
+        format!("{}, {}", self.name, self.country)
+    }
+}
+
+impl From<&City> for Place {
+    fn from(city: &City) -> Place {
+        Place {
+            name: city.name.clone(),
+            country: city.country.clone(),
+            coord: Coord {
+                lat: city.lat,
+                lon: city.lon,
+            },
+            timezone: Some(city.timezone),
+            fixed_offset: 0,
+        }
+    }
+}
+
+// Gazetteer first; only places it doesn't know cost a (cached) weather lookup
+pub async fn resolve(query: &str) -> Result<Place, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(city) = find(query) {
+        return Ok(Place::from(city));
+    }
+    let weather = get_weather_cached(query).await?;
+    Ok(Place {
+        name: weather.name,
+        country: weather.sys.country,
+        coord: weather.coord,
+        timezone: None,
+        fixed_offset: weather.timezone,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_in_gazetteer_loads() {
+        assert!(load().is_ok());
+        let paris = find("paris, fr").unwrap();
+        assert_eq!(paris.timezone, chrono_tz::Europe::Paris);
+    }
+
+    #[test]
+    fn bad_entries_are_rejected() {
+        let city = |timezone: &str, lat: f64| {
+            format!(
+                r#"[{{"name": "Atlantis", "country": "GR", "lat": {}, "lon": 25.0, "timezone": "{}"}}]"#,
+                lat, timezone
+            )
+        };
+        assert!(parse(&city("Europe/Athens", 36.4)).is_ok());
+        let error = parse(&city("Europe/Atlantis", 36.4)).unwrap_err();
+        assert!(error.contains("unknown time zone 'Europe/Atlantis'"));
+        assert!(parse(&city("Europe/Athens", 136.4)).is_err());
+    }
+}
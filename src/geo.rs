@@ -0,0 +1,210 @@
+use crate::weather::Coord;
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+// Great-circle distance in kilometers
+pub fn haversine_km(from: Coord, to: Coord) -> f64 {
+    let (lat1, lat2) = (from.lat.to_radians(), to.lat.to_radians());
+    let d_lat = (to.lat - from.lat).to_radians();
+    let d_lon = (to.lon - from.lon).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    let c = 2.0 * a.sqrt().atan2((1.0 - a).sqrt());
+    EARTH_RADIUS_KM * c
+}
+
+// Direction to set off in, degrees clockwise from north
+pub fn initial_bearing(from: Coord, to: Coord) -> f64 {
+    let (lat1, lat2) = (from.lat.to_radians(), to.lat.to_radians());
+    let d_lon = (to.lon - from.lon).to_radians();
+    let y = d_lon.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * d_lon.cos();
+    y.atan2(x).to_degrees().rem_euclid(360.0)
+}
+
+// Direction you're heading when you arrive (great circles curve, so it differs)
+pub fn final_bearing(from: Coord, to: Coord) -> f64 {
+    (initial_bearing(to, from) + 180.0).rem_euclid(360.0)
+}
+
+// Halfway point along the great circle
+pub fn midpoint(from: Coord, to: Coord) -> Coord {
+    let (lat1, lat2) = (from.lat.to_radians(), to.lat.to_radians());
+    let lon1 = from.lon.to_radians();
+    let d_lon = (to.lon - from.lon).to_radians();
+    let bx = lat2.cos() * d_lon.cos();
+    let by = lat2.cos() * d_lon.sin();
+    let lat = (lat1.sin() + lat2.sin()).atan2(((lat1.cos() + bx).powi(2) + by.powi(2)).sqrt());
+    let lon = lon1 + by.atan2(lat1.cos() + bx);
+    Coord {
+        lat: lat.to_degrees(),
+        // Normalise to -180..180
+        lon: (lon.to_degrees() + 540.0).rem_euclid(360.0) - 180.0,
+    }
+}
+
+// Point a fraction of the way along the great circle (0 = from, 1 = to)
+pub fn interpolate(from: Coord, to: Coord, fraction: f64) -> Coord {
+    let delta = haversine_km(from, to) / EARTH_RADIUS_KM;
+    if delta < 1e-9 {
+        return from;
+    }
+    let (lat1, lon1) = (from.lat.to_radians(), from.lon.to_radians());
+    let (lat2, lon2) = (to.lat.to_radians(), to.lon.to_radians());
+    let a = ((1.0 - fraction) * delta).sin() / delta.sin();
+    let b = (fraction * delta).sin() / delta.sin();
+    let x = a * lat1.cos() * lon1.cos() + b * lat2.cos() * lon2.cos();
+    let y = a * lat1.cos() * lon1.sin() + b * lat2.cos() * lon2.sin();
+    let z = a * lat1.sin() + b * lat2.sin();
+    Coord {
+        lat: z.atan2((x * x + y * y).sqrt()).to_degrees(),
+        lon: y.atan2(x).to_degrees(),
+    }
+}
+
+// Roads wander, so driving distance is roughly 1.25x the great circle at ~90 km/h
+pub fn driving_hours(km: f64) -> f64 {
+    km * 1.25 / 90.0
+}
+
+// Cruise at ~800 km/h plus about an hour for taxi, climb and descent
+pub fn flying_hours(km: f64) -> f64 {
+    km / 800.0 + 1.0
+}
+
+// Very rough door-to-door times. Nobody drives across an ocean, so car and
+// train are skipped for long trips.
+pub fn travel_estimates(km: f64) -> Vec<(&'static str, f64)> {
+    let mut estimates = Vec::new();
+    if km <= 3000.0 {
+        estimates.push(("🚗 Car", driving_hours(km)));
+        estimates.push(("🚆 Train", km * 1.2 / 120.0));
+    }
+    if km >= 150.0 {
+        estimates.push(("✈️ Plane", flying_hours(km)));
+    }
+    estimates
+}
+
+// "3h 25m"
+pub fn format_hours(hours: f64) -> String {
+    let minutes = (hours * 60.0).round() as i64;
+    match (minutes / 60, minutes % 60) {
+        (0, m) => format!("{}m", m),
+        (h, 0) => format!("{}h", h),
+        (h, m) => format!("{}h {}m", h, m),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meteo::compass;
+
+    fn at(lat: f64, lon: f64) -> Coord {
+        Coord { lat, lon }
+    }
+
+    fn close(a: f64, b: f64, within: f64) -> bool {
+        (a - b).abs() <= within
+    }
+
+    #[test]
+    fn bearings_point_along_the_axes() {
+        let origin = at(0.0, 0.0);
+        assert!(close(initial_bearing(origin, at(10.0, 0.0)), 0.0, 1e-9));
+        assert!(close(initial_bearing(origin, at(0.0, 10.0)), 90.0, 1e-9));
+        assert!(close(initial_bearing(origin, at(-10.0, 0.0)), 180.0, 1e-9));
+        assert!(close(initial_bearing(origin, at(0.0, -10.0)), 270.0, 1e-9));
+        assert_eq!(compass(initial_bearing(origin, at(0.0, 10.0))), "E");
+        assert_eq!(compass(initial_bearing(origin, at(10.0, -10.0))), "NW");
+    }
+
+    #[test]
+    fn great_circles_curve() {
+        // New York to London sets off northeast and arrives heading east-southeast
+        let (new_york, london) = (at(40.71, -74.01), at(51.51, -0.13));
+        assert!(close(initial_bearing(new_york, london), 51.2, 0.5));
+        assert!(close(final_bearing(new_york, london), 108.3, 0.5));
+        assert!(close(haversine_km(new_york, london), 5570.0, 10.0));
+    }
+
+    #[test]
+    fn bearings_cross_the_antimeridian() {
+        // Fiji to Samoa is a short hop east over the 180° line, not west around the world
+        let (fiji, samoa) = (at(-18.0, 178.0), at(-14.0, -172.0));
+        assert!(close(initial_bearing(fiji, samoa), 68.8, 0.1));
+        assert_eq!(compass(initial_bearing(fiji, samoa)), "ENE");
+        assert!(haversine_km(fiji, samoa) < 1200.0);
+    }
+
+    #[test]
+    fn midpoints_stay_on_the_map() {
+        let middle = midpoint(at(0.0, 0.0), at(0.0, 90.0));
+        assert!(close(middle.lat, 0.0, 1e-9) && close(middle.lon, 45.0, 1e-9));
+
+        // Across the antimeridian the midpoint is near 180°, not 0°
+        let middle = midpoint(at(0.0, 170.0), at(0.0, -170.0));
+        assert!(close(middle.lon.abs(), 180.0, 1e-9));
+
+        let same = midpoint(at(35.0, -80.0), at(35.0, -80.0));
+        assert!(close(same.lat, 35.0, 1e-9) && close(same.lon, -80.0, 1e-9));
+    }
+
+    #[test]
+    fn interpolation_runs_from_start_to_end() {
+        let (from, to) = (at(35.23, -80.84), at(51.51, -0.13));
+        let start = interpolate(from, to, 0.0);
+        let end = interpolate(from, to, 1.0);
+        assert!(close(start.lat, from.lat, 1e-6) && close(start.lon, from.lon, 1e-6));
+        assert!(close(end.lat, to.lat, 1e-6) && close(end.lon, to.lon, 1e-6));
+
+        // Halfway is the midpoint, and splits the distance evenly
+        let half = interpolate(from, to, 0.5);
+        let middle = midpoint(from, to);
+        assert!(close(half.lat, middle.lat, 1e-6) && close(half.lon, middle.lon, 1e-6));
+        assert!(close(
+            haversine_km(from, half),
+            haversine_km(half, to),
+            1e-6
+        ));
+
+        // Same place: nothing to divide by
+        let here = at(10.0, 20.0);
+        assert_eq!(interpolate(here, here, 0.5), here);
+        assert_eq!(haversine_km(here, here), 0.0);
+    }
+
+    #[test]
+    fn interpolation_crosses_the_antimeridian() {
+        let half = interpolate(at(0.0, 170.0), at(0.0, -170.0), 0.5);
+        assert!(close(half.lon.abs(), 180.0, 1e-6));
+        let quarter = interpolate(at(0.0, 170.0), at(0.0, -170.0), 0.25);
+        assert!(close(quarter.lon, 175.0, 1e-6));
+    }
+
+    #[test]
+    fn travel_modes_depend_on_distance() {
+        let modes =
+            |km| -> Vec<&str> { travel_estimates(km).into_iter().map(|(m, _)| m).collect() };
+        assert_eq!(modes(100.0), vec!["🚗 Car", "🚆 Train"]);
+        assert_eq!(modes(150.0), vec!["🚗 Car", "🚆 Train", "✈️ Plane"]);
+        assert_eq!(modes(3000.0), vec!["🚗 Car", "🚆 Train", "✈️ Plane"]);
+        assert_eq!(modes(3001.0), vec!["✈️ Plane"]);
+        assert!(modes(0.0).len() == 2);
+
+        let estimates = travel_estimates(900.0);
+        assert!(close(estimates[0].1, 12.5, 1e-9));
+        assert!(close(estimates[1].1, 9.0, 1e-9));
+        assert!(close(estimates[2].1, 2.125, 1e-9));
+    }
+
+    #[test]
+    fn hours_read_naturally() {
+        assert_eq!(format_hours(0.0), "0m");
+        assert_eq!(format_hours(0.5), "30m");
+        assert_eq!(format_hours(2.0), "2h");
+        assert_eq!(format_hours(3.42), "3h 25m");
+        // Rounds to the minute, carrying into the hour
+        assert_eq!(format_hours(1.999), "2h");
+    }
+}
@@ -0,0 +1,370 @@
+use crate::catalog::{self, Observation};
+use crate::leaderboard::{Award, Leaderboard};
+use crate::render;
+use crate::units::{Temperature, UnitFormat};
+use crate::{Context, Error};
+use futures::StreamExt;
+use poise::serenity_prelude as serenity;
+use poise::{CreateReply, Modal};
+use serenity::{
+    ButtonStyle, ComponentInteractionCollector, CreateActionRow, CreateButton, CreateEmbed,
+    CreateInteractionResponse, CreateInteractionResponseMessage, ModalInteractionCollector,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Round length when none is given, and the allowed range
+pub const DEFAULT_SECONDS: u64 = 60;
+pub const MIN_SECONDS: u64 = 15;
+pub const MAX_SECONDS: u64 = 300;
+
+// Points for an exact guess, minus this many per °C off
+const MAX_POINTS: f64 = 100.0;
+const POINTS_PER_DEGREE: f64 = 10.0;
+
+#[derive(Debug, poise::Modal)]
+#[name = "Guess the temperature"]
+struct GuessModal {
+    #[name = "Your guess"]
+    #[placeholder = "e.g. 72, or 22C for Celsius"]
+    #[max_length = 10]
+    temperature: String,
+}
+
+// Leaderboards for /guess plus the channels with a round in progress
+pub struct Games {
+    pub leaderboard: Leaderboard,
+    active: Mutex<HashSet<u64>>,
+}
+
+impl Games {
+    pub fn new() -> Games {
+        Games {
+            leaderboard: Leaderboard::open("guess"),
+            active: Mutex::new(HashSet::new()),
+        }
+    }
+
+    // False if the channel already has a round going
+    fn begin(&self, channel: u64) -> bool {
+        self.active.lock().unwrap().insert(channel)
+    }
+
+    fn end(&self, channel: u64) {
+        self.active.lock().unwrap().remove(&channel);
+    }
+}
+
+impl Default for Games {
+    fn default() -> Self {
+        Games::new()
+    }
+}
+
+struct Guess {
+    name: String,
+    temperature: Temperature,
+}
+
+// Closer guesses score more, nothing for 10 °C or further off
+fn points(error_celsius: f64) -> u64 {
+    (MAX_POINTS - error_celsius * POINTS_PER_DEGREE)
+        .round()
+        .max(0.0) as u64
+}
+
+fn prompt(observation: &Observation, fmt: &UnitFormat, closes: i64, guesses: usize) -> String {
+    let city = observation.city;
+    format!(
+        "🌡️ **Guess the temperature!** How warm is it in {} {}, {} right now?\n\
+         Answer in {} (or add C or F). Closes <t:{}:R>. {} so far.",
+        city.flag,
+        city.name,
+        city.country,
+        Temperature::symbol(fmt.system),
+        closes,
+        match guesses {
+            1 => "1 guess".to_string(),
+            n => format!("{} guesses", n),
+        }
+    )
+}
+
+fn components(prefix: &str, disabled: bool) -> Vec<CreateActionRow> {
+    let button = CreateButton::new(format!("{}-guess", prefix))
+        .label("Submit guess")
+        .style(ButtonStyle::Primary)
+        .disabled(disabled);
+    vec![CreateActionRow::Buttons(vec![button])]
+}
+
+// Ephemeral reply to a button click or modal submit
+fn whisper(text: impl Into<String>) -> CreateInteractionResponse {
+    CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(text)
+            .ephemeral(true),
+    )
+}
+
+// A guess once the answer is known
+struct Scored<'a> {
+    user: u64,
+    guess: &'a Guess,
+    points: u64,
+    // Closest guess of the round (ties all win)
+    won: bool,
+}
+
+// Guesses ranked by how far off they were
+fn results(actual: Temperature, guesses: &HashMap<u64, Guess>) -> Vec<Scored<'_>> {
+    let mut ranked: Vec<(u64, &Guess, f64)> = guesses
+        .iter()
+        .map(|(id, g)| (*id, g, (g.temperature.celsius() - actual.celsius()).abs()))
+        .collect();
+    ranked.sort_by(|a, b| a.2.total_cmp(&b.2));
+    let best = ranked.first().map(|r| r.2).unwrap_or(0.0);
+    ranked
+        .into_iter()
+        .map(|(user, guess, error)| Scored {
+            user,
+            guess,
+            points: points(error),
+            won: error <= best,
+        })
+        .collect()
+}
+
+fn reveal_lines(
+    actual: Temperature,
+    guesses: &HashMap<u64, Guess>,
+    fmt: &UnitFormat,
+) -> Vec<String> {
+    results(actual, guesses)
+        .into_iter()
+        .map(|scored| {
+            let guess = scored.guess;
+            let off = (guess.temperature.value(fmt.system) - actual.value(fmt.system)).abs();
+            format!(
+                "{} {} · {} (off by {}{}) · +{}",
+                if scored.won { "🏆" } else { "▫️" },
+                guess.name,
+                guess.temperature.format(fmt),
+                fmt.number(off, 1),
+                Temperature::symbol(fmt.system),
+                scored.points
+            )
+        })
+        .collect()
+}
+
+// Play one round in the channel: post a random city, take guesses through a
+// modal until time's up, then reveal the answer and update the leaderboard
+pub async fn play(ctx: Context<'_>, seconds: u64) -> Result<(), Error> {
+    let channel = ctx.channel_id().get();
+    let games = &ctx.data().guess;
+    if !games.begin(channel) {
+        ctx.send(
+            CreateReply::default()
+                .content("There's already a round going in this channel.")
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+    let result = round(ctx, seconds).await;
+    games.end(channel);
+    result
+}
+
+async fn round(ctx: Context<'_>, seconds: u64) -> Result<(), Error> {
+    // guild_only guarantees a guild id
+    let guild = ctx.guild_id().unwrap().get();
+    ctx.defer().await?;
+    let cities = catalog::filter(&catalog::Filter::default());
+    let observation = match catalog::pick(cities, None).await {
+        Some(observation) => observation,
+        None => {
+            ctx.say("Could not retrieve weather for a random city right now, try again in a bit.")
+                .await?;
+            return Ok(());
+        }
+    };
+    let actual = observation.weather.main.temp;
+    let fmt = render::unit_format(ctx).await;
+
+    let seconds = seconds.clamp(MIN_SECONDS, MAX_SECONDS);
+    let closes = chrono::Utc::now().timestamp() + seconds as i64;
+    let prefix = ctx.id().to_string();
+    let handle = ctx
+        .send(
+            CreateReply::default()
+                .content(prompt(&observation, &fmt, closes, 0))
+                .components(components(&prefix, false)),
+        )
+        .await?;
+
+    let button_id = format!("{}-guess", prefix);
+    let modal_id = format!("{}-modal", prefix);
+    let mut clicks = ComponentInteractionCollector::new(ctx)
+        .filter(move |i| i.data.custom_id == button_id)
+        .stream();
+    let mut submissions = ModalInteractionCollector::new(ctx)
+        .filter({
+            let modal_id = modal_id.clone();
+            move |i| i.data.custom_id == modal_id
+        })
+        .stream();
+
+    let mut guesses: HashMap<u64, Guess> = HashMap::new();
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(seconds);
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline) => break,
+            Some(click) = clicks.next() => {
+                let response = match guesses.get(&click.user.id.get()) {
+                    Some(guess) => whisper(format!(
+                        "You already guessed {}.",
+                        guess.temperature.format(&fmt)
+                    )),
+                    None => GuessModal::create(None, modal_id.clone()),
+                };
+                if let Err(e) = click.create_response(ctx, response).await {
+                    println!("Error: {:?}", e);
+                }
+            }
+            Some(submission) = submissions.next() => {
+                let user = submission.user.id.get();
+                let parsed = GuessModal::parse(submission.data.clone())
+                    .ok()
+                    .and_then(|m| Temperature::parse(&m.temperature, fmt.system))
+                    // Nothing on Earth is 100 °C in the shade
+                    .filter(|t| t.celsius().abs() <= 100.0);
+                let response = match parsed {
+                    _ if guesses.contains_key(&user) => whisper("You've already guessed."),
+                    Some(temperature) => {
+                        guesses.insert(
+                            user,
+                            Guess {
+                                name: submission.user.display_name().to_string(),
+                                temperature,
+                            },
+                        );
+                        whisper(format!("Locked in {}. Good luck!", temperature.format(&fmt)))
+                    }
+                    None => whisper("That doesn't look like a temperature, try 72 or 22C."),
+                };
+                if let Err(e) = submission.create_response(ctx, response).await {
+                    println!("Error: {:?}", e);
+                }
+                let update = CreateReply::default()
+                    .content(prompt(&observation, &fmt, closes, guesses.len()))
+                    .components(components(&prefix, false));
+                if let Err(e) = handle.edit(ctx, update).await {
+                    println!("Error: {:?}", e);
+                }
+            }
+        }
+    }
+
+    // Time's up: grey out the button, then reveal
+    handle
+        .edit(
+            ctx,
+            CreateReply::default()
+                .content(prompt(&observation, &fmt, closes, guesses.len()))
+                .components(components(&prefix, true)),
+        )
+        .await?;
+
+    let awards: Vec<Award> = results(actual, &guesses)
+        .into_iter()
+        .map(|scored| Award {
+            user: scored.user,
+            name: scored.guess.name.clone(),
+            points: scored.points,
+            won: scored.won,
+        })
+        .collect();
+    ctx.data().guess.leaderboard.award(guild, &awards).await;
+
+    let city = observation.city;
+    let title = format!(
+        "It's {} in {} {}, {}",
+        actual.format(&fmt),
+        city.flag,
+        city.name,
+        city.country
+    );
+    let lines = reveal_lines(actual, &guesses, &fmt);
+    let body = if lines.is_empty() {
+        "Nobody guessed this time.".to_string()
+    } else {
+        lines.join("\n")
+    };
+    let embed = CreateEmbed::new()
+        .title(&title)
+        .description(&body)
+        .color(render::temperature_color(actual));
+    render::send_embed(ctx, embed, format!("**{}**\n{}", title, body)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guesses(celsius: &[(u64, f64)]) -> HashMap<u64, Guess> {
+        celsius
+            .iter()
+            .map(|(user, c)| {
+                let guess = Guess {
+                    name: format!("player {}", user),
+                    temperature: Temperature::from_celsius(*c),
+                };
+                (*user, guess)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn points_fall_to_zero_at_ten_degrees_off() {
+        assert_eq!(points(0.0), 100);
+        assert_eq!(points(2.5), 75);
+        assert_eq!(points(0.04), 100);
+        assert_eq!(points(9.96), 0);
+        assert_eq!(points(10.0), 0);
+        assert_eq!(points(25.0), 0);
+    }
+
+    #[test]
+    fn closest_guess_wins() {
+        let actual = Temperature::from_celsius(20.0);
+        let guesses = guesses(&[(1, 25.0), (2, 19.0), (3, 35.0)]);
+        let results = results(actual, &guesses);
+
+        let ranked: Vec<(u64, u64, bool)> =
+            results.iter().map(|s| (s.user, s.points, s.won)).collect();
+        assert_eq!(ranked, vec![(2, 90, true), (1, 50, false), (3, 0, false)]);
+    }
+
+    #[test]
+    fn ties_all_win() {
+        // One over and one under by the same amount
+        let actual = Temperature::from_celsius(20.0);
+        let guesses = guesses(&[(1, 18.0), (2, 22.0), (3, 23.0)]);
+        let winners: HashSet<u64> = results(actual, &guesses)
+            .iter()
+            .filter(|s| s.won)
+            .map(|s| s.user)
+            .collect();
+        assert_eq!(winners, HashSet::from([1, 2]));
+    }
+
+    #[test]
+    fn no_guesses_no_results() {
+        let actual = Temperature::from_celsius(20.0);
+        assert!(results(actual, &HashMap::new()).is_empty());
+        assert!(reveal_lines(actual, &HashMap::new(), &UnitFormat::default()).is_empty());
+    }
+}
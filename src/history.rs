@@ -0,0 +1,520 @@
+use crate::storage::JsonStore;
+use crate::weather::{get_weather_at_cached, get_weather_cached, Coord, WeatherResponse};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+// Most locations one server can have recorded
+pub const MAX_WATCHED: usize = 10;
+
+// Minutes between recordings, HISTORY_INTERVAL_MINS or 30
+fn interval() -> std::time::Duration {
+    let mins = std::env::var("HISTORY_INTERVAL_MINS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&n: &u64| n > 0)
+        .unwrap_or(30);
+    std::time::Duration::from_secs(mins * 60)
+}
+
+// Days of observations kept, HISTORY_RETENTION_DAYS or 90
+fn retention_secs() -> i64 {
+    let days = std::env::var("HISTORY_RETENTION_DAYS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&n: &i64| n > 0)
+        .unwrap_or(90);
+    days * 86400
+}
+
+// Lookup key for a location as the weather API resolved it, so "Paris" and
+// "paris, FR" are the same place. Goes by the coordinate rather than the name,
+// since names repeat (Springfield IL and Springfield MO are both "Springfield, US").
+pub fn key(weather: &WeatherResponse) -> String {
+    format!("@{:.2},{:.2}", weather.coord.lat, weather.coord.lon)
+}
+
+// One recorded observation, in the API's units (Kelvin, %, m/s, mm)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Sample {
+    pub at: i64,
+    pub temp: f64,
+    pub humidity: f64,
+    pub wind: f64,
+    // Not in samples recorded before /chart existed
+    #[serde(default)]
+    pub feels_like: Option<f64>,
+    // Rain plus snow reported with the observation
+    #[serde(default)]
+    pub precipitation: Option<f64>,
+    // Cloud cover, percent
+    #[serde(default)]
+    pub clouds: Option<f64>,
+}
+
+impl Sample {
+    fn from_weather(weather: &WeatherResponse, at: i64) -> Sample {
+        Sample {
+            at,
+            temp: weather.main.temp.kelvin(),
+            humidity: weather.main.humidity as f64,
+            wind: weather.wind.speed.meters_per_sec(),
+            feels_like: Some(weather.main.feels_like.kelvin()),
+            precipitation: Some(
+                weather.rain_recent().map(|p| p.mm()).unwrap_or(0.0)
+                    + weather.snow_recent().map(|p| p.mm()).unwrap_or(0.0),
+            ),
+            clouds: Some(weather.clouds.all as f64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Series {
+    // Name the weather API gave the location
+    pub name: String,
+    // Where it's recorded from. Not in series keyed by name before coordinates.
+    pub coord: Option<Coord>,
+    // Offset from UTC in seconds, as of the last recording
+    pub timezone: i32,
+    // Oldest first
+    pub samples: Vec<Sample>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryData {
+    // Location keys each guild wants recorded
+    pub watched: HashMap<u64, BTreeSet<String>>,
+    // Recorded observations by location key, shared between guilds
+    pub series: HashMap<String, Series>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum Period {
+    Today,
+    #[name = "Last 24 hours"]
+    Day,
+    #[name = "Last 7 days"]
+    Week,
+    #[name = "Last 30 days"]
+    Month,
+}
+
+impl Period {
+    fn length(self) -> i64 {
+        match self {
+            Period::Today | Period::Day => 86400,
+            Period::Week => 7 * 86400,
+            Period::Month => 30 * 86400,
+        }
+    }
+
+    // Start and end (unix seconds) of the period ending now. "Today" starts at
+    // local midnight, using the location's UTC offset.
+    pub fn window(self, now: i64, utc_offset: i32) -> (i64, i64) {
+        match self {
+            Period::Today => {
+                let local = now + utc_offset as i64;
+                (local - local.rem_euclid(86400) - utc_offset as i64, now)
+            }
+            _ => (now - self.length(), now),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum Comparison {
+    #[name = "The period before"]
+    Previous,
+    #[name = "Same time a week earlier"]
+    WeekEarlier,
+}
+
+impl Comparison {
+    // The window to compare against, same length and shifted back
+    pub fn shift(self, period: Period, (start, end): (i64, i64)) -> (i64, i64) {
+        let by = match self {
+            Comparison::Previous => period.length(),
+            Comparison::WeekEarlier => 7 * 86400,
+        };
+        (start - by, end - by)
+    }
+
+    pub fn describe(self, period: Period) -> &'static str {
+        match (self, period) {
+            (Comparison::WeekEarlier, Period::Today) => "same day last week",
+            (Comparison::WeekEarlier, _) => "a week earlier",
+            (Comparison::Previous, Period::Today) => "yesterday",
+            (Comparison::Previous, Period::Day) => "the 24 hours before",
+            (Comparison::Previous, Period::Week) => "the week before",
+            (Comparison::Previous, Period::Month) => "the 30 days before",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Stat {
+    pub min: f64,
+    pub avg: f64,
+    pub max: f64,
+}
+
+impl Stat {
+    fn of(values: impl Iterator<Item = f64> + Clone) -> Option<Stat> {
+        let count = values.clone().count();
+        if count == 0 {
+            return None;
+        }
+        Some(Stat {
+            min: values.clone().fold(f64::INFINITY, f64::min),
+            max: values.clone().fold(f64::NEG_INFINITY, f64::max),
+            avg: values.sum::<f64>() / count as f64,
+        })
+    }
+}
+
+// What a window of samples adds up to
+#[derive(Debug, Clone, Copy)]
+pub struct Summary {
+    pub samples: usize,
+    // Kelvin
+    pub temp: Stat,
+    // Percent
+    pub humidity: Stat,
+    // m/s
+    pub wind: Stat,
+}
+
+impl Series {
+    // Samples recorded between start and end
+    pub fn within(&self, (start, end): (i64, i64)) -> Vec<Sample> {
+        self.samples
+            .iter()
+            .filter(|s| s.at >= start && s.at <= end)
+            .copied()
+            .collect()
+    }
+
+    // None if nothing was recorded in the window
+    pub fn summary(&self, window: (i64, i64)) -> Option<Summary> {
+        let samples = self.within(window);
+        Some(Summary {
+            samples: samples.len(),
+            temp: Stat::of(samples.iter().map(|s| s.temp))?,
+            humidity: Stat::of(samples.iter().map(|s| s.humidity))?,
+            wind: Stat::of(samples.iter().map(|s| s.wind))?,
+        })
+    }
+}
+
+pub struct History {
+    store: JsonStore<HistoryData>,
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            store: JsonStore::open("history"),
+        }
+    }
+
+    // Start recording a location for a guild, with the weather we just looked up
+    // as the first sample. False if the guild is already at MAX_WATCHED.
+    pub async fn watch(&self, guild: u64, weather: &WeatherResponse) -> bool {
+        let key = key(weather);
+        self.store
+            .update(|data| {
+                let watched = data.watched.entry(guild).or_default();
+                if !watched.contains(&key) && watched.len() >= MAX_WATCHED {
+                    return false;
+                }
+                watched.insert(key.clone());
+                record(data, &key, weather, Utc::now().timestamp());
+                true
+            })
+            .await
+    }
+
+    // Returns whether the guild was watching it
+    pub async fn unwatch(&self, guild: u64, key: &str) -> bool {
+        self.store
+            .update(|data| {
+                data.watched
+                    .get_mut(&guild)
+                    .map(|watched| watched.remove(key))
+                    .unwrap_or(false)
+            })
+            .await
+    }
+
+    // The guild's locations, with their recorded names where we have them
+    pub async fn watched(&self, guild: u64) -> Vec<(String, usize)> {
+        self.store
+            .read(|data| {
+                let keys = match data.watched.get(&guild) {
+                    Some(keys) => keys,
+                    None => return Vec::new(),
+                };
+                keys.iter()
+                    .map(|key| match data.series.get(key) {
+                        Some(series) => (series.name.clone(), series.samples.len()),
+                        None => (key.clone(), 0),
+                    })
+                    .collect()
+            })
+            .await
+    }
+
+    // Key of a location the guild watches by its recorded name, for when the
+    // name no longer looks up. Keys from before coordinates are the name itself.
+    pub async fn key_named(&self, guild: u64, name: &str) -> Option<String> {
+        let name = name.trim().to_lowercase();
+        self.store
+            .read(|data| {
+                let watched = data.watched.get(&guild)?;
+                watched
+                    .iter()
+                    .find(|key| {
+                        **key == name
+                            || data
+                                .series
+                                .get(*key)
+                                .is_some_and(|s| s.name.to_lowercase() == name)
+                    })
+                    .cloned()
+            })
+            .await
+    }
+
+    // Everything recorded for a location, if the guild watches it. Series are
+    // shared, but a guild only sees what it asked to have recorded.
+    pub async fn series(&self, guild: u64, key: &str) -> Option<Series> {
+        self.store
+            .read(|data| {
+                let watched = data.watched.get(&guild)?;
+                if !watched.contains(key) {
+                    return None;
+                }
+                data.series.get(key).cloned()
+            })
+            .await
+    }
+
+    // Record every watched location once and drop samples past retention
+    async fn record_all(&self) {
+        // With where each was recorded from, if we know
+        let keys: Vec<(String, Option<Series>)> = self
+            .store
+            .read(|data| {
+                let keys: BTreeSet<&String> = data.watched.values().flatten().collect();
+                keys.into_iter()
+                    .map(|key| (key.clone(), data.series.get(key).cloned()))
+                    .collect()
+            })
+            .await;
+
+        let mut fetched = Vec::new();
+        for (key, series) in keys {
+            let result = match series.as_ref().and_then(|s| s.coord.map(|c| (s, c))) {
+                Some((series, coord)) => get_weather_at_cached(coord).await.map(|mut weather| {
+                    // A coordinate can come back named after a district, keep
+                    // the name it was watched under
+                    weather.name = series.name.clone();
+                    weather
+                }),
+                // Watched before keys were coordinates, look the name up once
+                None => get_weather_cached(&key).await,
+            };
+            match result {
+                Ok(weather) => fetched.push((key, weather)),
+                Err(e) => println!("Error recording {}: {:?}", key, e),
+            }
+        }
+
+        let now = Utc::now().timestamp();
+        let cutoff = now - retention_secs();
+        self.store
+            .update(|data| {
+                for (queried, weather) in &fetched {
+                    let key = key(weather);
+                    if key != *queried {
+                        rekey(data, queried, &key);
+                    }
+                    // An old key and its new one can both be watched, once is enough
+                    let recorded = data.series.get(&key).and_then(|s| s.samples.last());
+                    if recorded.is_some_and(|s| s.at == now) {
+                        continue;
+                    }
+                    record(data, &key, weather, now);
+                }
+                for series in data.series.values_mut() {
+                    series.samples.retain(|s| s.at >= cutoff);
+                }
+                data.series.retain(|_, series| !series.samples.is_empty());
+            })
+            .await;
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new()
+    }
+}
+
+// Move a location to a new key, e.g. one watched before keys were coordinates
+// ("paris, fr" becomes "@48.85,2.35"). Samples are merged if both exist.
+fn rekey(data: &mut HistoryData, old: &str, new: &str) {
+    for watched in data.watched.values_mut() {
+        if watched.remove(old) {
+            watched.insert(new.to_string());
+        }
+    }
+    if let Some(old) = data.series.remove(old) {
+        let series = data.series.entry(new.to_string()).or_default();
+        series.samples.extend(old.samples);
+        series.samples.sort_by_key(|s| s.at);
+    }
+}
+
+fn record(data: &mut HistoryData, key: &str, weather: &WeatherResponse, at: i64) {
+    let series = data.series.entry(key.to_string()).or_default();
+    series.name = weather.name.clone();
+    series.coord = Some(weather.coord);
+    series.timezone = weather.timezone;
+    series.samples.push(Sample::from_weather(weather, at));
+}
+
+// Record watched locations on a schedule. Runs for the life of the bot.
+pub async fn run(history: Arc<History>) {
+    let interval = interval();
+    loop {
+        tokio::time::sleep(interval).await;
+        history.record_all().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::Temperature;
+    use crate::weather::{Main, Sys};
+
+    fn weather(name: &str, country: &str, lat: f64, lon: f64) -> WeatherResponse {
+        WeatherResponse {
+            name: name.to_string(),
+            coord: Coord { lat, lon },
+            sys: Sys {
+                country: country.to_string(),
+                ..Default::default()
+            },
+            main: Main {
+                temp: Temperature::from_celsius(15.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn history(name: &str) -> History {
+        History {
+            store: JsonStore::scratch(name),
+        }
+    }
+
+    const PARIS: &str = "@48.85,2.35";
+
+    fn paris() -> WeatherResponse {
+        weather("Paris", "FR", 48.8534, 2.3488)
+    }
+
+    #[test]
+    fn keys_follow_the_resolved_location() {
+        assert_eq!(key(&paris()), PARIS);
+        assert_eq!(key(&weather(" paris", "", 48.8534, 2.3488)), PARIS);
+    }
+
+    #[tokio::test]
+    async fn guilds_only_see_what_they_watch() {
+        let history = history("guilds");
+        assert!(history.watch(1, &paris()).await);
+
+        assert_eq!(history.series(1, PARIS).await.unwrap().samples.len(), 1);
+        assert!(history.series(2, PARIS).await.is_none());
+        assert!(history.series(1, "paris, fr").await.is_none());
+
+        assert!(!history.unwatch(2, PARIS).await);
+        assert!(history.unwatch(1, PARIS).await);
+        assert!(history.series(1, PARIS).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn places_sharing_a_name_are_kept_apart() {
+        let history = history("springfield");
+        let illinois = weather("Springfield", "US", 39.8017, -89.6437);
+        let missouri = weather("Springfield", "US", 37.2153, -93.2982);
+        assert_ne!(key(&illinois), key(&missouri));
+
+        assert!(history.watch(1, &illinois).await);
+        assert!(history.watch(1, &missouri).await);
+        assert_eq!(history.watched(1).await.len(), 2);
+
+        let series = history.series(1, &key(&missouri)).await.unwrap();
+        assert_eq!(series.samples.len(), 1);
+        assert_eq!(series.coord, Some(missouri.coord));
+        let series = history.series(1, &key(&illinois)).await.unwrap();
+        assert_eq!(series.coord, Some(illinois.coord));
+    }
+
+    #[tokio::test]
+    async fn old_keys_move_to_the_resolved_one() {
+        let history = history("rekey");
+        history
+            .store
+            .update(|data| {
+                data.watched
+                    .entry(1)
+                    .or_default()
+                    .insert("paris, fr".to_string());
+                record(data, "paris, fr", &paris(), 100);
+                data.watched.entry(2).or_default().insert(PARIS.to_string());
+                record(data, PARIS, &paris(), 50);
+                rekey(data, "paris, fr", PARIS);
+            })
+            .await;
+
+        let series = history.series(1, PARIS).await.unwrap();
+        let times: Vec<i64> = series.samples.iter().map(|s| s.at).collect();
+        assert_eq!(times, vec![50, 100]);
+        assert_eq!(history.watched(1).await, vec![("Paris".to_string(), 2)]);
+    }
+
+    #[tokio::test]
+    async fn watched_places_are_found_by_name() {
+        let history = history("named");
+        assert!(history.watch(1, &paris()).await);
+        history
+            .store
+            .update(|data| {
+                data.watched
+                    .entry(1)
+                    .or_default()
+                    .insert("lyon, fr".to_string());
+            })
+            .await;
+
+        assert_eq!(
+            history.key_named(1, " PARIS").await,
+            Some(PARIS.to_string())
+        );
+        assert_eq!(
+            history.key_named(1, "Lyon, FR").await,
+            Some("lyon, fr".to_string())
+        );
+        assert_eq!(history.key_named(2, "paris").await, None);
+        assert_eq!(history.key_named(1, "nice").await, None);
+    }
+}
@@ -0,0 +1,235 @@
+use crate::storage::JsonStore;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Finished seasons we keep per guild
+const PAST_SEASONS: usize = 20;
+
+// One player's record for a season
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Standing {
+    // Display name as of their last round
+    pub name: String,
+    pub points: u64,
+    pub played: u32,
+    pub wins: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Season {
+    pub number: u32,
+    pub started: i64,
+    // Set once the season is over
+    pub ended: Option<i64>,
+    pub standings: HashMap<u64, Standing>,
+}
+
+impl Season {
+    fn new(number: u32) -> Season {
+        Season {
+            number,
+            started: Utc::now().timestamp(),
+            ..Default::default()
+        }
+    }
+
+    // Most points first, then most wins, then fewest rounds played
+    pub fn ranked(&self) -> Vec<(u64, &Standing)> {
+        let mut ranked: Vec<(u64, &Standing)> =
+            self.standings.iter().map(|(id, s)| (*id, s)).collect();
+        ranked.sort_by(|a, b| {
+            b.1.points
+                .cmp(&a.1.points)
+                .then(b.1.wins.cmp(&a.1.wins))
+                .then(a.1.played.cmp(&b.1.played))
+        });
+        ranked
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuildBoard {
+    pub current: Season,
+    // Oldest first
+    pub past: Vec<Season>,
+}
+
+impl GuildBoard {
+    fn new() -> GuildBoard {
+        GuildBoard {
+            current: Season::new(1),
+            past: Vec::new(),
+        }
+    }
+}
+
+// What one player got out of a round
+pub struct Award {
+    pub user: u64,
+    pub name: String,
+    pub points: u64,
+    pub won: bool,
+}
+
+// Per-guild scores and seasons for one game, saved as DATA_DIR/<game>.json
+pub struct Leaderboard {
+    store: JsonStore<HashMap<u64, GuildBoard>>,
+}
+
+impl Leaderboard {
+    pub fn open(game: &str) -> Leaderboard {
+        Leaderboard {
+            store: JsonStore::open(game),
+        }
+    }
+
+    // An empty board that isn't saved to DATA_DIR, for tests
+    #[cfg(test)]
+    pub fn scratch(game: &str) -> Leaderboard {
+        Leaderboard {
+            store: JsonStore::scratch(game),
+        }
+    }
+
+    // Add a round's results to the current season
+    pub async fn award(&self, guild: u64, awards: &[Award]) {
+        self.store
+            .update(|boards| {
+                let board = boards.entry(guild).or_insert_with(GuildBoard::new);
+                for award in awards {
+                    let standing = board.current.standings.entry(award.user).or_default();
+                    standing.name = award.name.clone();
+                    standing.points += award.points;
+                    standing.played += 1;
+                    if award.won {
+                        standing.wins += 1;
+                    }
+                }
+            })
+            .await;
+    }
+
+    // The current season, or a past one by number
+    pub async fn season(&self, guild: u64, number: Option<u32>) -> Option<Season> {
+        self.store
+            .read(|boards| {
+                let board = match boards.get(&guild) {
+                    Some(board) => board,
+                    // Nothing played yet, so season 1 is empty
+                    None => {
+                        return match number {
+                            None | Some(1) => Some(Season::new(1)),
+                            Some(_) => None,
+                        }
+                    }
+                };
+                match number {
+                    None => Some(board.current.clone()),
+                    Some(n) if n == board.current.number => Some(board.current.clone()),
+                    Some(n) => board.past.iter().find(|s| s.number == n).cloned(),
+                }
+            })
+            .await
+    }
+
+    // Close the current season and start the next, returns the one that ended
+    pub async fn new_season(&self, guild: u64) -> Season {
+        self.store
+            .update(|boards| {
+                let board = boards.entry(guild).or_insert_with(GuildBoard::new);
+                let next = Season::new(board.current.number + 1);
+                let mut finished = std::mem::replace(&mut board.current, next);
+                finished.ended = Some(Utc::now().timestamp());
+                board.past.push(finished.clone());
+                if board.past.len() > PAST_SEASONS {
+                    let extra = board.past.len() - PAST_SEASONS;
+                    board.past.drain(..extra);
+                }
+                finished
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn award(user: u64, points: u64, won: bool) -> Award {
+        Award {
+            user,
+            name: format!("player {}", user),
+            points,
+            won,
+        }
+    }
+
+    #[tokio::test]
+    async fn ranked_by_points_then_wins_then_fewest_played() {
+        let board = Leaderboard::scratch("ranked");
+        board
+            .award(
+                1,
+                &[award(1, 50, true), award(2, 50, false), award(3, 80, false)],
+            )
+            .await;
+        // Player 4 matches player 2's points and wins, but took two rounds
+        board.award(1, &[award(4, 20, false)]).await;
+        board.award(1, &[award(4, 30, false)]).await;
+
+        let season = board.season(1, None).await.unwrap();
+        let order: Vec<u64> = season.ranked().iter().map(|(id, _)| *id).collect();
+        assert_eq!(order, vec![3, 1, 2, 4]);
+        assert_eq!(season.standings[&4].played, 2);
+        assert_eq!(season.standings[&1].wins, 1);
+    }
+
+    #[tokio::test]
+    async fn guilds_start_on_an_empty_first_season() {
+        let board = Leaderboard::scratch("empty");
+        let season = board.season(1, None).await.unwrap();
+        assert_eq!(season.number, 1);
+        assert!(season.standings.is_empty());
+        assert_eq!(board.season(1, Some(1)).await.unwrap().number, 1);
+        assert!(board.season(1, Some(2)).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn new_seasons_start_from_zero() {
+        let board = Leaderboard::scratch("seasons");
+        board.award(1, &[award(1, 40, true)]).await;
+        board.award(2, &[award(1, 10, true)]).await;
+
+        let finished = board.new_season(1).await;
+        assert_eq!(finished.number, 1);
+        assert!(finished.ended.is_some());
+        assert_eq!(finished.standings[&1].points, 40);
+
+        board.award(1, &[award(1, 5, false)]).await;
+        let current = board.season(1, None).await.unwrap();
+        assert_eq!(current.number, 2);
+        assert!(current.ended.is_none());
+        assert_eq!(current.standings[&1].points, 5);
+        // The old one is still there to look back on
+        let past = board.season(1, Some(1)).await.unwrap();
+        assert_eq!(past.standings[&1].points, 40);
+        // Other guilds carry on
+        assert_eq!(board.season(2, None).await.unwrap().number, 1);
+    }
+
+    #[tokio::test]
+    async fn only_recent_seasons_are_kept() {
+        let board = Leaderboard::scratch("past");
+        for _ in 0..PAST_SEASONS + 3 {
+            board.new_season(1).await;
+        }
+        assert!(board.season(1, Some(3)).await.is_none());
+        assert!(board.season(1, Some(4)).await.is_some());
+        let current = board.season(1, None).await.unwrap();
+        assert_eq!(current.number, PAST_SEASONS as u32 + 4);
+    }
+}
@@ -3,22 +3,77 @@
 
 use dotenv::dotenv;
 use poise::serenity_prelude as serenity;
+use poise::ChoiceParameter;
 use poise::Context as PoiseContext;
 use reqwest::Client;
 use serde::Deserialize;
 use serenity::async_trait;
 use serenity::client::{Context as SContext, EventHandler};
 use serenity::model::gateway::Ready;
+mod buttons;
+mod catalog;
+mod chart;
 mod chatbot;
+mod conversation;
+mod facts;
+mod gazetteer;
+mod geo;
+mod guess;
+mod history;
+mod leaderboard;
+mod meteo;
+mod moderation;
+mod narrative;
+mod predict;
+mod preferences;
+mod prompts;
+mod quiz;
+mod render;
+mod roundup;
+mod tools;
+mod trip;
+mod storage;
+mod units;
+mod usage;
 mod weather;
 use chrono::prelude::*;
 use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use std::sync::Arc;
 use substring::Substring;
-use tokio;
-use weather::get_weather;
+use chatbot::Llm;
+use weather::{get_weather, get_weather_cached};
+
+// Shared state handed to every command
+pub struct Data {
+    // Chat completion backend used by weatherfact/weather_joke
+    pub llm: Arc<dyn chatbot::Llm>,
+    // Token budgets and usage accounting for the LLM commands
+    pub usage: Arc<usage::UsageTracker>,
+    // Facts and jokes already posted, so we don't repeat them
+    pub facts: Arc<facts::FactHistory>,
+    // Prompt templates and per-server personas
+    pub prompts: Arc<prompts::Prompts>,
+    // Screens LLM output before it's posted
+    pub moderation: Arc<moderation::Moderation>,
+    // /chat history per channel
+    pub conversations: Arc<conversation::Conversations>,
+    // Where and when each server gets the weekly extremes roundup
+    pub roundups: Arc<roundup::Roundups>,
+    // Cities /random showed lately in each channel
+    pub recent_picks: Arc<catalog::RecentPicks>,
+    // /guess rounds in progress and the per-server leaderboards
+    pub guess: Arc<guess::Games>,
+    // Daily forecast prediction league
+    pub predictions: Arc<predict::League>,
+    // /quiz games in progress and the per-server leaderboards
+    pub quiz: Arc<quiz::Quizzes>,
+    // Recorded observations for the locations servers watch
+    pub history: Arc<history::History>,
+    // Units people and servers picked with /units
+    pub preferences: Arc<preferences::Preferences>,
+}
 
 // Boilerplate from Poise docs
-type Data = ();
 pub type Error = Box<dyn std::error::Error + Send + Sync>;
 pub type Context<'a> = PoiseContext<'a, Data, Error>;
 
@@ -39,35 +94,18 @@ async fn weather(
     ctx: Context<'_>,
     // Define optional City argument
     #[description = "City to check weather for"] city: Option<String>,
+    #[description = "Add a short plain-English summary"] narrative: Option<bool>,
 ) -> Result<(), Error> {
     // Default to Charlotte if no city is provided
     let city = city // If the user didn't provide a city, default to "Charlotte"
         .as_deref()
         .unwrap_or("Charlotte");
 
-    // Get weather data from our weather API
-    match get_weather(city).await {
+    // Get weather data from our weather API (or a recent cached copy)
+    match get_weather_cached(city).await {
         Ok(weather) => {
-            let fahrenheit = (weather.main.temp - 273.15) * (9. / 5.) + 32.;
-            let fahrenheit_feels_like = (weather.main.feels_like - 273.15) * (9. / 5.) + 32.;
-            let fahrenheit_temp_min = (weather.main.temp_min - 273.15) * (9. / 5.) + 32.;
-            let fahrenheit_temp_max = (weather.main.temp_max - 273.15) * (9. / 5.) + 32.;
-            let humidity_merc = weather.main.pressure as f64 * 0.02953;
-
-            // Format the response as a string
-            let response = format!(
-                "The weather in {} is:\n🌡️ Temp: {:.2}°F  😓 Feels Like: {:.2}°F,\n🧊 Min Temp: {:.2}°F  🔥 Max Temp: {:.2}°F\n🌬️ Pressure: {:.2}inHg  💧 Humidity: {:.2}%",
-                weather.name,
-                fahrenheit,
-                fahrenheit_feels_like,
-                fahrenheit_temp_min,
-                fahrenheit_temp_max,
-                humidity_merc,
-                weather.main.humidity
-            );
-
-            // Send formatted response to Discord
-            ctx.say(response).await?;
+            // Send the rendered report with unit/refresh/forecast buttons
+            buttons::send_weather(ctx, city, weather, None, narrative.unwrap_or(false)).await?;
         }
         Err(e) => {
             println!("Error: {:?}", e);
@@ -80,6 +118,51 @@ async fn weather(
     Ok(())
 }
 
+#[poise::command(slash_command, prefix_command)]
+async fn forecast(
+    ctx: Context<'_>,
+    #[description = "City to get the forecast for"] city: Option<String>,
+    #[description = "Add a short plain-English summary"] narrative: Option<bool>,
+) -> Result<(), Error> {
+    // Default to "Charlotte" if no city is provided
+    let city = city.as_deref().unwrap_or("Charlotte");
+
+    match weather::get_forecast_cached(city).await {
+        Ok(forecast) => {
+            let fmt = render::unit_format(ctx).await;
+            let mut embed = render::daily_embed(&forecast, &fmt);
+            let mut text = format!("Forecast for {}", forecast.city.name);
+
+            // Summary sentence from the templates (reworded by the LLM if enabled)
+            if narrative.unwrap_or(false) {
+                // The LLM can take longer than Discord waits for a reply
+                if narrative::llm_enabled() {
+                    ctx.defer().await?;
+                }
+                let summary = narrative::polish(ctx, narrative::forecast(&forecast, &fmt)).await;
+                embed = embed.description(summary.clone());
+                text.push_str(&format!("\n{}", summary));
+            }
+            for day in forecast.daily().iter().take(7) {
+                text.push_str(&format!(
+                    "\n{}: 🔥 {} / 🧊 {}",
+                    day.date.format("%a %b %-d"),
+                    day.temp_max.format(&fmt),
+                    day.temp_min.format(&fmt)
+                ));
+            }
+
+            render::send_embed(ctx, embed, text).await?;
+        }
+        Err(_) => {
+            let response = format!("Could not retrieve a forecast for '{}'.", city);
+            ctx.say(response).await?;
+        }
+    }
+
+    Ok(())
+}
+
 #[poise::command(slash_command, prefix_command)]
 async fn temp(
     ctx: Context<'_>,
@@ -93,14 +176,11 @@ async fn temp(
     // Call the get_weather function to fetch weather data for the specified city
     match get_weather(city).await {
         Ok(weather_response) => {
-            // Extract temperature from the weather response in Kelvin
-            let temperature_kelvin = weather_response.main.temp;
-
-            // Convert temperature from Kelvin to Fahrenheit
-            let temperature_fahrenheit = (temperature_kelvin - 273.15) * (9.0 / 5.0) + 32.0;
-
-            // Convert temperature from Kelvin to Celsius
-            let temperature_celsius = temperature_kelvin - 273.15;
+            // Extract temperature from the weather response
+            let temperature = weather_response.main.temp;
+            let temperature_kelvin = temperature.kelvin();
+            let temperature_celsius = temperature.celsius();
+            let temperature_fahrenheit = temperature.fahrenheit();
 
             // Format the response with temperatures in all three units
             let response = format!(
@@ -112,7 +192,8 @@ async fn temp(
             );
 
             // Send the response to the Discord channel
-            ctx.say(response).await?;
+            let embed = render::simple_embed(&weather_response, "Temperature", &response);
+            render::send_embed(ctx, embed, response).await?;
         }
         Err(_) => {
             // Handle error if the city is not found or weather data cannot be retrieved
@@ -137,24 +218,30 @@ async fn sun(
     // Call the get_weather function to fetch weather data for the specified city
     match get_weather(city).await {
         Ok(weather_response) => {
-            // Extract temperature from the weather response in Kelvin
+            // Convert sunrise/sunset to the city's own local time
             let sunset = weather_response.sys.sunset as i64;
             let sunrise = weather_response.sys.sunrise as i64;
-            let sunset_utc = Utc.timestamp_opt(sunset, 0).unwrap().naive_utc();
-            let sunrise_utc = Utc.timestamp_opt(sunrise, 0).unwrap().naive_utc();
-            let dt = Local::now();
-            let offset = dt.offset().clone();
-            let sunset_local = DateTime::<Local>::from_naive_utc_and_offset(sunset_utc, offset);
-            let sunrise_local = DateTime::<Local>::from_naive_utc_and_offset(sunrise_utc, offset);
-
-            // Format the response as a string with UTC date and time
+            let (sunrise_local, sunset_local) = match (
+                weather_response.local_time(sunrise),
+                weather_response.local_time(sunset),
+            ) {
+                (Some(sunrise), Some(sunset)) => (sunrise, sunset),
+                _ => {
+                    let response = format!("Could not work out local times for '{}'", city);
+                    ctx.say(response).await?;
+                    return Ok(());
+                }
+            };
+
+            // Format the response as a string with the local date and time
             let response = format!(
                 "The sunset/sunrise in {} is:\nSunrise🌅 {:?}\nSunset🌙 {:?}",
                 city, sunrise_local, sunset_local,
             );
 
             // Send the response to the Discord channel
-            ctx.say(response).await?;
+            let embed = render::simple_embed(&weather_response, "Sunrise & Sunset", &response);
+            render::send_embed(ctx, embed, response).await?;
         }
         Err(_) => {
             // Handle error if the city is not found or weather data cannot be retrieved
@@ -187,7 +274,8 @@ async fn clouds(
             );
 
             // Send the response to the Discord channel
-            ctx.say(response).await?;
+            let embed = render::simple_embed(&weather_response, "Cloud Coverage", &response);
+            render::send_embed(ctx, embed, response).await?;
         }
         Err(_) => {
             // Handle error if the city is not found or weather data cannot be retrieved
@@ -211,17 +299,26 @@ async fn wind(
     match get_weather(city).await {
         Ok(weather_response) => {
             // Extract wind speed information from the weather response
-            let wind_speed_meters_per_sec = weather_response.wind.get_speed_meters_per_sec();
-            let wind_speed_mph = weather_response.wind.get_speed_mph();
+            let wind_speed_meters_per_sec = weather_response.wind.speed.meters_per_sec();
+            let wind_speed_mph = weather_response.wind.speed.mph();
+            let direction = meteo::compass(weather_response.wind.deg as f64);
+            let beaufort = meteo::beaufort(weather_response.wind.speed);
 
             // Format the response with the wind speed in miles per hour
             let response = format!(
-                "The wind speed in {} is\n💨 {:.2} mph ({} m/s)",
-                city, wind_speed_mph, wind_speed_meters_per_sec
+                "The wind speed in {} is\n💨 {:.2} mph ({} m/s) from the {} ({}°)\n🌀 Beaufort {}: {}",
+                city,
+                wind_speed_mph,
+                wind_speed_meters_per_sec,
+                direction,
+                weather_response.wind.deg,
+                beaufort.force,
+                beaufort.description
             );
 
             // Send the response to the Discord channel
-            ctx.say(response).await?;
+            let embed = render::simple_embed(&weather_response, "Wind", &response);
+            render::send_embed(ctx, embed, response).await?;
         }
         Err(_) => {
             // Handle error if the city is not found or weather data cannot be retrieved
@@ -232,176 +329,1584 @@ async fn wind(
 
     Ok(())
 }
+
 #[poise::command(slash_command, prefix_command)]
-pub async fn weatherfact(
+async fn precip(
     ctx: Context<'_>,
-    #[description = "City to check wind speed for"] city: Option<String>,
+    #[description = "City to check precipitation for"] city: Option<String>,
 ) -> Result<(), Error> {
+    // Default to "Charlotte" if no city is provided
     let city = city.as_deref().unwrap_or("Charlotte");
 
-    let http_client = Client::new();
-    let api = std::env::var("OPENAI_API_KEY").expect("missing OPENAI_API_KEY");
-    let request_body = serde_json::json!({
-        "model": "gpt-3.5-turbo",
-        "messages": [
-            {
-                "role": "user",
-                "content": format!("what is a different crazy historical weather fact for the city of {} in only 2 sentences", city)
+    match get_weather_cached(city).await {
+        Ok(weather_response) => {
+            let fmt = render::unit_format(ctx).await;
+            let mut response = format!("Precipitation in {}:", weather_response.name);
+
+            // Rain and snow are only in the payload when something fell
+            if let Some(rain) = &weather_response.rain {
+                if let Some(amount) = rain.rain_1h {
+                    response.push_str(&format!("\n🌧️ Rain, last hour: {}", amount.format(&fmt)));
+                }
+                if let Some(amount) = rain.rain_3h {
+                    response.push_str(&format!("\n🌧️ Rain, last 3 hours: {}", amount.format(&fmt)));
+                }
+            }
+            if let Some(snow) = &weather_response.snow {
+                if let Some(amount) = snow.snow_1h {
+                    response.push_str(&format!("\n🌨️ Snow, last hour: {}", amount.format(&fmt)));
+                }
+                if let Some(amount) = snow.snow_3h {
+                    response.push_str(&format!("\n🌨️ Snow, last 3 hours: {}", amount.format(&fmt)));
+                }
+            }
+            if weather_response.rain_recent().is_none() && weather_response.snow_recent().is_none() {
+                response.push_str("\n☀️ No precipitation reported");
             }
-        ],
-        "max_tokens": 100,
-    });
 
-    let res = http_client
-        .post("https://api.openai.com/v1/chat/completions")
-        .bearer_auth(api)
-        .json(&request_body)
-        .send()
-        .await?;
+            if let Some(visibility) = weather_response.visibility {
+                response.push_str(&format!("\n👀 Visibility: {}", visibility.format(&fmt)));
+            }
+
+            // Chance of precipitation over the next few hours, if the forecast is available
+            if let Ok(forecast) = weather::get_forecast_cached(city).await {
+                if let Some(next) = forecast.list.first() {
+                    response.push_str(&format!(
+                        "\n☔ Chance of precipitation in the next 3 hours: {:.0}%",
+                        next.pop * 100.0
+                    ));
+                }
+            }
+
+            let embed = render::simple_embed(&weather_response, "Precipitation", &response);
+            render::send_embed(ctx, embed, response).await?;
+        }
+        Err(_) => {
+            let response = format!("Could not retrieve precipitation information for '{}'.", city);
+            ctx.say(response).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[poise::command(slash_command, prefix_command)]
+async fn comfort(
+    ctx: Context<'_>,
+    #[description = "City to check comfort for"] city: Option<String>,
+) -> Result<(), Error> {
+    // Default to "Charlotte" if no city is provided
+    let city = city.as_deref().unwrap_or("Charlotte");
+
+    match get_weather_cached(city).await {
+        Ok(weather_response) => {
+            let fmt = render::unit_format(ctx).await;
+            let temp = weather_response.main.temp;
+            let humidity = weather_response.main.humidity;
+            let wind = weather_response.wind.speed;
+
+            // Work out the derived values from temperature, humidity and wind
+            let dew_point = meteo::dew_point(temp, humidity);
+            let feels_like = meteo::feels_like(temp, humidity, wind);
+            let apparent = meteo::apparent_temperature(temp, humidity, wind);
+            let absolute_humidity = meteo::absolute_humidity(temp, humidity);
+            let beaufort = meteo::beaufort(wind);
+
+            let mut response = format!(
+                "It's {} in {}, but it feels like {}.\n🌫️ Dew point {} — {}.\n💧 {}% humidity ({} g/m³ of water vapor)",
+                temp.format(&fmt),
+                city,
+                feels_like.format(&fmt),
+                dew_point.format(&fmt),
+                meteo::dew_point_comfort(dew_point).to_lowercase(),
+                humidity,
+                fmt.number(absolute_humidity, 1)
+            );
+            if let Some(chill) = meteo::wind_chill(temp, wind) {
+                response.push_str(&format!("\n🥶 Wind chill {}", chill.format(&fmt)));
+            } else if temp.fahrenheit() >= 80.0 {
+                let heat_index = meteo::heat_index(temp, humidity);
+                response.push_str(&format!("\n🥵 Heat index {}", heat_index.format(&fmt)));
+            }
+            response.push_str(&format!(
+                "\n💨 {} from the {} (Beaufort {})\n🌡️ Apparent temperature {}",
+                beaufort.description,
+                meteo::compass(weather_response.wind.deg as f64),
+                beaufort.force,
+                apparent.format(&fmt)
+            ));
+
+            let embed = render::simple_embed(&weather_response, "How it feels", &response);
+            render::send_embed(ctx, embed, response).await?;
+        }
+        Err(_) => {
+            let response = format!("Could not retrieve comfort information for '{}'.", city);
+            ctx.say(response).await?;
+        }
+    }
+
+    Ok(())
+}
 
-    let response_text = res.text().await?;
-    let response: chatbot::OpenAIResponse = serde_json::from_str(&response_text)?;
+// Generate a fact or joke that hasn't been posted here recently and remember it.
+// Falls back to the built-in corpus if the LLM can't come up with something new.
+async fn post_fact(ctx: Context<'_>, kind: facts::Kind, city: &str) -> Result<(), Error> {
+    let history = &ctx.data().facts;
+    let scope = facts::scope(ctx);
+    let recent = history.recent(kind, &scope, city).await;
 
-    let response_text = response
-        .choices
-        .get(0)
-        .and_then(|c| Some(c.message.content.clone()))
-        .unwrap_or_else(|| String::from("No response"));
+    // Fill in the configured template in this server's persona
+    let prompts = &ctx.data().prompts;
+    let template = match kind {
+        facts::Kind::Fact => "weatherfact",
+        facts::Kind::Joke => "weather_joke",
+    };
+    let fmt = render::unit_format(ctx).await;
+    let mut vars = vec![
+        ("city", city.to_string()),
+        ("units", fmt.system.name().to_string()),
+        ("locale", ctx.locale().unwrap_or("en-US").to_string()),
+    ];
+    // Only look up the weather if the template actually wants it
+    if prompts.template(template).uses("conditions") {
+        let conditions = match get_weather_cached(city).await {
+            Ok(weather) => narrative::current(&weather, &fmt),
+            Err(_) => "unknown".to_string(),
+        };
+        vars.push(("conditions", conditions));
+    }
+    let guild = ctx.guild_id().map(|g| g.get());
+    let request = prompts
+        .request(template, guild, &vars, &facts::avoid_prompt(&recent))
+        .await;
 
-    poise::say_reply(ctx, response_text).await?;
+    // Each generated reply counts against the user's and server's daily budget
+    let llm = match usage::metered(ctx).await {
+        Ok(llm) => llm,
+        Err(exhausted) => {
+            ctx.say(exhausted.to_string()).await?;
+            return Ok(());
+        }
+    };
 
+    // Post the reply once it's finished, swapping in a corpus entry if it
+    // turns out to be something we've already posted. Not streamed live so a
+    // repeat never shows up, even briefly.
+    let fallback = facts::fallback(kind, &recent);
+    let posted = chatbot::stream_reply(ctx, &llm, request, &fallback, false, |text| {
+        if facts::is_repeat(&text, &recent) {
+            fallback.clone()
+        } else {
+            text
+        }
+    })
+    .await?;
+    // Nothing to remember if the message was deleted before it was posted
+    let posted = match posted {
+        Some(posted) => posted,
+        None => return Ok(()),
+    };
+
+    history
+        .record(kind, &scope, city, &posted, posted != fallback)
+        .await;
+    Ok(())
+}
+
+// Prefix use (~weatherfact Paris) still posts a fact, slash commands use the subcommands
+#[poise::command(
+    slash_command,
+    prefix_command,
+    subcommands("weatherfact_new", "weatherfact_history")
+)]
+pub async fn weatherfact(
+    ctx: Context<'_>,
+    #[description = "City to get a weather fact for"] city: Option<String>,
+) -> Result<(), Error> {
+    let city = city.as_deref().unwrap_or("Charlotte");
+    post_fact(ctx, facts::Kind::Fact, city).await
+}
+
+#[poise::command(slash_command, prefix_command, rename = "new")]
+pub async fn weatherfact_new(
+    ctx: Context<'_>,
+    #[description = "City to get a weather fact for"] city: Option<String>,
+) -> Result<(), Error> {
+    let city = city.as_deref().unwrap_or("Charlotte");
+    post_fact(ctx, facts::Kind::Fact, city).await
+}
+
+#[poise::command(slash_command, prefix_command, rename = "history")]
+pub async fn weatherfact_history(
+    ctx: Context<'_>,
+    #[description = "City to list past facts for"] city: Option<String>,
+) -> Result<(), Error> {
+    let city = city.as_deref().unwrap_or("Charlotte");
+    let entries = ctx
+        .data()
+        .facts
+        .list(facts::Kind::Fact, &facts::scope(ctx), city, 10)
+        .await;
+
+    if entries.is_empty() {
+        let response = format!("No weather facts have been posted for {} here yet.", city);
+        ctx.say(response).await?;
+        return Ok(());
+    }
+
+    let mut response = format!("📜 Recent weather facts for {}\n", city);
+    for entry in entries {
+        let source = if entry.generated { "" } else { " (archive)" };
+        response.push_str(&format!("\n<t:{}:d>{} {}", entry.at, source, entry.text));
+    }
+    ctx.say(chatbot::clamp_message(&response)).await?;
     Ok(())
 }
 
 #[poise::command(slash_command, prefix_command)]
 pub async fn weather_joke(ctx: Context<'_>) -> Result<(), Error> {
-    let http_client = Client::new();
-    let api = std::env::var("OPENAI_API_KEY").expect("missing OPENAI_API_KEY");
-    let request_body = serde_json::json!({
-        "model": "gpt-3.5-turbo",
-        "messages": [
-            {
-                "role": "user",
-                "content": "tell me a joke about the weather or jokes about meteorologists"
-            }
-        ],
-        "max_tokens": 50,
-    });
+    post_fact(ctx, facts::Kind::Joke, "").await
+}
 
-    let res = http_client
-        .post("https://api.openai.com/v1/chat/completions")
-        .bearer_auth(api)
-        .json(&request_body)
-        .send()
-        .await?;
+// One turn of /chat: reply with the channel's history as context, then remember the exchange
+async fn chat_turn(ctx: Context<'_>, message: &str) -> Result<(), Error> {
+    // The weather and history lookups below can outlast Discord's 3 second window
+    ctx.defer().await?;
+    let data = ctx.data();
+    let channel = ctx.channel_id().get();
+    let guild = ctx.guild_id().map(|g| g.get());
+
+    let llm = match usage::metered(ctx).await {
+        Ok(llm) => llm,
+        Err(exhausted) => {
+            ctx.say(exhausted.to_string()).await?;
+            return Ok(());
+        }
+    };
+
+    // Current weather for the server's city, so "should I bring a jacket?" just works
+    let city = data.conversations.default_city(guild).await;
+    let weather = match get_weather_cached(&city).await {
+        Ok(weather) => Some(format!(
+            "Current weather in {}: {}",
+            city,
+            narrative::current(&weather, &render::unit_format(ctx).await)
+        )),
+        Err(_) => None,
+    };
+
+    let (_, persona) = data.prompts.persona(guild).await;
+    let conversation = data.conversations.get(channel).await;
+    let user = chatbot::Message::user(format!("{}: {}", ctx.author().name, message));
+    let request = conversation::request(&persona.system, weather, &conversation, &user);
+
+    let fallback = "Sorry, I couldn't come up with a reply right now.";
+    let reply = chatbot::stream_reply(ctx, &llm, request, fallback, true, |text| text).await?;
+    if let Some(reply) = reply.filter(|reply| reply != fallback) {
+        data.conversations
+            .push(channel, user, chatbot::Message::assistant(reply))
+            .await;
+        data.conversations.compact(channel, &llm).await;
+    }
+    Ok(())
+}
 
-    let response_text = res.text().await?;
-    let response: chatbot::OpenAIResponse = serde_json::from_str(&response_text)?;
+// Prefix use (~chat hello) talks to the bot, slash commands use the subcommands
+#[poise::command(
+    slash_command,
+    prefix_command,
+    subcommands("chat_say", "chat_reset", "chat_city")
+)]
+async fn chat(
+    ctx: Context<'_>,
+    #[description = "What to say to the bot"]
+    #[rest]
+    message: String,
+) -> Result<(), Error> {
+    chat_turn(ctx, &message).await
+}
 
-    let response_text = response
-        .choices
-        .get(0)
-        .and_then(|c| Some(c.message.content.clone()))
-        .unwrap_or_else(|| String::from("No response"));
+#[poise::command(slash_command, prefix_command, rename = "say")]
+async fn chat_say(
+    ctx: Context<'_>,
+    #[description = "What to say to the bot"]
+    #[rest]
+    message: String,
+) -> Result<(), Error> {
+    chat_turn(ctx, &message).await
+}
 
-    poise::say_reply(ctx, response_text).await?;
+#[poise::command(slash_command, prefix_command, rename = "reset")]
+async fn chat_reset(ctx: Context<'_>) -> Result<(), Error> {
+    let channel = ctx.channel_id().get();
+    let response = if ctx.data().conversations.reset(channel).await {
+        "Conversation forgotten, starting fresh."
+    } else {
+        "There's no conversation to reset in this channel."
+    };
+    ctx.say(response).await?;
+    Ok(())
+}
 
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "city",
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+async fn chat_city(
+    ctx: Context<'_>,
+    #[description = "City whose weather the bot knows about in /chat"] city: String,
+) -> Result<(), Error> {
+    // guild_only guarantees a guild id
+    let guild = ctx.guild_id().unwrap().get();
+    if get_weather_cached(&city).await.is_err() {
+        let response = format!("Could not find weather for '{}'.", city);
+        ctx.say(response).await?;
+        return Ok(());
+    }
+    ctx.data().conversations.set_default_city(guild, &city).await;
+    let response = format!("/chat will now use the weather in {}.", city);
+    ctx.say(response).await?;
     Ok(())
 }
 
 #[poise::command(slash_command, prefix_command)]
-async fn random(
+async fn ask(
     ctx: Context<'_>,
+    #[description = "Your weather question, e.g. should I bike to work in Charlotte tomorrow?"]
+    #[rest]
+    question: String,
 ) -> Result<(), Error> {
-    let (city, country, flag) = weather::get_random_city();  // Get a random city name
+    let llm = match usage::metered(ctx).await {
+        Ok(llm) => llm,
+        Err(exhausted) => {
+            ctx.say(exhausted.to_string()).await?;
+            return Ok(());
+        }
+    };
+    if !llm.capabilities().tools {
+        ctx.say("The configured language model doesn't support looking up weather data, so I can't answer questions right now.")
+            .await?;
+        return Ok(());
+    }
 
-    match weather::get_weather(city).await {
-        Ok(weather_response) => {
-            let fahrenheit = (weather_response.main.temp - 273.15) * 9.0 / 5.0 + 32.0;
-            let feels_like = (weather_response.main.feels_like - 273.15) * 9.0 / 5.0 + 32.0;
-            let temp_min = (weather_response.main.temp_min - 273.15) * 9.0 / 5.0 + 32.0;
-            let temp_max = (weather_response.main.temp_max - 273.15) * 9.0 / 5.0 + 32.0;
-            let pressure = weather_response.main.pressure as f64 * 0.02953;
-            
-            let response = format!(
-                "The weather in {}, {} {} is:\n🌡️ Temp: {:.2}°F  😓 Feels Like: {:.2}°F,\n🧊 Min Temp: {:.2}°F  🔥 Max Temp: {:.2}°F\n🌬️ Pressure: {:.2}inHg  💧 Humidity: {}%",
-                city, country, flag, fahrenheit, feels_like, temp_min, temp_max, pressure, weather_response.main.humidity
-            );
- 
-            ctx.say(response).await?;
+    // Looking things up can take a while, so acknowledge the interaction first
+    ctx.defer().await?;
+
+    let response = match tools::answer(&llm, &question).await {
+        Ok((answer, sources)) => match moderation::screen(ctx, &answer).await {
+            Some(answer) if sources.is_empty() => answer,
+            Some(answer) => format!("{}\n\n📊 Based on: {}", answer, sources.join(", ")),
+            None => String::from("Sorry, I can't post the answer I came up with for that."),
         },
-        Err(_) => {
-            ctx.say(format!("Could not find weather data for '{}', '{}'", city, country)).await?;
+        Err(e) => {
+            println!("Error: {}", e);
+            String::from("Sorry, I couldn't answer that right now.")
         }
+    };
+
+    ctx.say(chatbot::clamp_message(&response)).await?;
+
+    Ok(())
+}
+
+#[poise::command(slash_command, prefix_command, rename = "usage")]
+async fn usage_report(ctx: Context<'_>) -> Result<(), Error> {
+    let tracker = &ctx.data().usage;
+    let user = ctx.author().id.get();
+    let guild = ctx.guild_id().map(|g| g.get());
+
+    let (user_today, guild_today, guild_total) = tracker.report(user, guild).await;
+    let (user_limit, guild_limit) = tracker.limits(guild).await;
+
+    let mut response = format!(
+        "🤖 AI usage today\nYou: {} / {} tokens ({} requests)",
+        user_today.tokens, user_limit, user_today.requests
+    );
+    if guild.is_some() {
+        response.push_str(&format!(
+            "\nThis server: {} / {} tokens ({} requests, ~${:.4})\nAll time: {} tokens ({} requests, ~${:.4})",
+            guild_today.tokens,
+            guild_limit,
+            guild_today.requests,
+            tracker.cost(guild_today.tokens),
+            guild_total.tokens,
+            guild_total.requests,
+            tracker.cost(guild_total.tokens)
+        ));
     }
+    response.push_str(&format!("\nBudgets reset <t:{}:R>", usage::next_reset()));
 
+    ctx.say(response).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, prefix_command, guild_only, required_permissions = "MANAGE_GUILD")]
+async fn budget(
+    ctx: Context<'_>,
+    #[description = "Daily tokens each member may use (leave empty for the default)"] user_daily: Option<u64>,
+    #[description = "Daily tokens for the whole server (leave empty for the default)"] guild_daily: Option<u64>,
+) -> Result<(), Error> {
+    // guild_only guarantees a guild id
+    let guild = ctx.guild_id().unwrap().get();
+    let tracker = &ctx.data().usage;
+
+    tracker
+        .set_limits(guild, usage::Limits { user_daily, guild_daily })
+        .await;
+    let (user_limit, guild_limit) = tracker.limits(Some(guild)).await;
+
+    ctx.say(format!(
+        "Daily AI budgets updated: {} tokens per member, {} tokens for the server.",
+        user_limit, guild_limit
+    ))
+    .await?;
     Ok(())
 }
 
 #[poise::command(slash_command, prefix_command)]
-async fn distance(
+async fn personas(ctx: Context<'_>) -> Result<(), Error> {
+    let prompts = &ctx.data().prompts;
+    let (current, _) = prompts.persona(ctx.guild_id().map(|g| g.get())).await;
+
+    let mut response = String::from("🎭 Bot personas");
+    for (name, persona) in prompts.personas() {
+        let marker = if *name == current { " ← current" } else { "" };
+        response.push_str(&format!("\n**{}**: {}{}", name, persona.description, marker));
+    }
+    ctx.say(response).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, prefix_command, guild_only, required_permissions = "MANAGE_GUILD")]
+async fn persona(
     ctx: Context<'_>,
-    #[description = "First city"] city1: Option<String>,
-    #[description = "Second city"] city2: Option<String>,
+    #[description = "Persona for this server's AI replies (see /personas)"] name: String,
 ) -> Result<(), Error> {
-    let city1 = city1.as_deref().unwrap_or("Charlotte");
-    let city2 = city2.as_deref().unwrap_or("Charlotte"); 
+    // guild_only guarantees a guild id
+    let guild = ctx.guild_id().unwrap().get();
+    let name = name.trim().to_lowercase();
 
-    pub const PI: f64 = 3.14159265358979323846264338327950288_f64; // 3.1415926535897931f64
+    match ctx.data().prompts.set_persona(guild, &name).await {
+        Ok(()) => {
+            let response = format!("AI replies in this server will now use the **{}** persona.", name);
+            ctx.say(response).await?;
+        }
+        Err(e) => {
+            ctx.say(e).await?;
+        }
+    }
+    Ok(())
+}
 
-    match get_weather(city1).await {
-        Ok(weather_response) => { 
-            let coord1 = weather_response.coord.lat;
-            let coord2 = weather_response.coord.lon;
-            let _response = format!(
-                "The weather in {}, lat {}, lon {}",
-                city1, coord1, coord2
-            );
+#[poise::command(slash_command, prefix_command, guild_only, required_permissions = "MANAGE_GUILD")]
+async fn moderation_log(ctx: Context<'_>) -> Result<(), Error> {
+    // guild_only guarantees a guild id
+    let guild = ctx.guild_id().unwrap().get();
+    let blocked = ctx.data().moderation.blocked(guild, 10).await;
 
-            // ctx.say(response).await?;
-        
-
-        match get_weather(city2).await {
-            Ok(weather_response) => { 
-    
-                let coord3 = weather_response.coord.lat;
-                let coord4 = weather_response.coord.lon;
-
-                use std::f64::consts::PI;
-
-            fn haversine_distance(coord1: f64, coord2: f64, coord3: f64, coord4: f64) -> f64 {
-                let r = 6371.0; // Earth's radius in kilometers
-                let d_lat = (coord3 - coord1) * (PI / 180.0);
-                let d_lon = (coord4 - coord2) * (PI / 180.0);
-                let a = (d_lat / 2.0).sin().powi(2)
-                        + (coord1 * (PI / 180.0)).cos()
-                        * (coord3 * (PI / 180.0)).cos()
-                        * (d_lon / 2.0).sin().powi(2);
-                let c = 2.0 * ((a.sqrt()).atan2((1.0 - a).sqrt()));
-                let d = r * c;
-    
-                d // Return the distance
+    if blocked.is_empty() {
+        ctx.say("No AI replies have been blocked in this server.").await?;
+        return Ok(());
+    }
+
+    let mut response = String::from("🚫 Recently blocked AI replies");
+    for entry in blocked {
+        response.push_str(&format!(
+            "\n\n<t:{}:f> /{} by <@{}> in <#{}>\nReason: {}\n> {}",
+            entry.at,
+            entry.command,
+            entry.user,
+            entry.channel,
+            entry.reason,
+            moderation::strip_mentions(&entry.text.replace('\n', " "))
+        ));
+    }
+    // Only the admin who asked sees the blocked text
+    ctx.send(
+        poise::CreateReply::default()
+            .content(chatbot::clamp_message(&response))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, prefix_command)]
+async fn random(
+    ctx: Context<'_>,
+    #[description = "Only cities on this continent"] continent: Option<catalog::Continent>,
+    #[description = "Only cities in this country, e.g. USA"] country: Option<String>,
+    #[description = "Only cities in this hemisphere"] hemisphere: Option<catalog::Hemisphere>,
+    #[description = "Somewhere it's like this right now"] conditions: Option<catalog::Conditions>,
+    #[description = "Only cities with at least this many people"] min_population: Option<u64>,
+) -> Result<(), Error> {
+    let filter = catalog::Filter {
+        continent,
+        country,
+        hemisphere,
+        min_population,
+    };
+    let candidates = catalog::filter(&filter);
+    if candidates.is_empty() {
+        ctx.say("None of the cities I know match that.").await?;
+        return Ok(());
+    }
+
+    // Checking conditions means looking up several cities
+    if conditions.is_some() {
+        ctx.defer().await?;
+    }
+    let channel = ctx.channel_id().get();
+    let candidates = ctx.data().recent_picks.exclude(channel, candidates).await;
+    match catalog::pick(candidates, conditions).await {
+        Some(observation) => {
+            let city = observation.city;
+            ctx.data().recent_picks.record(channel, city).await;
+            render::send_weather(ctx, &observation.weather, Some(city.flag.as_str())).await?;
+        }
+        None => {
+            let response = match conditions {
+                Some(conditions) => format!(
+                    "Couldn't find anywhere {} right now, try again or loosen the filters.",
+                    conditions.describe()
+                ),
+                None => "Could not retrieve weather for a random city right now.".to_string(),
+            };
+            ctx.say(response).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[poise::command(slash_command, prefix_command)]
+async fn compare(
+    ctx: Context<'_>,
+    #[description = "Two to five cities, e.g. Charlotte Denver Miami"]
+    #[rest]
+    cities: String,
+) -> Result<(), Error> {
+    let names = gazetteer::split_places(&cities);
+    if !(2..=5).contains(&names.len()) {
+        ctx.say("Give me two to five cities to compare, e.g. `/compare Charlotte Denver`")
+            .await?;
+        return Ok(());
+    }
+
+    // Look them all up at once rather than one after another
+    let lookups = names.iter().map(|name| get_weather_cached(name));
+    let results = futures::future::join_all(lookups).await;
+
+    let mut weathers = Vec::new();
+    for (name, result) in names.iter().zip(results) {
+        match result {
+            Ok(weather) => weathers.push(weather),
+            Err(_) => {
+                ctx.say(format!("Could not find weather data for '{}'", name)).await?;
+                return Ok(());
             }
-                let distance = haversine_distance(coord1, coord2, coord3, coord4);
-                let miles = distance * 0.621371;
-                let _response = format!(
-                    "The distance between {} and {} is {:.2} kilometers, and {:.2} miles.",
-                    city1, city2, distance, miles,
+        }
+    }
+
+    let fmt = render::unit_format(ctx).await;
+    let embed = render::compare_embed(&weathers, &fmt);
+    render::send_embed(ctx, embed, render::compare_text(&weathers, &fmt)).await
+}
+
+// Sweep the catalog (or part of it) and post the standout cities
+async fn post_extremes(
+    ctx: Context<'_>,
+    continent: Option<catalog::Continent>,
+    country: Option<String>,
+) -> Result<(), Error> {
+    let filter = catalog::Filter {
+        continent,
+        country: country.clone(),
+        ..Default::default()
+    };
+    let cities = catalog::filter(&filter);
+    if cities.is_empty() {
+        ctx.say("None of the cities I know match that.").await?;
+        return Ok(());
+    }
+    let scope = match (continent, &country) {
+        (_, Some(country)) => country.clone(),
+        (Some(continent), None) => continent.name().to_string(),
+        (None, None) => "the world".to_string(),
+    };
+
+    // Dozens of lookups, so acknowledge the interaction first
+    ctx.defer().await?;
+    let (observations, _) = catalog::sweep(cities).await;
+    let extremes = match catalog::extremes(&observations) {
+        Some(extremes) => extremes,
+        None => {
+            ctx.say("Could not retrieve weather for any of those cities right now.")
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let fmt = render::unit_format(ctx).await;
+    let checked = observations.len();
+    let embed = render::extremes_embed(&extremes, checked, &scope, &fmt);
+    let text = render::extremes_text(&extremes, checked, &scope, &fmt);
+    render::send_embed(ctx, embed, text).await
+}
+
+// Prefix use (~extremes) posts the roundup, slash commands use the subcommands
+#[poise::command(
+    slash_command,
+    prefix_command,
+    subcommands("extremes_now", "extremes_schedule", "extremes_stop")
+)]
+async fn extremes(ctx: Context<'_>) -> Result<(), Error> {
+    post_extremes(ctx, None, None).await
+}
+
+#[poise::command(slash_command, prefix_command, rename = "now")]
+async fn extremes_now(
+    ctx: Context<'_>,
+    #[description = "Only cities on this continent"] continent: Option<catalog::Continent>,
+    #[description = "Only cities in this country, e.g. USA"] country: Option<String>,
+) -> Result<(), Error> {
+    post_extremes(ctx, continent, country).await
+}
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "schedule",
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+async fn extremes_schedule(
+    ctx: Context<'_>,
+    #[description = "Channel to post in (defaults to this one)"]
+    channel: Option<serenity::ChannelId>,
+    #[description = "Day of the week (default Monday)"] day: Option<roundup::Day>,
+    #[description = "Hour of the day in UTC, 0-23 (default 12)"]
+    #[min = 0]
+    #[max = 23]
+    hour: Option<u32>,
+) -> Result<(), Error> {
+    // guild_only guarantees a guild id
+    let guild = ctx.guild_id().unwrap().get();
+    let channel = channel.unwrap_or_else(|| ctx.channel_id());
+    let schedule = ctx
+        .data()
+        .roundups
+        .set(
+            guild,
+            channel.get(),
+            day.unwrap_or(roundup::Day::Monday),
+            hour.unwrap_or(12).min(23),
+        )
+        .await;
+
+    let response = format!(
+        "Weekly extremes roundup will be posted in <#{}>, next on <t:{}:F>.",
+        channel.get(),
+        schedule.next(Utc::now())
+    );
+    ctx.say(response).await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "stop",
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+async fn extremes_stop(ctx: Context<'_>) -> Result<(), Error> {
+    // guild_only guarantees a guild id
+    let guild = ctx.guild_id().unwrap().get();
+    let response = if ctx.data().roundups.remove(guild).await {
+        "Weekly extremes roundup turned off."
+    } else {
+        "There's no weekly roundup scheduled in this server."
+    };
+    ctx.say(response).await?;
+    Ok(())
+}
+
+// A game's standings for this server, the current season or a past one
+async fn show_leaderboard(
+    ctx: Context<'_>,
+    leaderboard: &leaderboard::Leaderboard,
+    game: &str,
+    season: Option<u32>,
+) -> Result<(), Error> {
+    // Only called from guild_only commands
+    let guild = ctx.guild_id().unwrap().get();
+    let season = match leaderboard.season(guild, season).await {
+        Some(season) => season,
+        None => {
+            ctx.say("There's no season with that number.").await?;
+            return Ok(());
+        }
+    };
+    let embed = render::leaderboard_embed(game, &season, 10);
+    let text = render::leaderboard_text(game, &season, 10);
+    render::send_embed(ctx, embed, text).await
+}
+
+// Close a game's season for this server and announce the champion
+async fn start_new_season(
+    ctx: Context<'_>,
+    leaderboard: &leaderboard::Leaderboard,
+) -> Result<(), Error> {
+    // Only called from guild_only commands
+    let guild = ctx.guild_id().unwrap().get();
+    let finished = leaderboard.new_season(guild).await;
+    let champion = match finished.ranked().first() {
+        Some((_, standing)) => {
+            format!(" Champion: {} with {} pts.", standing.name, standing.points)
+        }
+        None => String::new(),
+    };
+    let response = format!(
+        "Season {} is over and season {} starts now.{}",
+        finished.number,
+        finished.number + 1,
+        champion
+    );
+    ctx.say(response).await?;
+    Ok(())
+}
+
+// Prefix use (~guess) starts a round, slash commands use the subcommands
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    subcommands("guess_start", "guess_leaderboard", "guess_new_season")
+)]
+async fn guess(ctx: Context<'_>) -> Result<(), Error> {
+    guess::play(ctx, guess::DEFAULT_SECONDS).await
+}
+
+#[poise::command(slash_command, prefix_command, rename = "start", guild_only)]
+async fn guess_start(
+    ctx: Context<'_>,
+    #[description = "How long guessing stays open, in seconds (default 60)"]
+    #[min = 15]
+    #[max = 300]
+    seconds: Option<u64>,
+) -> Result<(), Error> {
+    guess::play(ctx, seconds.unwrap_or(guess::DEFAULT_SECONDS)).await
+}
+
+#[poise::command(slash_command, prefix_command, rename = "leaderboard", guild_only)]
+async fn guess_leaderboard(
+    ctx: Context<'_>,
+    #[description = "A past season's number (defaults to the current one)"] season: Option<u32>,
+) -> Result<(), Error> {
+    show_leaderboard(
+        ctx,
+        &ctx.data().guess.leaderboard,
+        "Guess the temperature",
+        season,
+    )
+    .await
+}
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "newseason",
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+async fn guess_new_season(ctx: Context<'_>) -> Result<(), Error> {
+    start_new_season(ctx, &ctx.data().guess.leaderboard).await
+}
+
+// Prefix use (~quiz) starts a quiz, slash commands use the subcommands
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    subcommands("quiz_start", "quiz_leaderboard", "quiz_new_season")
+)]
+async fn quiz(ctx: Context<'_>) -> Result<(), Error> {
+    quiz::play(ctx, quiz::DEFAULT_QUESTIONS, quiz::Difficulty::Medium, None).await
+}
+
+#[poise::command(slash_command, prefix_command, rename = "start", guild_only)]
+async fn quiz_start(
+    ctx: Context<'_>,
+    #[description = "How many questions (default 5)"]
+    #[min = 1]
+    #[max = 10]
+    questions: Option<u32>,
+    #[description = "Harder is closer choices and less time"] difficulty: Option<quiz::Difficulty>,
+    #[description = "Only ask this kind of question"] topic: Option<quiz::Topic>,
+) -> Result<(), Error> {
+    quiz::play(
+        ctx,
+        questions.unwrap_or(quiz::DEFAULT_QUESTIONS),
+        difficulty.unwrap_or(quiz::Difficulty::Medium),
+        topic,
+    )
+    .await
+}
+
+#[poise::command(slash_command, prefix_command, rename = "leaderboard", guild_only)]
+async fn quiz_leaderboard(
+    ctx: Context<'_>,
+    #[description = "A past season's number (defaults to the current one)"] season: Option<u32>,
+) -> Result<(), Error> {
+    show_leaderboard(ctx, &ctx.data().quiz.leaderboard, "Weather quiz", season).await
+}
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "newseason",
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+async fn quiz_new_season(ctx: Context<'_>) -> Result<(), Error> {
+    start_new_season(ctx, &ctx.data().quiz.leaderboard).await
+}
+
+async fn prediction_standings(ctx: Context<'_>) -> Result<(), Error> {
+    // guild_only guarantees a guild id
+    let guild = ctx.guild_id().unwrap().get();
+    let season = ctx
+        .data()
+        .predictions
+        .leaderboard
+        .season(guild, None)
+        .await
+        .unwrap_or_default();
+    // Seasons run a calendar month
+    let month = match Utc.timestamp_opt(season.started, 0).single() {
+        Some(started) => started.format("%B %Y").to_string(),
+        None => String::new(),
+    };
+    let game = format!("Prediction league, {}", month);
+    let embed = render::leaderboard_embed(&game, &season, 10);
+    let text = render::leaderboard_text(&game, &season, 10);
+    render::send_embed(ctx, embed, text).await
+}
+
+// Prefix use (~predict) shows the standings, slash commands use the subcommands
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    subcommands("predict_tomorrow", "predict_standings", "predict_me", "predict_setup")
+)]
+async fn predict(ctx: Context<'_>) -> Result<(), Error> {
+    prediction_standings(ctx).await
+}
+
+#[poise::command(slash_command, prefix_command, rename = "tomorrow", guild_only)]
+async fn predict_tomorrow(
+    ctx: Context<'_>,
+    #[description = "Tomorrow's high, e.g. 75 or 24C"] high: String,
+    #[description = "Tomorrow's low, e.g. 58 or 14C"] low: String,
+    #[description = "Will it rain or snow at all?"] precipitation: bool,
+) -> Result<(), Error> {
+    // guild_only guarantees a guild id
+    let guild = ctx.guild_id().unwrap().get();
+    let fmt = render::unit_format(ctx).await;
+    let (high, low) = match (
+        units::Temperature::parse(&high, fmt.system),
+        units::Temperature::parse(&low, fmt.system),
+    ) {
+        (Some(high), Some(low)) => (high, low),
+        _ => {
+            ctx.say("Give temperatures like 75 or 24C.").await?;
+            return Ok(());
+        }
+    };
+    if low > high {
+        ctx.say("The low can't be warmer than the high.").await?;
+        return Ok(());
+    }
+
+    let city = match ctx.data().predictions.city(guild).await {
+        Some(city) => city,
+        None => ctx.data().conversations.default_city(Some(guild)).await,
+    };
+    // "Tomorrow" is by the city's clock, not the bot's
+    let weather = match get_weather_cached(&city).await {
+        Ok(weather) => weather,
+        Err(_) => {
+            ctx.say(format!("Could not find weather data for '{}'", city))
+                .await?;
+            return Ok(());
+        }
+    };
+    let tomorrow = match predict::local_date(&weather).and_then(|d| d.succ_opt()) {
+        Some(tomorrow) => tomorrow,
+        None => {
+            ctx.say("Could not work out the date there, try again later.")
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let prediction = predict::Prediction {
+        name: ctx.author().display_name().to_string(),
+        high,
+        low,
+        precipitation,
+        at: Utc::now().timestamp(),
+    };
+    let city = ctx
+        .data()
+        .predictions
+        .predict(
+            guild,
+            tomorrow,
+            &city,
+            weather.coord,
+            ctx.channel_id().get(),
+            ctx.author().id.get(),
+            prediction,
+        )
+        .await;
+
+    let outlook = if precipitation {
+        "☔ wet"
+    } else {
+        "☀️ dry"
+    };
+    // Kept private so nobody can copy
+    let response = format!(
+        "Got it: high {}, low {}, {} in {} on {}. You can change it until midnight there.",
+        high.format(&fmt),
+        low.format(&fmt),
+        outlook,
+        city,
+        tomorrow.format("%A %B %-d")
+    );
+    ctx.send(
+        poise::CreateReply::default()
+            .content(response)
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, prefix_command, rename = "standings", guild_only)]
+async fn predict_standings(ctx: Context<'_>) -> Result<(), Error> {
+    prediction_standings(ctx).await
+}
+
+#[poise::command(slash_command, prefix_command, rename = "me", guild_only)]
+async fn predict_me(ctx: Context<'_>) -> Result<(), Error> {
+    // guild_only guarantees a guild id
+    let guild = ctx.guild_id().unwrap().get();
+    let user = ctx.author().id.get();
+    let player = ctx.data().predictions.player(guild, user).await;
+    let season = ctx
+        .data()
+        .predictions
+        .leaderboard
+        .season(guild, None)
+        .await
+        .unwrap_or_default();
+
+    let mut response = match season.standings.get(&user) {
+        Some(standing) => format!(
+            "{} pts this month from {} days ({} won).",
+            standing.points, standing.played, standing.wins
+        ),
+        None => "No scored predictions this month yet.".to_string(),
+    };
+    response.push_str(&format!(
+        "\n🔥 Streak: {} days (best {})",
+        player.streak, player.best_streak
+    ));
+    if player.achievements.is_empty() {
+        response.push_str("\nNo achievements yet.");
+    }
+    for achievement in &player.achievements {
+        response.push_str(&format!(
+            "\n{}: {}",
+            achievement.name(),
+            achievement.description()
+        ));
+    }
+    ctx.say(response).await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "setup",
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+async fn predict_setup(
+    ctx: Context<'_>,
+    #[description = "City everyone predicts for"] city: Option<String>,
+    #[description = "Channel for the daily results"] channel: Option<serenity::ChannelId>,
+) -> Result<(), Error> {
+    // guild_only guarantees a guild id
+    let guild = ctx.guild_id().unwrap().get();
+    if let Some(city) = &city {
+        if get_weather_cached(city).await.is_err() {
+            ctx.say(format!("Could not find weather data for '{}'", city))
+                .await?;
+            return Ok(());
+        }
+    }
+
+    let league = &ctx.data().predictions;
+    league
+        .configure(guild, city, channel.map(|c| c.get()))
+        .await;
+    let city = match league.city(guild).await {
+        Some(city) => city,
+        None => ctx.data().conversations.default_city(Some(guild)).await,
+    };
+    let response = format!(
+        "The prediction league is for {}. Changes apply from the next day nobody has predicted yet.",
+        city
+    );
+    ctx.say(response).await?;
+    Ok(())
+}
+
+// Prefix use (~history) just explains, slash commands use the subcommands
+#[poise::command(
+    slash_command,
+    prefix_command,
+    subcommands("history_show", "history_watch", "history_unwatch", "history_watched")
+)]
+async fn history(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.say("Use `history show <city>` for recorded weather, or `history watched` to see what's recorded here.")
+        .await?;
+    Ok(())
+}
+
+// What this server has recorded for a city, or None after explaining why not
+async fn watched_series(ctx: Context<'_>, city: &str) -> Result<Option<history::Series>, Error> {
+    let guild = match ctx.guild_id() {
+        Some(guild) => guild.get(),
+        None => {
+            ctx.say("Recorded weather is kept per server, try this in one.")
+                .await?;
+            return Ok(None);
+        }
+    };
+    // Recordings are keyed by where the weather API puts the city
+    let key = match get_weather_cached(city).await {
+        Ok(weather) => history::key(&weather),
+        Err(_) => {
+            ctx.say(format!("Could not find weather data for '{}'", city))
+                .await?;
+            return Ok(None);
+        }
+    };
+    let series = ctx.data().history.series(guild, &key).await;
+    if series.is_none() {
+        let response = format!(
+            "This server isn't recording '{}'. An admin can start with `/history watch`.",
+            city
+        );
+        ctx.say(response).await?;
+    }
+    Ok(series)
+}
+
+#[poise::command(slash_command, prefix_command, rename = "show", guild_only)]
+async fn history_show(
+    ctx: Context<'_>,
+    #[description = "A watched city"] city: String,
+    #[description = "Time span to summarize (default today)"] period: Option<history::Period>,
+    #[description = "Compare with another span"] compare: Option<history::Comparison>,
+) -> Result<(), Error> {
+    let series = match watched_series(ctx, &city).await? {
+        Some(series) => series,
+        None => return Ok(()),
+    };
+
+    let period = period.unwrap_or(history::Period::Today);
+    let window = period.window(Utc::now().timestamp(), series.timezone);
+    let summary = match series.summary(window) {
+        Some(summary) => summary,
+        None => {
+            ctx.say("Nothing was recorded in that period.").await?;
+            return Ok(());
+        }
+    };
+    let compared = compare.and_then(|c| {
+        series
+            .summary(c.shift(period, window))
+            .map(|s| (c.describe(period), s))
+    });
+    if compare.is_some() && compared.is_none() {
+        ctx.say("There aren't any recordings to compare with yet, here's the period on its own.")
+            .await?;
+    }
+
+    let fmt = render::unit_format(ctx).await;
+    let title = format!("{} · {}", series.name, period.name());
+    let compared = compared.as_ref().map(|(label, s)| (*label, s));
+    let embed = render::history_embed(&title, &summary, compared, &fmt);
+    let text = render::history_text(&title, &summary, compared, &fmt);
+    render::send_embed(ctx, embed, text).await
+}
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "watch",
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+async fn history_watch(
+    ctx: Context<'_>,
+    #[description = "City to start recording"]
+    #[rest]
+    city: String,
+) -> Result<(), Error> {
+    // guild_only guarantees a guild id
+    let guild = ctx.guild_id().unwrap().get();
+    let weather = match get_weather_cached(&city).await {
+        Ok(weather) => weather,
+        Err(_) => {
+            ctx.say(format!("Could not find weather data for '{}'", city))
+                .await?;
+            return Ok(());
+        }
+    };
+    let response = if ctx.data().history.watch(guild, &weather).await {
+        format!(
+            "Recording the weather in {} from now on. Check it with `/history show`.",
+            weather.name
+        )
+    } else {
+        format!(
+            "This server already records {} places, unwatch one first.",
+            history::MAX_WATCHED
+        )
+    };
+    ctx.say(response).await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "unwatch",
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+async fn history_unwatch(
+    ctx: Context<'_>,
+    #[description = "City to stop recording"]
+    #[rest]
+    city: String,
+) -> Result<(), Error> {
+    // guild_only guarantees a guild id
+    let guild = ctx.guild_id().unwrap().get();
+    // Fall back to the recorded name if it doesn't look up any more
+    let key = match get_weather_cached(&city).await {
+        Ok(weather) => history::key(&weather),
+        Err(_) => ctx
+            .data()
+            .history
+            .key_named(guild, &city)
+            .await
+            .unwrap_or_default(),
+    };
+    let response = if ctx.data().history.unwatch(guild, &key).await {
+        format!("No longer recording '{}' for this server.", city)
+    } else {
+        format!("This server isn't recording '{}'.", city)
+    };
+    ctx.say(response).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, prefix_command, rename = "watched", guild_only)]
+async fn history_watched(ctx: Context<'_>) -> Result<(), Error> {
+    // guild_only guarantees a guild id
+    let guild = ctx.guild_id().unwrap().get();
+    let watched = ctx.data().history.watched(guild).await;
+    if watched.is_empty() {
+        ctx.say("Nothing is being recorded for this server yet.")
+            .await?;
+        return Ok(());
+    }
+    let lines: Vec<String> = watched
+        .iter()
+        .map(|(name, samples)| format!("{} · {} observations", name, samples))
+        .collect();
+    let response = format!("Recording:\n{}", lines.join("\n"));
+    ctx.say(response).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, prefix_command)]
+async fn chart(
+    ctx: Context<'_>,
+    #[description = "City to chart"] city: String,
+    #[description = "Forecast or recorded history"] source: Option<chart::Source>,
+    #[description = "Forecast hours to cover (default 48, up to 120)"] hours: Option<u32>,
+    #[description = "Recorded span to cover (default 24 hours)"] period: Option<history::Period>,
+    #[description = "Units: imperial, metric or scientific"] units: Option<String>,
+) -> Result<(), Error> {
+    let mut fmt = render::unit_format(ctx).await;
+    if let Some(units) = units {
+        match units::UnitSystem::parse(&units) {
+            Some(system) => fmt = fmt.with_system(system),
+            None => {
+                let response = format!(
+                    "I don't know the units '{}', try metric or imperial.",
+                    units
                 );
-    
-                ctx.say(_response).await?;
-            },
-            Err(_) => {
-                ctx.say(format!("Could not find weather data for '{}'", city2)).await?;
+                ctx.say(response).await?;
+                return Ok(());
             }
         }
+    }
+    let hours = hours
+        .unwrap_or(chart::DEFAULT_HOURS)
+        .clamp(chart::MIN_HOURS, chart::MAX_HOURS);
+    ctx.defer().await?;
+
+    let mut graph = match source.unwrap_or(chart::Source::Forecast) {
+        chart::Source::Forecast => match weather::get_forecast_cached(&city).await {
+            Ok(forecast) => chart::Chart::forecast(&forecast, hours),
+            Err(_) => {
+                ctx.say(format!("Could not retrieve a forecast for '{}'.", city))
+                    .await?;
+                return Ok(());
+            }
         },
-        Err(_) => {
-            ctx.say(format!("Could not find weather data for '{}'", city1)).await?;
+        chart::Source::History => {
+            let series = match watched_series(ctx, &city).await? {
+                Some(series) => series,
+                None => return Ok(()),
+            };
+            let period = period.unwrap_or(history::Period::Day);
+            let window = period.window(Utc::now().timestamp(), series.timezone);
+            chart::Chart::history(&series, window, period.name())
+        }
+    };
+    // History doesn't keep sun times, today's are close enough to repeat
+    if graph.sun.is_none() {
+        if let Ok(weather) = get_weather_cached(&city).await {
+            graph.sun = Some((weather.sys.sunrise as i64, weather.sys.sunset as i64));
         }
-    
     }
 
+    let png = match chart::render(&graph, &fmt)? {
+        Some(png) => png,
+        None => {
+            ctx.say("There isn't enough data in that period to chart yet.")
+                .await?;
+            return Ok(());
+        }
+    };
+    let embed = serenity::CreateEmbed::new()
+        .title(&graph.title)
+        .image("attachment://chart.png");
+    ctx.send(
+        poise::CreateReply::default()
+            .embed(embed)
+            .attachment(serenity::CreateAttachment::bytes(png, "chart.png")),
+    )
+    .await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, prefix_command)]
+async fn trip(
+    ctx: Context<'_>,
+    #[description = "Stops in order, e.g. Charlotte Atlanta Nashville --depart 8am"]
+    #[rest]
+    route: String,
+) -> Result<(), Error> {
+    // Everything after --depart is the departure time
+    let (route, depart) = match route.split_once("--depart") {
+        Some((route, depart)) => (route, depart.trim()),
+        None => (route.as_str(), "now"),
+    };
+    let names = gazetteer::split_places(route);
+    if names.len() < 2 {
+        ctx.say("A trip needs at least two stops, e.g. `/trip Charlotte Atlanta Nashville --depart 8am`")
+            .await?;
+        return Ok(());
+    }
+    if names.len() > trip::MAX_STOPS {
+        let response = format!("A trip can have at most {} stops.", trip::MAX_STOPS);
+        ctx.say(response).await?;
+        return Ok(());
+    }
+
+    // Several forecast lookups, so acknowledge the interaction first
+    ctx.defer().await?;
+
+    let mut stops = Vec::new();
+    for name in &names {
+        match gazetteer::resolve(name).await {
+            Ok(place) => stops.push(place),
+            Err(_) => {
+                ctx.say(format!("Could not find a location for '{}'", name)).await?;
+                return Ok(());
+            }
+        }
+    }
+
+    let now = Utc::now().timestamp();
+    let depart_at = match trip::parse_depart(depart, stops[0].utc_offset_at(now), now) {
+        Some(depart_at) => depart_at,
+        None => {
+            let response = format!(
+                "Couldn't understand the departure time '{}'. Try something like 8am, 14:30 or tomorrow 9am.",
+                depart
+            );
+            ctx.say(response).await?;
+            return Ok(());
+        }
+    };
+
+    let fmt = render::unit_format(ctx).await;
+    let reports = trip::forecast(trip::plan(&stops, depart_at)).await;
+    let mut lines = Vec::new();
+    for report in &reports {
+        let waypoint = &report.waypoint;
+        let marker = match (&waypoint.stop, waypoint.flown) {
+            (Some(_), true) => "✈️",
+            (Some(_), false) => "📍",
+            (None, _) => "·",
+        };
+        let weather = match &report.entry {
+            Some(entry) => format!(
+                "{} · {} · 💧 {:.0}%",
+                entry.main.temp.format(&fmt),
+                entry
+                    .weather
+                    .first()
+                    .map(|c| c.description.clone())
+                    .unwrap_or_default(),
+                entry.pop * 100.0
+            ),
+            None => "beyond the forecast".to_string(),
+        };
+        lines.push(format!(
+            "{} <t:{}:t> **{}** ({}) · {}",
+            marker,
+            waypoint.eta,
+            report.name,
+            units::Distance::from_km(waypoint.km).format(&fmt),
+            weather
+        ));
+    }
+
+    let title = format!(
+        "Trip: {}",
+        stops.iter().map(|s| s.name.as_str()).collect::<Vec<_>>().join(" → ")
+    );
+    let footer = format!(
+        "Leaving <t:{}:f>. Times assume driving at ~90 km/h (legs over 1000 km are flown).",
+        depart_at
+    );
+    let body = chatbot::clamp_message(&format!("{}\n\n{}", lines.join("\n"), footer));
+    let mut embed = serenity::CreateEmbed::new()
+        .title(&title)
+        .description(&body);
+    if let Some(entry) = reports.first().and_then(|r| r.entry.as_ref()) {
+        embed = embed.color(render::temperature_color(entry.main.temp));
+    }
+    render::send_embed(ctx, embed, format!("{}\n{}", title, body)).await
+}
+
+#[poise::command(slash_command, prefix_command)]
+async fn distance(
+    ctx: Context<'_>,
+    #[description = "Where to"] destination: String,
+    #[description = "Where from (default Charlotte)"] origin: Option<String>,
+) -> Result<(), Error> {
+    let origin = origin.as_deref().unwrap_or("Charlotte");
+
+    let lookups = futures::join!(gazetteer::resolve(origin), gazetteer::resolve(&destination));
+    let (from, to) = match lookups {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(_), _) => {
+            ctx.say(format!("Could not find a location for '{}'", origin))
+                .await?;
+            return Ok(());
+        }
+        (_, Err(_)) => {
+            ctx.say(format!("Could not find a location for '{}'", destination))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let km = geo::haversine_km(from.coord, to.coord);
+    let distance = units::Distance::from_km(km);
+    let initial = geo::initial_bearing(from.coord, to.coord);
+    let final_bearing = geo::final_bearing(from.coord, to.coord);
+
+    let mut body = format!(
+        "📏 {:.0} km · {:.0} mi · {:.0} nmi",
+        distance.km(),
+        distance.miles(),
+        distance.nautical_miles()
+    );
+    body.push_str(&format!(
+        "\n🧭 Head {} ({:.0}°), arriving heading {} ({:.0}°)",
+        meteo::compass(initial),
+        initial,
+        meteo::compass(final_bearing),
+        final_bearing
+    ));
+
+    // Time zone difference right now, daylight saving included where we know the zone
+    let difference = to.utc_offset() - from.utc_offset();
+    let hours = geo::format_hours(difference.abs() as f64 / 3600.0);
+    body.push_str(&match difference {
+        0 => format!("\n🕐 {} and {} are on the same time", from.name, to.name),
+        d if d > 0 => format!("\n🕐 {} is {} ahead of {}", to.name, hours, from.name),
+        _ => format!("\n🕐 {} is {} behind {}", to.name, hours, from.name),
+    });
+
+    let estimates = geo::travel_estimates(km);
+    if !estimates.is_empty() {
+        body.push_str("\n\n**Rough travel times**");
+        for (mode, hours) in estimates {
+            body.push_str(&format!("\n{}: ~{}", mode, geo::format_hours(hours)));
+        }
+    }
+
+    // Weather halfway there, which is often somewhere nobody has heard of (or the sea)
+    let middle = geo::midpoint(from.coord, to.coord);
+    body.push_str(&format!(
+        "\n\n**Midpoint** {:.2}, {:.2}",
+        middle.lat, middle.lon
+    ));
+    let midpoint_weather = weather::get_weather_at_cached(middle).await.ok();
+    if let Some(weather) = &midpoint_weather {
+        let fmt = render::unit_format(ctx).await;
+        let place = if weather.name.is_empty() {
+            "open water".to_string()
+        } else {
+            format!("near {}", weather.name)
+        };
+        body.push_str(&format!(
+            " ({})\n{}, {}",
+            place,
+            render::condition_text(weather).unwrap_or_else(|| "Unknown conditions".to_string()),
+            weather.main.temp.format(&fmt)
+        ));
+    }
+
+    let title = format!("{} → {}", from.label(), to.label());
+    let embed = match &midpoint_weather {
+        Some(weather) => render::simple_embed(weather, &title, &body),
+        None => serenity::CreateEmbed::new().title(&title).description(&body),
+    };
+    render::send_embed(ctx, embed, format!("{}\n{}", title, body)).await
+}
+
+// "default" clears a preference, anything else has to be a unit system
+fn parse_units(input: &str) -> Option<Option<units::UnitSystem>> {
+    match input.trim().to_lowercase().as_str() {
+        "default" | "reset" => Some(None),
+        other => units::UnitSystem::parse(other).map(Some),
+    }
+}
+
+// Prefix use (~units) shows the units in effect, slash commands use the subcommands
+#[poise::command(
+    slash_command,
+    prefix_command,
+    subcommands("units_show", "units_set", "units_server")
+)]
+async fn units(ctx: Context<'_>) -> Result<(), Error> {
+    show_units(ctx).await
+}
+
+async fn show_units(ctx: Context<'_>) -> Result<(), Error> {
+    let fmt = render::unit_format(ctx).await;
+    let response = format!("You're seeing weather in {} units.", fmt.system.name());
+    ctx.say(response).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, prefix_command, rename = "show")]
+async fn units_show(ctx: Context<'_>) -> Result<(), Error> {
+    show_units(ctx).await
+}
+
+#[poise::command(slash_command, prefix_command, rename = "set")]
+async fn units_set(
+    ctx: Context<'_>,
+    #[description = "imperial, metric, scientific, or default to use the server's"] units: String,
+) -> Result<(), Error> {
+    let system = match parse_units(&units) {
+        Some(system) => system,
+        None => {
+            let response = format!(
+                "I don't know the units '{}', try metric or imperial.",
+                units
+            );
+            ctx.say(response).await?;
+            return Ok(());
+        }
+    };
+    ctx.data()
+        .preferences
+        .set_user_units(ctx.author().id.get(), system)
+        .await;
+    let response = match system {
+        Some(system) => format!("You'll see weather in {} units.", system.name()),
+        None => "You'll see weather in this server's units.".to_string(),
+    };
+    ctx.say(response).await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "server",
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+async fn units_server(
+    ctx: Context<'_>,
+    #[description = "imperial, metric, scientific, or default"] units: String,
+) -> Result<(), Error> {
+    // guild_only guarantees a guild id
+    let guild = ctx.guild_id().unwrap().get();
+    let system = match parse_units(&units) {
+        Some(system) => system,
+        None => {
+            let response = format!(
+                "I don't know the units '{}', try metric or imperial.",
+                units
+            );
+            ctx.say(response).await?;
+            return Ok(());
+        }
+    };
+    ctx.data().preferences.set_guild_units(guild, system).await;
+    let name = system.unwrap_or(units::UnitSystem::Imperial).name();
+    let response = format!(
+        "Weather in this server now defaults to {} units. Anyone can pick their own with /units set.",
+        name
+    );
+    ctx.say(response).await?;
     Ok(())
 }
 
@@ -419,21 +1924,75 @@ async fn main() {
             // (Adding slash commands here)
             commands: vec![
                 weather(),
+                forecast(),
                 weather_joke(),
                 temp(),
                 clouds(),
                 wind(),
+                comfort(),
+                precip(),
                 sun(),
                 weatherfact(),
+                ask(),
+                usage_report(),
+                budget(),
+                personas(),
+                persona(),
+                moderation_log(),
+                chat(),
+                trip(),
+                compare(),
+                extremes(),
+                guess(),
+                predict(),
+                quiz(),
+                history(),
+                chart(),
                 random(),
                 distance(),
+                units(),
             ],
+            // Nothing the bot posts may ping anyone: no @everyone/@here, roles or users
+            allowed_mentions: Some(serenity::CreateAllowedMentions::new()),
             ..Default::default()
         })
         .setup(|ctx, _ready, framework| {
             Box::pin(async move {
                 poise::builtins::register_globally(ctx, &framework.options().commands).await?;
-                Ok(())
+                // Connect to the configured LLM server and see what it supports
+                let mut llm = chatbot::LlmClient::from_env()?;
+                // Bad prompt config should stop the bot here, not fail on the first command
+                let prompts = prompts::Prompts::load()?;
+                let moderation = moderation::Moderation::from_env()?;
+                catalog::load()?;
+                gazetteer::load()?;
+                llm.detect_capabilities().await;
+
+                // Weekly extremes roundups post in the background
+                let roundups = Arc::new(roundup::Roundups::new());
+                tokio::spawn(roundup::run(roundups.clone(), ctx.http.clone()));
+                // The prediction league samples and scores in the background too
+                let predictions = Arc::new(predict::League::new());
+                tokio::spawn(predict::run(predictions.clone(), ctx.http.clone()));
+                // And watched locations get recorded for /history
+                let history = Arc::new(history::History::new());
+                tokio::spawn(history::run(history.clone()));
+
+                Ok(Data {
+                    llm: Arc::new(llm),
+                    usage: Arc::new(usage::UsageTracker::new()),
+                    facts: Arc::new(facts::FactHistory::new()),
+                    prompts: Arc::new(prompts),
+                    moderation: Arc::new(moderation),
+                    conversations: Arc::new(conversation::Conversations::new()),
+                    roundups,
+                    recent_picks: Arc::new(catalog::RecentPicks::new()),
+                    guess: Arc::new(guess::Games::new()),
+                    predictions,
+                    quiz: Arc::new(quiz::Quizzes::new()),
+                    history,
+                    preferences: Arc::new(preferences::Preferences::new()),
+                })
             })
         })
         .build();
@@ -0,0 +1,307 @@
+use crate::units::{Speed, Temperature};
+
+// Dew point using the Magnus formula (good to ~0.35°C between -45°C and 60°C)
+pub fn dew_point(temp: Temperature, humidity: u32) -> Temperature {
+    let (a, b) = (17.62, 243.12);
+    let t = temp.celsius();
+    let rh = (humidity.max(1) as f64) / 100.0;
+    let gamma = rh.ln() + a * t / (b + t);
+    Temperature::from_celsius(b * gamma / (a - gamma))
+}
+
+// NWS heat index (Rothfusz regression with the NWS adjustments)
+pub fn heat_index(temp: Temperature, humidity: u32) -> Temperature {
+    let t = temp.fahrenheit();
+    let rh = humidity as f64;
+
+    // Steadman's simple formula, used when it comes out below 80°F
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+    if (simple + t) / 2.0 < 80.0 {
+        return Temperature::from_fahrenheit(simple);
+    }
+
+    let mut hi = -42.379 + 2.04901523 * t + 10.14333127 * rh
+        - 0.22475541 * t * rh
+        - 0.00683783 * t * t
+        - 0.05481717 * rh * rh
+        + 0.00122874 * t * t * rh
+        + 0.00085282 * t * rh * rh
+        - 0.00000199 * t * t * rh * rh;
+
+    if rh < 13.0 && (80.0..=112.0).contains(&t) {
+        hi -= ((13.0 - rh) / 4.0) * ((17.0 - (t - 95.0).abs()) / 17.0).sqrt();
+    } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+        hi += ((rh - 85.0) / 10.0) * ((87.0 - t) / 5.0);
+    }
+    Temperature::from_fahrenheit(hi)
+}
+
+// NWS wind chill, only defined at or below 50°F with wind of at least 3 mph
+pub fn wind_chill(temp: Temperature, wind: Speed) -> Option<Temperature> {
+    let t = temp.fahrenheit();
+    let v = wind.mph();
+    if t > 50.0 || v < 3.0 {
+        return None;
+    }
+    let v16 = v.powf(0.16);
+    Some(Temperature::from_fahrenheit(
+        35.74 + 0.6215 * t - 35.75 * v16 + 0.4275 * t * v16,
+    ))
+}
+
+// Australian apparent temperature (Steadman), accounts for humidity and wind together
+pub fn apparent_temperature(temp: Temperature, humidity: u32, wind: Speed) -> Temperature {
+    let t = temp.celsius();
+    let e = (humidity as f64 / 100.0) * 6.105 * (17.27 * t / (237.7 + t)).exp();
+    Temperature::from_celsius(t + 0.33 * e - 0.70 * wind.meters_per_sec() - 4.00)
+}
+
+// What the NWS would call "feels like": wind chill when cold, heat index when hot
+pub fn feels_like(temp: Temperature, humidity: u32, wind: Speed) -> Temperature {
+    if let Some(chill) = wind_chill(temp, wind) {
+        chill
+    } else if temp.fahrenheit() >= 80.0 {
+        heat_index(temp, humidity)
+    } else {
+        temp
+    }
+}
+
+// Water vapor in the air, in grams per cubic meter
+pub fn absolute_humidity(temp: Temperature, humidity: u32) -> f64 {
+    let t = temp.celsius();
+    6.112 * (17.67 * t / (t + 243.5)).exp() * humidity as f64 * 2.1674 / (273.15 + t)
+}
+
+// How a dew point feels to most people
+pub fn dew_point_comfort(dew_point: Temperature) -> &'static str {
+    match dew_point.fahrenheit() {
+        d if d < 50.0 => "Dry and comfortable",
+        d if d < 60.0 => "Comfortable",
+        d if d < 65.0 => "A little sticky",
+        d if d < 70.0 => "Humid and muggy",
+        d if d < 75.0 => "Oppressive",
+        _ => "Miserable",
+    }
+}
+
+// Beaufort wind force and its description
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Beaufort {
+    pub force: u8,
+    pub description: &'static str,
+}
+
+// Upper bound (m/s) of each Beaufort number, force 12 is anything above the last
+const BEAUFORT_SCALE: [(f64, &str); 12] = [
+    (0.5, "Calm"),
+    (1.6, "Light air"),
+    (3.4, "Light breeze"),
+    (5.5, "Gentle breeze"),
+    (8.0, "Moderate breeze"),
+    (10.8, "Fresh breeze"),
+    (13.9, "Strong breeze"),
+    (17.2, "Near gale"),
+    (20.8, "Gale"),
+    (24.5, "Strong gale"),
+    (28.5, "Storm"),
+    (32.7, "Violent storm"),
+];
+
+pub fn beaufort(wind: Speed) -> Beaufort {
+    let speed = wind.meters_per_sec();
+    for (force, (limit, description)) in BEAUFORT_SCALE.iter().enumerate() {
+        if speed < *limit {
+            return Beaufort {
+                force: force as u8,
+                description,
+            };
+        }
+    }
+    Beaufort {
+        force: 12,
+        description: "Hurricane force",
+    }
+}
+
+const COMPASS_POINTS: [&str; 16] = [
+    "N", "NNE", "NE", "ENE", "E", "ESE", "SE", "SSE", "S", "SSW", "SW", "WSW", "W", "WNW", "NW",
+    "NNW",
+];
+
+// Meteorological degrees (direction the wind comes from) to a 16-point compass label
+pub fn compass(degrees: f64) -> &'static str {
+    let index = ((degrees.rem_euclid(360.0) / 22.5) + 0.5) as usize % 16;
+    COMPASS_POINTS[index]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn f(fahrenheit: f64) -> Temperature {
+        Temperature::from_fahrenheit(fahrenheit)
+    }
+
+    fn c(celsius: f64) -> Temperature {
+        Temperature::from_celsius(celsius)
+    }
+
+    fn mph(mph: f64) -> Speed {
+        Speed::from_mph(mph)
+    }
+
+    // The NWS charts are rounded to whole degrees
+    fn assert_near(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "expected {} ± {}, got {}",
+            expected,
+            tolerance,
+            actual
+        );
+    }
+
+    #[test]
+    fn dew_point_reference_values() {
+        assert_near(dew_point(c(20.0), 50).celsius(), 9.3, 0.1);
+        assert_near(dew_point(c(25.0), 60).celsius(), 16.7, 0.1);
+        assert_near(dew_point(c(30.0), 80).celsius(), 26.2, 0.1);
+        // Saturated air is at its dew point
+        assert_near(dew_point(c(0.0), 100).celsius(), 0.0, 1e-9);
+        assert_near(dew_point(c(35.0), 100).celsius(), 35.0, 1e-9);
+        // 0% would be ln(0), it's clamped to 1%
+        assert!(dew_point(c(20.0), 0).celsius().is_finite());
+    }
+
+    #[test]
+    fn heat_index_matches_nws_chart() {
+        assert_near(heat_index(f(90.0), 50).fahrenheit(), 95.0, 0.5);
+        assert_near(heat_index(f(100.0), 40).fahrenheit(), 109.0, 0.5);
+        assert_near(heat_index(f(96.0), 65).fahrenheit(), 121.0, 0.5);
+        // Humid adjustment (RH over 85% between 80 and 87°F)
+        assert_near(heat_index(f(86.0), 90).fahrenheit(), 105.0, 0.5);
+    }
+
+    #[test]
+    fn heat_index_boundaries() {
+        // Below 80°F the simple formula is used as is
+        assert_near(heat_index(f(70.0), 50).fahrenheit(), 69.05, 1e-9);
+        // Right at 80°F the regression takes over
+        assert_near(heat_index(f(80.0), 40).fahrenheit(), 80.0, 0.5);
+        // Dry adjustment (RH under 13%) only lowers the value
+        let dry = heat_index(f(100.0), 10).fahrenheit();
+        assert!(dry < heat_index(f(100.0), 13).fahrenheit());
+        assert_near(dry, 94.1, 0.1);
+    }
+
+    #[test]
+    fn wind_chill_matches_nws_chart() {
+        let chill = |t, v| wind_chill(f(t), mph(v)).unwrap().fahrenheit();
+        assert_near(chill(40.0, 5.0), 36.0, 0.5);
+        assert_near(chill(30.0, 10.0), 21.0, 0.5);
+        assert_near(chill(0.0, 15.0), -19.0, 0.5);
+        assert_near(chill(-10.0, 20.0), -35.0, 0.5);
+        assert_near(chill(5.0, 60.0), -26.0, 0.5);
+    }
+
+    #[test]
+    fn wind_chill_boundaries() {
+        // Defined at exactly 50°F and exactly 3 mph
+        assert!(wind_chill(f(50.0), mph(3.0)).is_some());
+        assert!(wind_chill(f(50.1), mph(10.0)).is_none());
+        assert!(wind_chill(f(30.0), mph(2.9)).is_none());
+        assert!(wind_chill(f(30.0), mph(0.0)).is_none());
+    }
+
+    #[test]
+    fn feels_like_picks_the_right_index() {
+        assert_near(feels_like(f(0.0), 50, mph(15.0)).fahrenheit(), -19.0, 0.5);
+        assert_near(feels_like(f(90.0), 50, mph(5.0)).fahrenheit(), 95.0, 0.5);
+        assert_near(feels_like(f(65.0), 50, mph(5.0)).fahrenheit(), 65.0, 1e-9);
+    }
+
+    #[test]
+    fn apparent_temperature_reference_values() {
+        let mps = Speed::from_meters_per_sec;
+        assert_near(
+            apparent_temperature(c(25.0), 50, mps(2.0)).celsius(),
+            24.8,
+            0.05,
+        );
+        // Humid and still feels hotter than it is
+        assert_near(
+            apparent_temperature(c(30.0), 70, mps(0.0)).celsius(),
+            35.8,
+            0.05,
+        );
+        // Cold and windy feels colder
+        assert_near(
+            apparent_temperature(c(0.0), 50, mps(10.0)).celsius(),
+            -10.0,
+            0.05,
+        );
+        // More wind never makes it feel warmer
+        let calm = apparent_temperature(c(15.0), 60, mps(0.0)).celsius();
+        let breezy = apparent_temperature(c(15.0), 60, mps(5.0)).celsius();
+        assert_near(calm - breezy, 3.5, 1e-9);
+    }
+
+    #[test]
+    fn absolute_humidity_reference_values() {
+        // Saturated air holds about 17.3 g/m³ at 20°C and 30.4 at 30°C
+        assert_near(absolute_humidity(c(20.0), 100), 17.3, 0.05);
+        assert_near(absolute_humidity(c(30.0), 100), 30.4, 0.05);
+        assert_near(absolute_humidity(c(-10.0), 100), 2.36, 0.01);
+        // Linear in relative humidity
+        assert_near(
+            absolute_humidity(c(20.0), 50) * 2.0,
+            absolute_humidity(c(20.0), 100),
+            1e-9,
+        );
+        assert_eq!(absolute_humidity(c(20.0), 0), 0.0);
+    }
+
+    #[test]
+    fn beaufort_thresholds_start_the_next_force() {
+        let force = |mps| beaufort(Speed::from_meters_per_sec(mps)).force;
+        assert_eq!(force(0.0), 0);
+        assert_eq!(force(0.49), 0);
+        // Exactly on a limit is the next force up
+        assert_eq!(force(0.5), 1);
+        assert_eq!(force(3.4), 3);
+        assert_eq!(force(17.2), 8);
+        assert_eq!(force(32.69), 11);
+        assert_eq!(force(32.7), 12);
+        assert_eq!(force(80.0), 12);
+        assert_eq!(
+            beaufort(Speed::from_meters_per_sec(10.0)).description,
+            "Fresh breeze"
+        );
+        assert_eq!(
+            beaufort(Speed::from_meters_per_sec(40.0)).description,
+            "Hurricane force"
+        );
+    }
+
+    #[test]
+    fn compass_wraps_around_north() {
+        assert_eq!(compass(0.0), "N");
+        assert_eq!(compass(360.0), "N");
+        assert_eq!(compass(720.0), "N");
+        assert_eq!(compass(354.0), "N");
+        assert_eq!(compass(-90.0), "W");
+        assert_eq!(compass(-11.0), "N");
+    }
+
+    #[test]
+    fn compass_boundaries_round_up() {
+        assert_eq!(compass(11.24), "N");
+        assert_eq!(compass(11.25), "NNE");
+        assert_eq!(compass(348.74), "NNW");
+        assert_eq!(compass(348.75), "N");
+        assert_eq!(compass(90.0), "E");
+        assert_eq!(compass(202.5), "SSW");
+        assert_eq!(compass(315.0), "NW");
+    }
+}
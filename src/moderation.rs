@@ -0,0 +1,347 @@
+use crate::storage::JsonStore;
+use crate::Context;
+use chrono::Utc;
+use poise::serenity_prelude as serenity;
+use regex::Regex;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serenity::async_trait;
+
+// How many blocked replies we keep for admins to look at
+const LOG_SIZE: usize = 200;
+
+// Used when MODERATION_PATTERNS_FILE isn't set. One regex per line in the file,
+// blank lines and lines starting with # are skipped.
+const DEFAULT_PATTERNS: &[&str] = &[
+    r"(?i)\b(fuck|shit|cunt|bitch|bastard|asshole)\w*",
+    r"(?i)\b(kill|hurt) (yourself|urself)\b",
+    // Invite links and other server advertising
+    r"(?i)discord(app)?\.(gg|com/invite)/",
+];
+
+// Zero-width space, breaks a mention without changing how the text looks
+const ZWSP: char = '\u{200B}';
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Allowed,
+    Blocked(String),
+}
+
+// A check generated text has to pass before it's posted
+#[async_trait]
+pub trait Moderator: Send + Sync {
+    async fn check(&self, text: &str) -> Verdict;
+}
+
+// Local regex filter, always on and cheap enough to run on every streamed edit
+#[derive(Clone)]
+pub struct KeywordFilter {
+    patterns: Vec<Regex>,
+}
+
+impl KeywordFilter {
+    pub fn from_env() -> Result<KeywordFilter, String> {
+        let lines: Vec<String> = match std::env::var("MODERATION_PATTERNS_FILE") {
+            Ok(path) => std::fs::read_to_string(&path)
+                .map_err(|e| format!("could not read {}: {}", path, e))?
+                .lines()
+                .map(|l| l.trim().to_string())
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .collect(),
+            Err(_) => DEFAULT_PATTERNS.iter().map(|p| p.to_string()).collect(),
+        };
+        let patterns = lines
+            .iter()
+            .map(|p| Regex::new(p).map_err(|e| format!("bad moderation pattern '{}': {}", p, e)))
+            .collect::<Result<_, _>>()?;
+        Ok(KeywordFilter { patterns })
+    }
+
+    pub fn check_now(&self, text: &str) -> Verdict {
+        match self.patterns.iter().find(|p| p.is_match(text)) {
+            Some(pattern) => Verdict::Blocked(format!("matched /{}/", pattern.as_str())),
+            None => Verdict::Allowed,
+        }
+    }
+}
+
+#[async_trait]
+impl Moderator for KeywordFilter {
+    async fn check(&self, text: &str) -> Verdict {
+        self.check_now(text)
+    }
+}
+
+#[derive(Deserialize)]
+struct ModerationResponse {
+    results: Vec<ModerationResult>,
+}
+
+#[derive(Deserialize)]
+struct ModerationResult {
+    flagged: bool,
+    #[serde(default)]
+    categories: serde_json::Map<String, serde_json::Value>,
+}
+
+// OpenAI-style /moderations endpoint, turned on with MODERATION_API=true
+pub struct ApiModerator {
+    client: Client,
+    url: String,
+    api_key: String,
+}
+
+impl ApiModerator {
+    // MODERATION_URL (default OpenAI) and MODERATION_API_KEY or OPENAI_API_KEY
+    pub fn from_env() -> Result<ApiModerator, String> {
+        let api_key = std::env::var("MODERATION_API_KEY")
+            .or_else(|_| std::env::var("OPENAI_API_KEY"))
+            .map_err(|_| "MODERATION_API is on but no MODERATION_API_KEY is set".to_string())?;
+        Ok(ApiModerator {
+            client: Client::new(),
+            url: std::env::var("MODERATION_URL")
+                .unwrap_or_else(|_| "https://api.openai.com/v1/moderations".to_string()),
+            api_key,
+        })
+    }
+}
+
+#[async_trait]
+impl Moderator for ApiModerator {
+    async fn check(&self, text: &str) -> Verdict {
+        let response = self
+            .client
+            .post(&self.url)
+            .bearer_auth(&self.api_key)
+            .json(&json!({ "input": text }))
+            .send()
+            .await;
+        let body: ModerationResponse = match response {
+            Ok(r) => match r.error_for_status() {
+                Ok(r) => match r.json().await {
+                    Ok(body) => body,
+                    Err(e) => return unavailable(e),
+                },
+                Err(e) => return unavailable(e),
+            },
+            Err(e) => return unavailable(e),
+        };
+
+        match body.results.into_iter().find(|r| r.flagged) {
+            Some(result) => {
+                let categories: Vec<String> = result
+                    .categories
+                    .into_iter()
+                    .filter(|(_, flagged)| flagged.as_bool() == Some(true))
+                    .map(|(name, _)| name)
+                    .collect();
+                Verdict::Blocked(format!("moderation API: {}", categories.join(", ")))
+            }
+            None => Verdict::Allowed,
+        }
+    }
+}
+
+// If the API can't be reached we'd rather hold a reply back than post it unchecked
+fn unavailable(e: reqwest::Error) -> Verdict {
+    println!("Error: moderation API: {}", e);
+    Verdict::Blocked("moderation API unavailable".to_string())
+}
+
+// A generated reply that didn't make it to the channel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockedOutput {
+    pub at: i64,
+    pub guild: Option<u64>,
+    pub channel: u64,
+    pub user: u64,
+    pub command: String,
+    pub reason: String,
+    pub text: String,
+}
+
+// Every stage generated text goes through, plus the log of what was blocked
+pub struct Moderation {
+    keywords: KeywordFilter,
+    stages: Vec<Box<dyn Moderator>>,
+    log: JsonStore<Vec<BlockedOutput>>,
+}
+
+impl Moderation {
+    pub fn from_env() -> Result<Moderation, String> {
+        let api_enabled = std::env::var("MODERATION_API")
+            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+        let keywords = KeywordFilter::from_env()?;
+        let mut stages: Vec<Box<dyn Moderator>> = vec![Box::new(keywords.clone())];
+        if api_enabled {
+            stages.push(Box::new(ApiModerator::from_env()?));
+        }
+        Ok(Moderation {
+            keywords,
+            stages,
+            log: JsonStore::open("moderation"),
+        })
+    }
+
+    // Local filter only, for partial text while a reply is streaming
+    pub fn quick_check(&self, text: &str) -> Verdict {
+        self.keywords.check_now(text)
+    }
+
+    // Every configured stage, for the final text
+    pub async fn check(&self, text: &str) -> Verdict {
+        for stage in &self.stages {
+            if let Verdict::Blocked(reason) = stage.check(text).await {
+                return Verdict::Blocked(reason);
+            }
+        }
+        Verdict::Allowed
+    }
+
+    pub async fn record(&self, ctx: Context<'_>, reason: &str, text: &str) {
+        println!(
+            "Blocked reply to /{}: {}",
+            ctx.command().qualified_name,
+            reason
+        );
+        let entry = BlockedOutput {
+            at: Utc::now().timestamp(),
+            guild: ctx.guild_id().map(|g| g.get()),
+            channel: ctx.channel_id().get(),
+            user: ctx.author().id.get(),
+            command: ctx.command().qualified_name.clone(),
+            reason: reason.to_string(),
+            text: text.to_string(),
+        };
+        self.log
+            .update(|log| {
+                log.push(entry);
+                if log.len() > LOG_SIZE {
+                    let extra = log.len() - LOG_SIZE;
+                    log.drain(..extra);
+                }
+            })
+            .await;
+    }
+
+    // Most recent blocked replies in a guild, newest first
+    pub async fn blocked(&self, guild: u64, limit: usize) -> Vec<BlockedOutput> {
+        self.log
+            .read(|log| {
+                log.iter()
+                    .rev()
+                    .filter(|b| b.guild == Some(guild))
+                    .take(limit)
+                    .cloned()
+                    .collect()
+            })
+            .await
+    }
+}
+
+// Run the full check on a finished reply, logging it if it's blocked.
+// Returns the text with mentions defused, or None if it shouldn't be posted.
+pub async fn screen(ctx: Context<'_>, text: &str) -> Option<String> {
+    let moderation = &ctx.data().moderation;
+    match moderation.check(text).await {
+        Verdict::Allowed => Some(strip_mentions(text)),
+        Verdict::Blocked(reason) => {
+            moderation.record(ctx, &reason, text).await;
+            None
+        }
+    }
+}
+
+// Defuse @everyone/@here and user, role and channel mentions so generated
+// text can't ping anyone, even if allowed mentions are misconfigured
+pub fn strip_mentions(text: &str) -> String {
+    let text = text
+        .replace("@everyone", &format!("@{}everyone", ZWSP))
+        .replace("@here", &format!("@{}here", ZWSP));
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        out.push(c);
+        if c == '<' && matches!(chars.peek(), Some('@') | Some('#')) {
+            out.push(ZWSP);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults() -> KeywordFilter {
+        KeywordFilter {
+            patterns: DEFAULT_PATTERNS
+                .iter()
+                .map(|p| Regex::new(p).unwrap())
+                .collect(),
+        }
+    }
+
+    fn blocked(filter: &KeywordFilter, text: &str) -> bool {
+        matches!(filter.check_now(text), Verdict::Blocked(_))
+    }
+
+    #[test]
+    fn mass_mentions_are_defused() {
+        let out = strip_mentions("@everyone and @here, look");
+        assert_eq!(out, "@\u{200B}everyone and @\u{200B}here, look");
+        assert!(!out.contains("@everyone") && !out.contains("@here"));
+    }
+
+    #[test]
+    fn user_role_and_channel_mentions_are_defused() {
+        assert_eq!(strip_mentions("hi <@123>"), "hi <\u{200B}@123>");
+        assert_eq!(strip_mentions("<@!123>"), "<\u{200B}@!123>");
+        assert_eq!(strip_mentions("ping <@&456>"), "ping <\u{200B}@&456>");
+        assert_eq!(strip_mentions("see <#789>"), "see <\u{200B}#789>");
+    }
+
+    #[test]
+    fn ordinary_text_is_untouched() {
+        for text in ["It's < 5°C", "email me@example.com", "<b>bold</b>", ""] {
+            assert_eq!(strip_mentions(text), text);
+        }
+    }
+
+    #[test]
+    fn keywords_match_any_case() {
+        let filter = defaults();
+        assert!(blocked(&filter, "what the SHIT"));
+        assert!(blocked(&filter, "Fucking rain again"));
+        assert!(blocked(&filter, "go KILL YOURSELF"));
+        assert!(blocked(&filter, "join discord.gg/abc"));
+        assert!(blocked(&filter, "https://discordapp.com/invite/abc"));
+        assert!(!blocked(&filter, "Sunny with a light breeze"));
+    }
+
+    #[test]
+    fn keywords_respect_word_boundaries() {
+        let filter = defaults();
+        // Starting a word is enough, being inside one isn't
+        assert!(blocked(&filter, "shitty weather"));
+        assert!(!blocked(&filter, "Scunthorpe is cloudy"));
+        assert!(blocked(&filter, "a bastardised forecast"));
+        assert!(!blocked(&filter, "the skill yourself is fine"));
+        assert!(!blocked(&filter, "kill yourselves"));
+    }
+
+    #[test]
+    fn blocked_verdicts_name_the_pattern() {
+        let filter = KeywordFilter {
+            patterns: vec![Regex::new(r"(?i)\bhail\b").unwrap()],
+        };
+        assert_eq!(
+            filter.check_now("HAIL tonight"),
+            Verdict::Blocked(r"matched /(?i)\bhail\b/".to_string())
+        );
+        assert_eq!(filter.check_now("hailstones"), Verdict::Allowed);
+    }
+}
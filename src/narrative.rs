@@ -0,0 +1,330 @@
+use crate::chatbot::Llm;
+use crate::meteo;
+use crate::moderation;
+use crate::units::{Temperature, UnitFormat};
+use crate::usage;
+use crate::weather::{ForecastEntry, ForecastResponse, Weather, WeatherResponse};
+use crate::Context;
+use chrono::{TimeZone, Utc};
+
+// Turns weather data into a short human summary. The rule-based templates
+// always work (offline, no API keys); set NARRATIVE_USE_LLM=true to have the
+// language model reword them.
+
+fn temperature_word(feels_like: Temperature) -> &'static str {
+    match feels_like.fahrenheit() {
+        f if f < 10.0 => "Frigid",
+        f if f < 32.0 => "Freezing",
+        f if f < 45.0 => "Cold",
+        f if f < 58.0 => "Chilly",
+        f if f < 68.0 => "Mild",
+        f if f < 78.0 => "Pleasant",
+        f if f < 88.0 => "Warm",
+        f if f < 98.0 => "Hot",
+        _ => "Scorching",
+    }
+}
+
+fn humidity_word(temp: Temperature, humidity: u32) -> Option<&'static str> {
+    match meteo::dew_point(temp, humidity).fahrenheit() {
+        d if d < 35.0 => Some("dry"),
+        d if d >= 70.0 => Some("oppressively humid"),
+        d if d >= 65.0 => Some("muggy"),
+        d if d >= 60.0 => Some("humid"),
+        _ => None,
+    }
+}
+
+fn wind_phrase(force: u8) -> Option<&'static str> {
+    match force {
+        0..=2 => None,
+        3 => Some("a light breeze"),
+        4..=5 => Some("a steady breeze"),
+        6..=7 => Some("strong winds"),
+        _ => Some("damaging winds"),
+    }
+}
+
+fn sky_phrase(clouds: u32) -> &'static str {
+    match clouds {
+        0..=10 => "clear skies",
+        11..=40 => "mostly sunny skies",
+        41..=70 => "partly cloudy skies",
+        71..=90 => "mostly cloudy skies",
+        _ => "overcast skies",
+    }
+}
+
+// Precipitation type from an OpenWeather condition code, if it is one
+fn precipitation_word(condition: &Weather) -> Option<&'static str> {
+    match condition.id {
+        200..=299 => Some("storms"),
+        300..=399 => Some("drizzle"),
+        500..=599 => Some("rain"),
+        600..=699 => Some("snow"),
+        700..=799 => Some("fog"),
+        _ => None,
+    }
+}
+
+// "Warm and humid with a light breeze under partly cloudy skies."
+pub fn current(weather: &WeatherResponse, fmt: &UnitFormat) -> String {
+    let main = &weather.main;
+    let mut summary = temperature_word(main.feels_like).to_string();
+    if let Some(humidity) = humidity_word(main.temp, main.humidity) {
+        summary.push_str(&format!(" and {}", humidity));
+    }
+    if let Some(wind) = wind_phrase(meteo::beaufort(weather.wind.speed).force) {
+        summary.push_str(&format!(" with {}", wind));
+    }
+    match weather.condition().and_then(precipitation_word) {
+        Some(precipitation) => summary.push_str(&format!(", {} right now.", precipitation)),
+        None => summary.push_str(&format!(" under {}.", sky_phrase(weather.clouds.all))),
+    }
+
+    let (temp, feels_like) = (main.temp.format(fmt), main.feels_like.format(fmt));
+    if temp == feels_like {
+        summary.push_str(&format!(" It's {}.", temp));
+    } else {
+        summary.push_str(&format!(" It's {}, feeling like {}.", temp, feels_like));
+    }
+    summary
+}
+
+// Local hour of a forecast entry, e.g. "4pm"
+fn local_hour(entry: &ForecastEntry, timezone: i32) -> String {
+    Utc.timestamp_opt(entry.dt + timezone as i64, 0)
+        .single()
+        .map(|t| t.format("%-I%P").to_string())
+        .unwrap_or_default()
+}
+
+// "Warm and humid, storms likely after 4pm. High 88°F, low 71°F."
+pub fn forecast(forecast: &ForecastResponse, fmt: &UnitFormat) -> String {
+    let entries = forecast.hourly(24);
+    let first = match entries.first() {
+        Some(first) => first,
+        None => return "No forecast available.".to_string(),
+    };
+
+    let high = entries
+        .iter()
+        .map(|e| e.main.temp_max)
+        .fold(first.main.temp_max, Temperature::max);
+    let low = entries
+        .iter()
+        .map(|e| e.main.temp_min)
+        .fold(first.main.temp_min, Temperature::min);
+
+    let mut summary = temperature_word(high).to_string();
+    if let Some(humidity) = humidity_word(first.main.temp, first.main.humidity) {
+        summary.push_str(&format!(" and {}", humidity));
+    }
+
+    // First time precipitation becomes likely, and what kind
+    let wet = entries.iter().find_map(|e| {
+        let kind = e.weather.first().and_then(precipitation_word)?;
+        (e.pop >= 0.3 && kind != "fog").then_some((e, kind))
+    });
+    match wet {
+        Some((entry, kind)) => {
+            let likelihood = if entry.pop >= 0.7 {
+                "likely"
+            } else {
+                "possible"
+            };
+            if entry.dt == first.dt {
+                summary.push_str(&format!(", {} {} now", kind, likelihood));
+            } else {
+                summary.push_str(&format!(
+                    ", {} {} after {}",
+                    kind,
+                    likelihood,
+                    local_hour(entry, forecast.city.timezone)
+                ));
+            }
+        }
+        None => summary.push_str(", staying dry"),
+    }
+
+    let windiest = entries
+        .iter()
+        .map(|e| meteo::beaufort(e.wind.speed).force)
+        .max()
+        .unwrap_or(0);
+    if let Some(wind) = wind_phrase(windiest) {
+        summary.push_str(&format!(" with {} at times", wind));
+    }
+
+    summary.push_str(&format!(
+        ". High {}, low {}.",
+        high.format(fmt),
+        low.format(fmt)
+    ));
+    summary
+}
+
+// Whether NARRATIVE_USE_LLM is turned on
+pub fn llm_enabled() -> bool {
+    std::env::var("NARRATIVE_USE_LLM")
+        .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+// Reword a rule-based summary with the LLM when enabled, charged to whoever
+// ran the command and screened like any other generated text. Keeps the
+// template text if it's disabled, over budget, fails or gets blocked.
+pub async fn polish(ctx: Context<'_>, summary: String) -> String {
+    if !llm_enabled() {
+        return summary;
+    }
+    let llm = match usage::metered(ctx).await {
+        Ok(llm) => llm,
+        Err(_) => return summary,
+    };
+    let text = reword(&llm, summary.clone()).await;
+    moderation::screen(ctx, &text).await.unwrap_or(summary)
+}
+
+async fn reword(llm: &dyn Llm, summary: String) -> String {
+    let prompt = format!(
+        "Rewrite this weather summary as one or two friendly sentences. \
+         Keep every number exactly as given and don't add any new facts:\n{}",
+        summary
+    );
+    match llm.ask(&prompt, 80).await {
+        Ok(text) => text,
+        Err(e) => {
+            println!("Error: {}", e);
+            summary
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chatbot::testing::FakeLlm;
+    use crate::units::{Speed, UnitSystem};
+    use crate::weather::{ForecastCity, Main, Wind};
+
+    fn fmt() -> UnitFormat {
+        UnitFormat::new(UnitSystem::Imperial, None)
+    }
+
+    fn condition(id: u32) -> Weather {
+        Weather {
+            id,
+            ..Default::default()
+        }
+    }
+
+    fn weather(
+        temp: f64,
+        feels_like: f64,
+        humidity: u32,
+        wind_mph: f64,
+        id: u32,
+    ) -> WeatherResponse {
+        WeatherResponse {
+            main: Main {
+                temp: Temperature::from_fahrenheit(temp),
+                feels_like: Temperature::from_fahrenheit(feels_like),
+                humidity,
+                ..Default::default()
+            },
+            wind: Wind {
+                speed: Speed::from_mph(wind_mph),
+                ..Default::default()
+            },
+            weather: vec![condition(id)],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn current_describes_calm_clear_weather() {
+        let text = current(&weather(72.0, 72.0, 40, 2.0, 800), &fmt());
+        assert_eq!(text, "Pleasant under clear skies. It's 72.0°F.");
+    }
+
+    #[test]
+    fn current_mentions_humidity_wind_and_rain() {
+        let text = current(&weather(88.0, 97.0, 80, 20.0, 501), &fmt());
+        assert_eq!(
+            text,
+            "Hot and oppressively humid with a steady breeze, rain right now. \
+             It's 88.0°F, feeling like 97.0°F."
+        );
+    }
+
+    fn entry(dt: i64, high: f64, low: f64, pop: f64, id: u32) -> ForecastEntry {
+        ForecastEntry {
+            dt,
+            main: Main {
+                temp: Temperature::from_fahrenheit(high),
+                temp_max: Temperature::from_fahrenheit(high),
+                temp_min: Temperature::from_fahrenheit(low),
+                humidity: 50,
+                ..Default::default()
+            },
+            weather: vec![condition(id)],
+            pop,
+            ..Default::default()
+        }
+    }
+
+    fn forecast_of(list: Vec<ForecastEntry>) -> ForecastResponse {
+        ForecastResponse {
+            list,
+            city: ForecastCity::default(),
+        }
+    }
+
+    #[test]
+    fn forecast_finds_the_first_likely_rain() {
+        // 2024-06-01 12:00 UTC, then every 3 hours
+        let start = 1717243200;
+        let list = vec![
+            entry(start, 70.0, 62.0, 0.0, 800),
+            entry(start + 3 * 3600, 75.0, 64.0, 0.4, 500),
+            entry(start + 6 * 3600, 80.0, 66.0, 0.9, 500),
+        ];
+        assert_eq!(
+            forecast(&forecast_of(list), &fmt()),
+            "Warm, rain possible after 3pm. High 80.0°F, low 62.0°F."
+        );
+    }
+
+    #[test]
+    fn forecast_stays_dry() {
+        let list = vec![
+            entry(0, 60.0, 50.0, 0.1, 500),
+            entry(10800, 65.0, 55.0, 0.0, 800),
+        ];
+        assert_eq!(
+            forecast(&forecast_of(list), &fmt()),
+            "Mild, staying dry. High 65.0°F, low 50.0°F."
+        );
+        assert_eq!(
+            forecast(&forecast_of(Vec::new()), &fmt()),
+            "No forecast available."
+        );
+    }
+
+    #[tokio::test]
+    async fn reword_uses_the_llm_reply() {
+        let llm = FakeLlm::replying("A lovely warm day, 80°F.");
+        let text = reword(&llm, "Warm. High 80°F.".to_string()).await;
+        assert_eq!(text, "A lovely warm day, 80°F.");
+        // The template goes along so the numbers can be kept
+        let sent = &llm.requests.lock().unwrap()[0];
+        assert!(sent.messages[0].content.ends_with("Warm. High 80°F."));
+    }
+
+    #[tokio::test]
+    async fn reword_keeps_the_template_when_the_llm_fails() {
+        let text = reword(&FakeLlm::failing(), "Warm. High 80°F.".to_string()).await;
+        assert_eq!(text, "Warm. High 80°F.");
+    }
+}
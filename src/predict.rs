@@ -0,0 +1,738 @@
+use crate::leaderboard::{Award, Leaderboard};
+use crate::storage::JsonStore;
+use crate::units::{Temperature, UnitFormat};
+use crate::weather::{get_weather_at_cached, get_weather_cached, Coord, WeatherResponse};
+use chrono::{Datelike, Duration, NaiveDate, Utc};
+use poise::serenity_prelude as serenity;
+use serde::{Deserialize, Serialize};
+use serenity::{ChannelId, CreateEmbed, CreateMessage, Http};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+
+// How often we look at the weather in each league's city. The observed high and
+// low are the extremes of these samples, so they can miss a short spike.
+const SAMPLE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+// A day needs this many samples (about half of it) before we'll score it
+const MIN_SAMPLES: u32 = 48;
+
+// Points for a spot-on high or low, minus this many per °C off
+const TEMPERATURE_POINTS: f64 = 40.0;
+const POINTS_PER_DEGREE: f64 = 4.0;
+// For calling rain or snow right
+const PRECIPITATION_POINTS: u64 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Achievement {
+    FirstForecast,
+    Bullseye,
+    DailyWinner,
+    WeekStreak,
+    MonthStreak,
+}
+
+impl Achievement {
+    pub fn name(self) -> &'static str {
+        match self {
+            Achievement::FirstForecast => "🌱 First forecast",
+            Achievement::Bullseye => "🎯 Bullseye",
+            Achievement::DailyWinner => "🏆 Forecaster of the day",
+            Achievement::WeekStreak => "🔥 Week streak",
+            Achievement::MonthStreak => "☄️ Month streak",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Achievement::FirstForecast => "Had a prediction scored",
+            Achievement::Bullseye => "Got the high and low both within 1 °C",
+            Achievement::DailyWinner => "Top score of the day",
+            Achievement::WeekStreak => "Predicted 7 days in a row",
+            Achievement::MonthStreak => "Predicted 30 days in a row",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Prediction {
+    pub name: String,
+    pub high: Temperature,
+    pub low: Temperature,
+    pub precipitation: bool,
+    pub at: i64,
+}
+
+// What the day actually did, built up from samples as it goes
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Observed {
+    pub high: Option<Temperature>,
+    pub low: Option<Temperature>,
+    pub precipitation: bool,
+    pub samples: u32,
+}
+
+impl Observed {
+    fn add(&mut self, weather: &WeatherResponse) {
+        let temp = weather.main.temp;
+        self.high = Some(self.high.map_or(temp, |t| t.max(temp)));
+        self.low = Some(self.low.map_or(temp, |t| t.min(temp)));
+        let wet = weather.rain_recent().is_some()
+            || weather.snow_recent().is_some()
+            || weather
+                .condition()
+                .is_some_and(|c| matches!(c.id, 200..=622));
+        self.precipitation |= wet;
+        self.samples += 1;
+    }
+}
+
+// Everyone's predictions for one day in one guild
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Round {
+    pub city: String,
+    // Where the city resolved when the round opened, so the samples come from
+    // there and not whichever same-named place a lookup finds. None for rounds
+    // opened before it was kept.
+    #[serde(default)]
+    pub coord: Option<Coord>,
+    // Where the results get posted
+    pub channel: u64,
+    pub predictions: HashMap<u64, Prediction>,
+    #[serde(default)]
+    pub observed: Observed,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Player {
+    // Days in a row with a prediction in, scored or not
+    pub streak: u32,
+    pub best_streak: u32,
+    pub last_day: Option<NaiveDate>,
+    pub achievements: BTreeSet<Achievement>,
+}
+
+impl Player {
+    // Count a day they predicted towards their streak
+    fn predicted(&mut self, date: NaiveDate) {
+        self.streak = match self.last_day {
+            Some(last) if last + Duration::days(1) == date => self.streak + 1,
+            _ => 1,
+        };
+        self.best_streak = self.best_streak.max(self.streak);
+        self.last_day = Some(date);
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuildLeague {
+    // Set by an admin, otherwise the guild's default city
+    pub city: Option<String>,
+    // Set by an admin, otherwise wherever people predict
+    pub channel: Option<u64>,
+    // Keyed by the city's local date being predicted
+    pub rounds: BTreeMap<NaiveDate, Round>,
+    pub players: HashMap<u64, Player>,
+    // Month the current leaderboard season covers, e.g. "2026-10"
+    pub month: Option<String>,
+}
+
+// One player's result for a scored day
+pub struct Score {
+    pub user: u64,
+    pub name: String,
+    pub prediction: Prediction,
+    pub points: u64,
+    pub won: bool,
+    pub streak: u32,
+    pub unlocked: Vec<Achievement>,
+}
+
+// A finished day, ready to post
+pub struct Settled {
+    pub date: NaiveDate,
+    pub city: String,
+    pub channel: u64,
+    pub observed: Observed,
+    // Best first. Empty if there weren't enough samples to score the day.
+    pub scores: Vec<Score>,
+}
+
+fn temperature_points(predicted: Temperature, observed: Temperature) -> u64 {
+    (TEMPERATURE_POINTS - (predicted.celsius() - observed.celsius()).abs() * POINTS_PER_DEGREE)
+        .round()
+        .max(0.0) as u64
+}
+
+fn score(prediction: &Prediction, high: Temperature, low: Temperature, wet: bool) -> u64 {
+    let mut points =
+        temperature_points(prediction.high, high) + temperature_points(prediction.low, low);
+    if prediction.precipitation == wet {
+        points += PRECIPITATION_POINTS;
+    }
+    points
+}
+
+// Today's date where the weather is, per the API's UTC offset
+pub fn local_date(weather: &WeatherResponse) -> Option<NaiveDate> {
+    weather
+        .local_time(Utc::now().timestamp())
+        .map(|t| t.date_naive())
+}
+
+pub struct League {
+    store: JsonStore<HashMap<u64, GuildLeague>>,
+    // Monthly standings, one leaderboard season per month
+    pub leaderboard: Leaderboard,
+}
+
+impl League {
+    pub fn new() -> League {
+        League {
+            store: JsonStore::open("predictions"),
+            leaderboard: Leaderboard::open("predict_standings"),
+        }
+    }
+
+    // The admin-chosen city, if any
+    pub async fn city(&self, guild: u64) -> Option<String> {
+        self.store
+            .read(|leagues| leagues.get(&guild).and_then(|l| l.city.clone()))
+            .await
+    }
+
+    pub async fn configure(&self, guild: u64, city: Option<String>, channel: Option<u64>) {
+        self.store
+            .update(|leagues| {
+                let league = leagues.entry(guild).or_default();
+                if city.is_some() {
+                    league.city = city;
+                }
+                if channel.is_some() {
+                    league.channel = channel;
+                }
+            })
+            .await;
+    }
+
+    // Record (or replace) a prediction for `date`. Returns the city the round is
+    // for, which stays what it was when the first prediction came in.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn predict(
+        &self,
+        guild: u64,
+        date: NaiveDate,
+        city: &str,
+        coord: Coord,
+        channel: u64,
+        user: u64,
+        prediction: Prediction,
+    ) -> String {
+        self.store
+            .update(|leagues| {
+                let league = leagues.entry(guild).or_default();
+                let channel = league.channel.unwrap_or(channel);
+                let round = league.rounds.entry(date).or_insert_with(|| Round {
+                    city: city.to_string(),
+                    coord: Some(coord),
+                    channel,
+                    predictions: HashMap::new(),
+                    observed: Observed::default(),
+                });
+                round.predictions.insert(user, prediction);
+                round.city.clone()
+            })
+            .await
+    }
+
+    pub async fn player(&self, guild: u64, user: u64) -> Player {
+        self.store
+            .read(|leagues| {
+                leagues
+                    .get(&guild)
+                    .and_then(|l| l.players.get(&user).cloned())
+            })
+            .await
+            .unwrap_or_default()
+    }
+
+    // (guild, date, city, coord) for every day still waiting to be scored
+    async fn open_rounds(&self) -> Vec<(u64, NaiveDate, String, Option<Coord>)> {
+        self.store
+            .read(|leagues| {
+                leagues
+                    .iter()
+                    .flat_map(|(guild, league)| {
+                        league.rounds.iter().map(move |(date, round)| {
+                            (*guild, *date, round.city.clone(), round.coord)
+                        })
+                    })
+                    .collect()
+            })
+            .await
+    }
+
+    async fn observe(&self, guild: u64, date: NaiveDate, weather: &WeatherResponse) {
+        self.store
+            .update(|leagues| {
+                if let Some(round) = leagues
+                    .get_mut(&guild)
+                    .and_then(|l| l.rounds.get_mut(&date))
+                {
+                    round.observed.add(weather);
+                }
+            })
+            .await;
+    }
+
+    // Score a finished day, update streaks and achievements and drop the round.
+    // Also returns whether the day starts a new month of standings.
+    async fn settle(&self, guild: u64, date: NaiveDate) -> Option<(Settled, bool)> {
+        self.store
+            .update(|leagues| {
+                let league = leagues.get_mut(&guild)?;
+                let round = league.rounds.remove(&date)?;
+                let mut settled = Settled {
+                    date,
+                    city: round.city,
+                    channel: round.channel,
+                    observed: round.observed,
+                    scores: Vec::new(),
+                };
+                let observed = &settled.observed;
+                let (high, low) = match (observed.high, observed.low) {
+                    (Some(high), Some(low)) if observed.samples >= MIN_SAMPLES => (high, low),
+                    // The bot was down for too much of the day to say what happened.
+                    // Not their fault, so the day still keeps their streaks going.
+                    _ => {
+                        for user in round.predictions.keys() {
+                            league.players.entry(*user).or_default().predicted(date);
+                        }
+                        return Some((settled, false));
+                    }
+                };
+
+                let mut scores: Vec<(u64, Prediction, u64)> = round
+                    .predictions
+                    .into_iter()
+                    .map(|(user, p)| {
+                        let points = score(&p, high, low, observed.precipitation);
+                        (user, p, points)
+                    })
+                    .collect();
+                scores.sort_by_key(|s| std::cmp::Reverse(s.2));
+                let best = scores.first().map(|s| s.2).unwrap_or(0);
+
+                for (user, prediction, points) in scores {
+                    let player = league.players.entry(user).or_default();
+                    player.predicted(date);
+
+                    let won = points == best;
+                    let bullseye = (prediction.high.celsius() - high.celsius()).abs() <= 1.0
+                        && (prediction.low.celsius() - low.celsius()).abs() <= 1.0;
+                    let earned = [
+                        (Achievement::FirstForecast, true),
+                        (Achievement::Bullseye, bullseye),
+                        (Achievement::DailyWinner, won),
+                        (Achievement::WeekStreak, player.streak >= 7),
+                        (Achievement::MonthStreak, player.streak >= 30),
+                    ];
+                    let unlocked = earned
+                        .into_iter()
+                        .filter(|(achievement, got)| {
+                            *got && player.achievements.insert(*achievement)
+                        })
+                        .map(|(achievement, _)| achievement)
+                        .collect();
+
+                    settled.scores.push(Score {
+                        user,
+                        name: prediction.name.clone(),
+                        prediction,
+                        points,
+                        won,
+                        streak: player.streak,
+                        unlocked,
+                    });
+                }
+
+                let month = format!("{}-{:02}", date.year(), date.month());
+                let new_month = matches!(&league.month, Some(current) if *current != month);
+                league.month = Some(month);
+                Some((settled, new_month))
+            })
+            .await
+    }
+
+    // Add a scored day to the standings, starting a new season when the month turns
+    async fn award(&self, guild: u64, settled: &Settled, new_month: bool) {
+        if new_month {
+            self.leaderboard.new_season(guild).await;
+        }
+        let awards: Vec<Award> = settled
+            .scores
+            .iter()
+            .map(|score| Award {
+                user: score.user,
+                name: score.name.clone(),
+                points: score.points,
+                won: score.won,
+            })
+            .collect();
+        self.leaderboard.award(guild, &awards).await;
+    }
+}
+
+impl Default for League {
+    fn default() -> Self {
+        League::new()
+    }
+}
+
+fn results_lines(settled: &Settled, fmt: &UnitFormat) -> Vec<String> {
+    settled
+        .scores
+        .iter()
+        .map(|score| {
+            let p = &score.prediction;
+            let mut line = format!(
+                "{} {} · {} / {} {} · {} pts",
+                if score.won { "🏆" } else { "▫️" },
+                score.name,
+                p.high.format(fmt),
+                p.low.format(fmt),
+                if p.precipitation { "☔" } else { "☀️" },
+                score.points
+            );
+            if score.streak > 1 {
+                line.push_str(&format!(" · 🔥 {} days", score.streak));
+            }
+            for achievement in &score.unlocked {
+                line.push_str(&format!("\n    unlocked {}", achievement.name()));
+            }
+            line
+        })
+        .collect()
+}
+
+fn results_message(settled: &Settled, fmt: &UnitFormat) -> (String, String) {
+    let title = format!(
+        "Prediction results for {}, {}",
+        settled.city,
+        settled.date.format("%A %B %-d")
+    );
+    let observed = &settled.observed;
+    let body = match (observed.high, observed.low, settled.scores.is_empty()) {
+        (Some(high), Some(low), false) => format!(
+            "Observed high {} · low {} · {}\n\n{}",
+            high.format(fmt),
+            low.format(fmt),
+            if observed.precipitation {
+                "☔ precipitation"
+            } else {
+                "☀️ stayed dry"
+            },
+            results_lines(settled, fmt).join("\n")
+        ),
+        _ => "I couldn't keep an eye on the weather for enough of the day, so no points this time."
+            .to_string(),
+    };
+    (title, body)
+}
+
+async fn post(http: &Http, settled: &Settled) {
+    let fmt = UnitFormat::default();
+    let (title, body) = results_message(settled, &fmt);
+    let channel = ChannelId::new(settled.channel);
+    let embed = CreateEmbed::new().title(&title).description(&body);
+    if let Err(e) = channel
+        .send_message(http, CreateMessage::new().embed(embed))
+        .await
+    {
+        // Probably can't embed there, try plain text
+        println!("Error posting prediction results embed: {:?}", e);
+        let text = format!("**{}**\n{}", title, body);
+        if let Err(e) = channel
+            .send_message(http, CreateMessage::new().content(text))
+            .await
+        {
+            println!("Error posting prediction results: {:?}", e);
+        }
+    }
+}
+
+// Sample each open round's city, and score days once they're over
+async fn check(league: &League, http: &Http) {
+    let rounds = league.open_rounds().await;
+    let mut weather: HashMap<String, Option<WeatherResponse>> = HashMap::new();
+    for (guild, date, city, coord) in rounds {
+        let key = match coord {
+            Some(coord) => format!("@{:.2},{:.2}", coord.lat, coord.lon),
+            None => city.clone(),
+        };
+        if !weather.contains_key(&key) {
+            let result = match coord {
+                Some(coord) => get_weather_at_cached(coord).await,
+                None => get_weather_cached(&city).await,
+            };
+            let fetched = match result {
+                Ok(w) => Some(w),
+                Err(e) => {
+                    println!("Error fetching {} for the prediction league: {:?}", city, e);
+                    None
+                }
+            };
+            weather.insert(key.clone(), fetched);
+        }
+        let current = match &weather[&key] {
+            Some(current) => current,
+            None => continue,
+        };
+        let today = match local_date(current) {
+            Some(today) => today,
+            None => continue,
+        };
+
+        if date == today {
+            league.observe(guild, date, current).await;
+        } else if date < today {
+            if let Some((settled, new_month)) = league.settle(guild, date).await {
+                league.award(guild, &settled, new_month).await;
+                post(http, &settled).await;
+            }
+        }
+    }
+}
+
+// Keep the league ticking over. Runs for the life of the bot.
+pub async fn run(league: Arc<League>, http: Arc<Http>) {
+    loop {
+        check(&league, &http).await;
+        tokio::time::sleep(SAMPLE_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn league(name: &str) -> League {
+        League {
+            store: JsonStore::scratch(name),
+            leaderboard: Leaderboard::scratch(&format!("{}-standings", name)),
+        }
+    }
+
+    const PARIS: Coord = Coord {
+        lat: 48.85,
+        lon: 2.35,
+    };
+
+    fn guess(name: &str, high: f64, low: f64, precipitation: bool) -> Prediction {
+        Prediction {
+            name: name.to_string(),
+            high: Temperature::from_celsius(high),
+            low: Temperature::from_celsius(low),
+            precipitation,
+            at: 0,
+        }
+    }
+
+    fn prediction() -> Prediction {
+        guess("Sam", 20.0, 10.0, false)
+    }
+
+    async fn play(league: &League, date: NaiveDate, samples: u32) -> Settled {
+        play_all(league, date, vec![(3, prediction())], samples).await
+    }
+
+    // Everyone's predictions for a day that reached 21 °C, fell to 10 °C and stayed dry
+    async fn play_all(
+        league: &League,
+        date: NaiveDate,
+        predictions: Vec<(u64, Prediction)>,
+        samples: u32,
+    ) -> Settled {
+        for (user, prediction) in predictions {
+            league
+                .predict(1, date, "Paris", PARIS, 2, user, prediction)
+                .await;
+        }
+        league
+            .store
+            .update(|leagues| {
+                let round = leagues.get_mut(&1).unwrap().rounds.get_mut(&date).unwrap();
+                round.observed = Observed {
+                    high: Some(Temperature::from_celsius(21.0)),
+                    low: Some(Temperature::from_celsius(10.0)),
+                    precipitation: false,
+                    samples,
+                };
+            })
+            .await;
+        league.settle(1, date).await.unwrap().0
+    }
+
+    #[tokio::test]
+    async fn unscored_days_keep_streaks_going() {
+        let league = league("streaks");
+        let day = NaiveDate::from_ymd_opt(2026, 10, 1).unwrap();
+
+        let first = play(&league, day, MIN_SAMPLES).await;
+        assert_eq!(first.scores[0].points, 36 + 40 + 20);
+        // The bot was down most of the second day
+        let second = play(&league, day + Duration::days(1), 3).await;
+        assert!(second.scores.is_empty());
+        assert_eq!(league.player(1, 3).await.streak, 2);
+
+        let third = play(&league, day + Duration::days(2), MIN_SAMPLES).await;
+        assert_eq!(third.scores[0].streak, 3);
+        assert_eq!(
+            league.player(1, 3).await.last_day,
+            Some(day + Duration::days(2))
+        );
+    }
+
+    #[tokio::test]
+    async fn missed_days_reset_streaks() {
+        let league = league("missed");
+        let day = NaiveDate::from_ymd_opt(2026, 10, 1).unwrap();
+        play(&league, day, MIN_SAMPLES).await;
+        play(&league, day + Duration::days(1), MIN_SAMPLES).await;
+        let later = play(&league, day + Duration::days(3), MIN_SAMPLES).await;
+        assert_eq!(later.scores[0].streak, 1);
+        assert_eq!(league.player(1, 3).await.best_streak, 2);
+    }
+
+    #[test]
+    fn temperature_points_drop_four_a_degree() {
+        let points = |predicted: f64, observed: f64| {
+            temperature_points(
+                Temperature::from_celsius(predicted),
+                Temperature::from_celsius(observed),
+            )
+        };
+        assert_eq!(points(20.0, 20.0), 40);
+        assert_eq!(points(20.0, 22.5), 30);
+        assert_eq!(points(22.5, 20.0), 30);
+        // Rounded to the nearest point
+        assert_eq!(points(20.1, 20.0), 40);
+        assert_eq!(points(20.0, 30.0), 0);
+        // Never negative
+        assert_eq!(points(-5.0, 30.0), 0);
+    }
+
+    #[test]
+    fn score_adds_both_temperatures_and_precipitation() {
+        let (high, low) = (
+            Temperature::from_celsius(21.0),
+            Temperature::from_celsius(10.0),
+        );
+        assert_eq!(score(&guess("A", 21.0, 10.0, false), high, low, false), 100);
+        assert_eq!(score(&guess("A", 21.0, 10.0, true), high, low, false), 80);
+        assert_eq!(
+            score(&guess("A", 23.0, 9.0, true), high, low, true),
+            32 + 36 + 20
+        );
+        assert_eq!(score(&guess("A", 40.0, 30.0, true), high, low, false), 0);
+    }
+
+    #[tokio::test]
+    async fn achievements_unlock_once() {
+        let league = league("achievements");
+        let day = NaiveDate::from_ymd_opt(2026, 10, 1).unwrap();
+
+        // High 1 °C off and low exact is a bullseye
+        let first = play(&league, day, MIN_SAMPLES).await;
+        assert_eq!(
+            first.scores[0].unlocked,
+            vec![
+                Achievement::FirstForecast,
+                Achievement::Bullseye,
+                Achievement::DailyWinner
+            ]
+        );
+        for offset in 1..6 {
+            let settled = play(&league, day + Duration::days(offset), MIN_SAMPLES).await;
+            assert!(settled.scores[0].unlocked.is_empty());
+        }
+        let seventh = play(&league, day + Duration::days(6), MIN_SAMPLES).await;
+        assert_eq!(seventh.scores[0].unlocked, vec![Achievement::WeekStreak]);
+        assert_eq!(league.player(1, 3).await.achievements.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn near_misses_are_not_bullseyes() {
+        let league = league("near-miss");
+        let day = NaiveDate::from_ymd_opt(2026, 10, 1).unwrap();
+        let settled = play_all(
+            &league,
+            day,
+            vec![(3, guess("Sam", 21.0, 11.5, false))],
+            MIN_SAMPLES,
+        )
+        .await;
+        assert!(!settled.scores[0].unlocked.contains(&Achievement::Bullseye));
+    }
+
+    #[tokio::test]
+    async fn tied_top_scores_all_win() {
+        let league = league("tie");
+        let day = NaiveDate::from_ymd_opt(2026, 10, 1).unwrap();
+        let settled = play_all(
+            &league,
+            day,
+            vec![
+                (3, guess("Sam", 22.0, 10.0, false)),
+                (4, guess("Alex", 21.0, 11.0, false)),
+                (5, guess("Jo", 25.0, 10.0, true)),
+            ],
+            MIN_SAMPLES,
+        )
+        .await;
+
+        let winners: BTreeSet<u64> = settled
+            .scores
+            .iter()
+            .filter(|s| s.won)
+            .map(|s| s.user)
+            .collect();
+        assert_eq!(winners, BTreeSet::from([3, 4]));
+        for score in &settled.scores {
+            assert_eq!(
+                score.unlocked.contains(&Achievement::DailyWinner),
+                score.user != 5
+            );
+        }
+        assert_eq!(settled.scores[2].user, 5);
+
+        // Both get the win in the standings
+        league.award(1, &settled, false).await;
+        let season = league.leaderboard.season(1, None).await.unwrap();
+        assert_eq!(season.standings[&3].wins, 1);
+        assert_eq!(season.standings[&4].wins, 1);
+        assert_eq!(season.standings[&5].wins, 0);
+    }
+
+    #[tokio::test]
+    async fn rounds_keep_the_first_place_predicted() {
+        let league = league("coord");
+        let day = NaiveDate::from_ymd_opt(2026, 10, 1).unwrap();
+        league
+            .predict(1, day, "Paris", PARIS, 2, 3, prediction())
+            .await;
+        let elsewhere = Coord {
+            lat: 33.66,
+            lon: -95.56,
+        };
+        let city = league
+            .predict(1, day, "Paris, TX", elsewhere, 2, 4, prediction())
+            .await;
+        assert_eq!(city, "Paris");
+        assert_eq!(
+            league.open_rounds().await,
+            vec![(1, day, "Paris".to_string(), Some(PARIS))]
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use crate::storage::JsonStore;
+use crate::units::UnitSystem;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PreferenceData {
+    // Units each user picked with /units set
+    pub users: HashMap<u64, UnitSystem>,
+    // Server defaults for everyone who hasn't picked
+    pub guilds: HashMap<u64, UnitSystem>,
+}
+
+// Per-user and per-server display preferences
+pub struct Preferences {
+    store: JsonStore<PreferenceData>,
+}
+
+impl Preferences {
+    pub fn new() -> Preferences {
+        Preferences {
+            store: JsonStore::open("preferences"),
+        }
+    }
+
+    // The user's own choice, then their server's, None if neither picked
+    pub async fn units(&self, user: u64, guild: Option<u64>) -> Option<UnitSystem> {
+        self.store
+            .read(|data| {
+                data.users
+                    .get(&user)
+                    .or_else(|| guild.and_then(|g| data.guilds.get(&g)))
+                    .copied()
+            })
+            .await
+    }
+
+    // None goes back to the server default
+    pub async fn set_user_units(&self, user: u64, system: Option<UnitSystem>) {
+        self.store
+            .update(|data| match system {
+                Some(system) => {
+                    data.users.insert(user, system);
+                }
+                None => {
+                    data.users.remove(&user);
+                }
+            })
+            .await;
+    }
+
+    // None goes back to imperial
+    pub async fn set_guild_units(&self, guild: u64, system: Option<UnitSystem>) {
+        self.store
+            .update(|data| match system {
+                Some(system) => {
+                    data.guilds.insert(guild, system);
+                }
+                None => {
+                    data.guilds.remove(&guild);
+                }
+            })
+            .await;
+    }
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Preferences::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn users_override_their_server() {
+        let preferences = Preferences {
+            store: JsonStore::scratch("preferences"),
+        };
+        assert_eq!(preferences.units(1, Some(2)).await, None);
+
+        preferences
+            .set_guild_units(2, Some(UnitSystem::Metric))
+            .await;
+        assert_eq!(
+            preferences.units(1, Some(2)).await,
+            Some(UnitSystem::Metric)
+        );
+        // Not in DMs, or in another server
+        assert_eq!(preferences.units(1, None).await, None);
+        assert_eq!(preferences.units(1, Some(3)).await, None);
+
+        preferences
+            .set_user_units(1, Some(UnitSystem::Scientific))
+            .await;
+        assert_eq!(
+            preferences.units(1, Some(2)).await,
+            Some(UnitSystem::Scientific)
+        );
+        assert_eq!(
+            preferences.units(1, None).await,
+            Some(UnitSystem::Scientific)
+        );
+
+        preferences.set_user_units(1, None).await;
+        assert_eq!(
+            preferences.units(1, Some(2)).await,
+            Some(UnitSystem::Metric)
+        );
+    }
+}
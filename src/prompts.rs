@@ -0,0 +1,311 @@
+use crate::chatbot::{ChatRequest, Message};
+use crate::storage::JsonStore;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+
+// Built-in copy of prompts.json, used unless PROMPTS_FILE points somewhere else
+const DEFAULT_CONFIG: &str = include_str!("../prompts.json");
+
+// Templates the bot needs and the {variables} each one may use
+const TEMPLATES: &[(&str, &[&str])] = &[
+    ("weatherfact", &["city", "conditions", "units", "locale"]),
+    ("weather_joke", &["units", "locale"]),
+];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Template {
+    pub prompt: String,
+    pub max_tokens: u32,
+}
+
+impl Template {
+    // Fill in {name} placeholders
+    pub fn render(&self, vars: &[(&str, String)]) -> String {
+        vars.iter()
+            .fold(self.prompt.clone(), |text, (name, value)| {
+                text.replace(&format!("{{{}}}", name), value)
+            })
+    }
+
+    pub fn uses(&self, var: &str) -> bool {
+        self.prompt.contains(&format!("{{{}}}", var))
+    }
+}
+
+// A personality the bot can take on, given to the model as its system prompt
+#[derive(Debug, Clone, Deserialize)]
+pub struct Persona {
+    pub description: String,
+    pub system: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PromptConfig {
+    pub default_persona: String,
+    pub personas: BTreeMap<String, Persona>,
+    pub templates: HashMap<String, Template>,
+}
+
+// Names inside {braces}, or an error if the braces don't pair up
+fn placeholders(text: &str) -> Result<Vec<&str>, String> {
+    let mut names = Vec::new();
+    let mut rest = text;
+    while let Some(open) = rest.find(['{', '}']) {
+        if rest[open..].starts_with('}') {
+            return Err("unmatched '}'".to_string());
+        }
+        let close = rest[open..]
+            .find('}')
+            .ok_or_else(|| "unclosed '{'".to_string())?;
+        names.push(&rest[open + 1..open + close]);
+        rest = &rest[open + close + 1..];
+    }
+    Ok(names)
+}
+
+impl PromptConfig {
+    pub fn parse(text: &str) -> Result<PromptConfig, String> {
+        let config: PromptConfig = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Catch config mistakes at startup rather than when someone runs a command
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, template) in &self.templates {
+            let allowed = TEMPLATES
+                .iter()
+                .find(|(known, _)| *known == name.as_str())
+                .map(|(_, vars)| *vars)
+                .ok_or_else(|| format!("unknown template '{}'", name))?;
+            let vars = placeholders(&template.prompt)
+                .map_err(|e| format!("template '{}': {}", name, e))?;
+            for var in vars {
+                if !allowed.contains(&var) {
+                    return Err(format!(
+                        "template '{}' uses unknown variable {{{}}} (allowed: {})",
+                        name,
+                        var,
+                        allowed.join(", ")
+                    ));
+                }
+            }
+            if !(1..=1000).contains(&template.max_tokens) {
+                return Err(format!(
+                    "template '{}': max_tokens must be between 1 and 1000",
+                    name
+                ));
+            }
+        }
+        for (name, _) in TEMPLATES {
+            if !self.templates.contains_key(*name) {
+                return Err(format!("missing template '{}'", name));
+            }
+        }
+
+        for (name, persona) in &self.personas {
+            if persona.system.trim().is_empty() {
+                return Err(format!("persona '{}' has an empty system prompt", name));
+            }
+        }
+        if !self.personas.contains_key(&self.default_persona) {
+            return Err(format!(
+                "default_persona '{}' isn't one of the personas",
+                self.default_persona
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Prompt templates plus which persona each guild picked
+pub struct Prompts {
+    config: PromptConfig,
+    guild_personas: JsonStore<HashMap<u64, String>>,
+}
+
+impl Prompts {
+    // Load and validate PROMPTS_FILE, or the built-in prompts.json
+    pub fn load() -> Result<Prompts, String> {
+        let config = match std::env::var("PROMPTS_FILE") {
+            Ok(path) => {
+                let text = std::fs::read_to_string(&path)
+                    .map_err(|e| format!("could not read {}: {}", path, e))?;
+                PromptConfig::parse(&text).map_err(|e| format!("{}: {}", path, e))?
+            }
+            Err(_) => PromptConfig::parse(DEFAULT_CONFIG)
+                .map_err(|e| format!("built-in prompts.json: {}", e))?,
+        };
+        Ok(Prompts {
+            config,
+            guild_personas: JsonStore::open("personas"),
+        })
+    }
+
+    // Validation guarantees every name in TEMPLATES exists
+    pub fn template(&self, name: &str) -> &Template {
+        &self.config.templates[name]
+    }
+
+    pub fn personas(&self) -> &BTreeMap<String, Persona> {
+        &self.config.personas
+    }
+
+    // The guild's persona, falling back to the default if unset or since removed from the config
+    pub async fn persona(&self, guild: Option<u64>) -> (String, &Persona) {
+        let chosen = match guild {
+            Some(guild) => self.guild_personas.read(|p| p.get(&guild).cloned()).await,
+            None => None,
+        };
+        let name = chosen
+            .filter(|name| self.config.personas.contains_key(name))
+            .unwrap_or_else(|| self.config.default_persona.clone());
+        let persona = &self.config.personas[&name];
+        (name, persona)
+    }
+
+    pub async fn set_persona(&self, guild: u64, name: &str) -> Result<(), String> {
+        if !self.config.personas.contains_key(name) {
+            let names: Vec<&str> = self.config.personas.keys().map(|k| k.as_str()).collect();
+            return Err(format!(
+                "Unknown persona '{}'. Choose one of: {}",
+                name,
+                names.join(", ")
+            ));
+        }
+        self.guild_personas
+            .update(|p| {
+                p.insert(guild, name.to_string());
+            })
+            .await;
+        Ok(())
+    }
+
+    // Build a request from a template in the guild's persona. `extra` is appended to the prompt.
+    pub async fn request(
+        &self,
+        name: &str,
+        guild: Option<u64>,
+        vars: &[(&str, String)],
+        extra: &str,
+    ) -> ChatRequest {
+        let template = self.template(name);
+        let (_, persona) = self.persona(guild).await;
+        let prompt = format!("{}{}", template.render(vars), extra);
+        ChatRequest::new(vec![
+            Message::system(&persona.system),
+            Message::user(prompt),
+        ])
+        .max_tokens(template.max_tokens)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> PromptConfig {
+        PromptConfig::parse(DEFAULT_CONFIG).unwrap()
+    }
+
+    // The built-in config with the fact template's prompt replaced
+    fn with_prompt(prompt: &str) -> Result<(), String> {
+        let mut config = config();
+        config.templates.get_mut("weatherfact").unwrap().prompt = prompt.to_string();
+        config.validate()
+    }
+
+    fn with_max_tokens(max_tokens: u32) -> Result<(), String> {
+        let mut config = config();
+        config.templates.get_mut("weather_joke").unwrap().max_tokens = max_tokens;
+        config.validate()
+    }
+
+    #[test]
+    fn placeholders_are_the_names_in_braces() {
+        assert_eq!(
+            placeholders("Fact about {city} in {units}."),
+            Ok(vec!["city", "units"])
+        );
+        assert_eq!(placeholders("No variables"), Ok(vec![]));
+        assert_eq!(placeholders("{}{city}"), Ok(vec!["", "city"]));
+    }
+
+    #[test]
+    fn placeholders_need_balanced_braces() {
+        assert_eq!(placeholders("{city"), Err("unclosed '{'".to_string()));
+        assert_eq!(placeholders("city}"), Err("unmatched '}'".to_string()));
+        assert_eq!(placeholders("{city}}"), Err("unmatched '}'".to_string()));
+        assert!(placeholders("{ci{ty}").is_ok_and(|names| names == vec!["ci{ty"]));
+    }
+
+    #[test]
+    fn built_in_config_is_valid() {
+        assert!(config().validate().is_ok());
+    }
+
+    #[test]
+    fn templates_may_only_use_their_variables() {
+        assert!(with_prompt("{city} {conditions} {units} {locale}").is_ok());
+        let err = with_prompt("Fact about {town}").unwrap_err();
+        assert!(err.contains("unknown variable {town}"), "{}", err);
+        // The joke has no city to give
+        let mut config = config();
+        config.templates.get_mut("weather_joke").unwrap().prompt = "Joke about {city}".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn templates_need_balanced_braces() {
+        let err = with_prompt("Fact about {city").unwrap_err();
+        assert_eq!(err, "template 'weatherfact': unclosed '{'");
+        assert!(with_prompt("Fact about city}").is_err());
+    }
+
+    #[test]
+    fn max_tokens_has_limits() {
+        assert!(with_max_tokens(1).is_ok());
+        assert!(with_max_tokens(1000).is_ok());
+        assert!(with_max_tokens(0).is_err());
+        let err = with_max_tokens(1001).unwrap_err();
+        assert!(err.contains("between 1 and 1000"), "{}", err);
+    }
+
+    #[test]
+    fn templates_must_be_known_and_present() {
+        let mut config = config();
+        let joke = config.templates.remove("weather_joke").unwrap();
+        assert_eq!(
+            config.validate().unwrap_err(),
+            "missing template 'weather_joke'"
+        );
+        config.templates.insert("weather_pun".to_string(), joke);
+        assert_eq!(
+            config.validate().unwrap_err(),
+            "unknown template 'weather_pun'"
+        );
+    }
+
+    #[test]
+    fn personas_need_a_prompt_and_the_default_must_exist() {
+        let mut quiet = config();
+        quiet.personas.get_mut("pirate").unwrap().system = "  ".to_string();
+        assert!(quiet.validate().is_err());
+
+        let mut missing = config();
+        missing.default_persona = "robot".to_string();
+        assert!(missing.validate().unwrap_err().contains("'robot'"));
+    }
+
+    #[test]
+    fn render_fills_in_what_it_is_given() {
+        let template = Template {
+            prompt: "{city} in {units}, {city} again".to_string(),
+            max_tokens: 10,
+        };
+        let text = template.render(&[("city", "Oslo".to_string())]);
+        assert_eq!(text, "Oslo in {units}, Oslo again");
+        assert!(template.uses("units"));
+        assert!(!template.uses("locale"));
+    }
+}
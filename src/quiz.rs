@@ -0,0 +1,671 @@
+use crate::catalog::{self, CatalogCity, Observation};
+use crate::leaderboard::{Award, Leaderboard};
+use crate::units::UnitFormat;
+use crate::{render, Context, Error};
+use futures::StreamExt;
+use poise::serenity_prelude as serenity;
+use poise::{ChoiceParameter, CreateReply};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serenity::{
+    ButtonStyle, ComponentInteractionCollector, CreateActionRow, CreateButton, CreateEmbed,
+    CreateInteractionResponse, CreateInteractionResponseMessage,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub const DEFAULT_QUESTIONS: u32 = 5;
+pub const MAX_QUESTIONS: u32 = 10;
+
+// Extra points for the first right answer to a question
+const FIRST_BONUS: u64 = 5;
+// Times we'll redraw cities when a weather question comes out ambiguous
+const ATTEMPTS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    fn points(self) -> u64 {
+        match self {
+            Difficulty::Easy => 10,
+            Difficulty::Medium => 20,
+            Difficulty::Hard => 30,
+        }
+    }
+
+    fn seconds(self) -> u64 {
+        match self {
+            Difficulty::Easy => 30,
+            Difficulty::Medium => 20,
+            Difficulty::Hard => 15,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum Topic {
+    #[name = "Which city has these conditions"]
+    Conditions,
+    #[name = "Whose flag is this"]
+    Flags,
+    #[name = "Which city is warmest"]
+    Warmest,
+}
+
+struct Question {
+    text: String,
+    options: Vec<String>,
+    answer: usize,
+    // Shown with the answer
+    explanation: String,
+}
+
+// Leaderboards for /quiz plus the channels with a quiz in progress
+pub struct Quizzes {
+    pub leaderboard: Leaderboard,
+    active: Mutex<HashSet<u64>>,
+}
+
+impl Quizzes {
+    pub fn new() -> Quizzes {
+        Quizzes {
+            leaderboard: Leaderboard::open("quiz"),
+            active: Mutex::new(HashSet::new()),
+        }
+    }
+
+    // False if the channel already has a quiz going
+    fn begin(&self, channel: u64) -> bool {
+        self.active.lock().unwrap().insert(channel)
+    }
+
+    fn end(&self, channel: u64) {
+        self.active.lock().unwrap().remove(&channel);
+    }
+}
+
+impl Default for Quizzes {
+    fn default() -> Self {
+        Quizzes::new()
+    }
+}
+
+// A target plus three others. Easy questions draw the others from different
+// continents, hard ones from the same continent, medium from anywhere.
+fn pick(
+    cities: &[&'static CatalogCity],
+    difficulty: Difficulty,
+) -> Option<Vec<&'static CatalogCity>> {
+    let mut rng = rand::thread_rng();
+    let target = *cities.choose(&mut rng)?;
+    let mut others: Vec<&'static CatalogCity> = cities
+        .iter()
+        .copied()
+        .filter(|c| !std::ptr::eq(*c, target))
+        .collect();
+    others.shuffle(&mut rng);
+    // Preferred ones first, so we still fill up when there aren't enough of them
+    others.sort_by_key(|c| {
+        let same = c.continent == target.continent;
+        match difficulty {
+            Difficulty::Easy => same,
+            Difficulty::Medium => false,
+            Difficulty::Hard => !same,
+        }
+    });
+    if others.len() < 3 {
+        return None;
+    }
+    let mut picked = vec![target];
+    picked.extend(others.into_iter().take(3));
+    Some(picked)
+}
+
+// Shuffle the options, keeping track of where the right one went
+fn shuffled(mut options: Vec<String>) -> (Vec<String>, usize) {
+    let right = options[0].clone();
+    options.shuffle(&mut rand::thread_rng());
+    let answer = options.iter().position(|o| *o == right).unwrap_or(0);
+    (options, answer)
+}
+
+fn flag_question(difficulty: Difficulty) -> Option<Question> {
+    // One city per country is enough to know its flag and continent
+    let mut seen = HashSet::new();
+    let countries: Vec<&'static CatalogCity> = catalog::cities()
+        .iter()
+        .filter(|c| seen.insert(c.country.clone()))
+        .collect();
+    let picked = pick(&countries, difficulty)?;
+    let target = picked[0];
+    let (options, answer) = shuffled(picked.iter().map(|c| c.country.clone()).collect());
+    Some(Question {
+        text: format!("Which country does this flag belong to? {}", target.flag),
+        options,
+        answer,
+        explanation: format!("{} is the flag of {}.", target.flag, target.country),
+    })
+}
+
+fn label(city: &CatalogCity) -> String {
+    format!("{}, {}", city.name, city.country)
+}
+
+// Four cities with their current weather
+async fn observe(difficulty: Difficulty) -> Option<Vec<Observation>> {
+    let cities: Vec<&'static CatalogCity> = catalog::cities().iter().collect();
+    let picked = pick(&cities, difficulty)?;
+    let (observations, _) = catalog::sweep(picked).await;
+    if observations.len() < 4 {
+        return None;
+    }
+    Some(observations)
+}
+
+async fn conditions_question(difficulty: Difficulty, fmt: &UnitFormat) -> Option<Question> {
+    for _ in 0..ATTEMPTS {
+        let observations = match observe(difficulty).await {
+            Some(observations) => observations,
+            None => continue,
+        };
+        let target = &observations[rand::thread_rng().gen_range(0..observations.len())];
+        let description = match render::condition_text(&target.weather) {
+            Some(description) => description,
+            None => continue,
+        };
+        // Another city with the same sky and a similar temperature would be a second right answer
+        let ambiguous = observations.iter().any(|o| {
+            !std::ptr::eq(o, target)
+                && render::condition_text(&o.weather).as_ref() == Some(&description)
+                && (o.weather.main.temp.celsius() - target.weather.main.temp.celsius()).abs() < 3.0
+        });
+        if ambiguous {
+            continue;
+        }
+
+        let mut options = vec![label(target.city)];
+        options.extend(
+            observations
+                .iter()
+                .filter(|o| !std::ptr::eq(*o, target))
+                .map(|o| label(o.city)),
+        );
+        let (options, answer) = shuffled(options);
+        let explanation = observations
+            .iter()
+            .map(|o| {
+                format!(
+                    "{} {}: {}, {}",
+                    o.city.flag,
+                    o.city.name,
+                    o.weather.main.temp.format(fmt),
+                    render::condition_text(&o.weather)
+                        .unwrap_or_default()
+                        .to_lowercase()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        return Some(Question {
+            text: format!(
+                "Where is it {} with {} right now?",
+                target.weather.main.temp.format(fmt),
+                description.to_lowercase()
+            ),
+            options,
+            answer,
+            explanation,
+        });
+    }
+    None
+}
+
+async fn warmest_question(difficulty: Difficulty, fmt: &UnitFormat) -> Option<Question> {
+    for _ in 0..ATTEMPTS {
+        let mut observations = match observe(difficulty).await {
+            Some(observations) => observations,
+            None => continue,
+        };
+        observations.sort_by(|a, b| {
+            b.weather
+                .main
+                .temp
+                .celsius()
+                .total_cmp(&a.weather.main.temp.celsius())
+        });
+        // Too close to call
+        let gap = observations[0].weather.main.temp.celsius()
+            - observations[1].weather.main.temp.celsius();
+        if gap < 1.0 {
+            continue;
+        }
+
+        let (options, answer) = shuffled(observations.iter().map(|o| label(o.city)).collect());
+        let explanation = observations
+            .iter()
+            .map(|o| {
+                format!(
+                    "{} {}: {}",
+                    o.city.flag,
+                    o.city.name,
+                    o.weather.main.temp.format(fmt)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        return Some(Question {
+            text: "Which of these cities is the warmest right now?".to_string(),
+            options,
+            answer,
+            explanation,
+        });
+    }
+    None
+}
+
+// Weather questions need the API; flags are the fallback when it's unavailable
+async fn ask(topic: Topic, difficulty: Difficulty, fmt: &UnitFormat) -> Option<Question> {
+    let asked = match topic {
+        Topic::Conditions => conditions_question(difficulty, fmt).await,
+        Topic::Warmest => warmest_question(difficulty, fmt).await,
+        Topic::Flags => None,
+    };
+    asked.or_else(|| flag_question(difficulty))
+}
+
+fn components(prefix: &str, question: &Question, revealed: bool) -> Vec<CreateActionRow> {
+    let buttons = question
+        .options
+        .iter()
+        .enumerate()
+        .map(|(i, option)| {
+            let style = if revealed && i == question.answer {
+                ButtonStyle::Success
+            } else if revealed {
+                ButtonStyle::Secondary
+            } else {
+                ButtonStyle::Primary
+            };
+            CreateButton::new(format!("{}-{}", prefix, i))
+                .label(option)
+                .style(style)
+                .disabled(revealed)
+        })
+        .collect();
+    vec![CreateActionRow::Buttons(buttons)]
+}
+
+// One player's tally for the whole quiz
+#[derive(Default)]
+struct Tally {
+    name: String,
+    points: u64,
+    correct: u32,
+    // Top score at the end (ties all win, nobody wins with nothing)
+    won: bool,
+}
+
+// Add one question's answers, given in the order they came in, to the tallies.
+// Returns who got it right, the first of whom gets the bonus.
+fn score_answers(
+    tallies: &mut HashMap<u64, Tally>,
+    answers: Vec<(u64, String, usize)>,
+    answer: usize,
+    difficulty: Difficulty,
+) -> Vec<String> {
+    let mut right = Vec::new();
+    for (user, name, choice) in answers {
+        let tally = tallies.entry(user).or_default();
+        tally.name = name.clone();
+        if choice == answer {
+            let bonus = if right.is_empty() { FIRST_BONUS } else { 0 };
+            tally.points += difficulty.points() + bonus;
+            tally.correct += 1;
+            right.push(name);
+        }
+    }
+    right
+}
+
+// Most points first, with the winners marked
+fn rank(tallies: HashMap<u64, Tally>) -> Vec<(u64, Tally)> {
+    let mut ranked: Vec<(u64, Tally)> = tallies.into_iter().collect();
+    ranked.sort_by_key(|(_, tally)| std::cmp::Reverse(tally.points));
+    let best = ranked.first().map(|(_, t)| t.points).unwrap_or(0);
+    for (_, tally) in &mut ranked {
+        tally.won = best > 0 && tally.points == best;
+    }
+    ranked
+}
+
+// Run a quiz in the channel: each question gets four buttons and everyone in
+// the channel can answer once before time runs out
+pub async fn play(
+    ctx: Context<'_>,
+    questions: u32,
+    difficulty: Difficulty,
+    topic: Option<Topic>,
+) -> Result<(), Error> {
+    let channel = ctx.channel_id().get();
+    let quizzes = &ctx.data().quiz;
+    if !quizzes.begin(channel) {
+        ctx.send(
+            CreateReply::default()
+                .content("There's already a quiz going in this channel.")
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+    let result = run(ctx, questions, difficulty, topic).await;
+    quizzes.end(channel);
+    result
+}
+
+async fn run(
+    ctx: Context<'_>,
+    questions: u32,
+    difficulty: Difficulty,
+    topic: Option<Topic>,
+) -> Result<(), Error> {
+    // guild_only guarantees a guild id
+    let guild = ctx.guild_id().unwrap().get();
+    ctx.defer().await?;
+    let fmt = render::unit_format(ctx).await;
+    let questions = questions.clamp(1, MAX_QUESTIONS);
+    let seconds = difficulty.seconds();
+    let mut tallies: HashMap<u64, Tally> = HashMap::new();
+    // Can end up short of `questions` if we run out of questions to ask
+    let mut asked = 0;
+
+    for number in 1..=questions {
+        let topic = topic.unwrap_or_else(|| {
+            *[Topic::Conditions, Topic::Flags, Topic::Warmest]
+                .choose(&mut rand::thread_rng())
+                .unwrap()
+        });
+        let question = match ask(topic, difficulty, &fmt).await {
+            Some(question) => question,
+            None => {
+                ctx.say("I couldn't come up with a question, so that's the end of the quiz.")
+                    .await?;
+                break;
+            }
+        };
+        asked = number;
+
+        let closes = chrono::Utc::now().timestamp() + seconds as i64;
+        let header = format!(
+            "**Question {}/{}** · {} · {} pts\n{}",
+            number,
+            questions,
+            difficulty.name(),
+            difficulty.points(),
+            question.text
+        );
+        let prefix = format!("{}-{}", ctx.id(), number);
+        let handle = ctx
+            .send(
+                CreateReply::default()
+                    .content(format!("{}\nCloses <t:{}:R>.", header, closes))
+                    .components(components(&prefix, &question, false)),
+            )
+            .await?;
+
+        // In the order they came in, so the first right answer gets the bonus
+        let mut answers: Vec<(u64, String, usize)> = Vec::new();
+        let collector_prefix = format!("{}-", prefix);
+        let mut collector = ComponentInteractionCollector::new(ctx)
+            .filter(move |i| i.data.custom_id.starts_with(&collector_prefix))
+            .timeout(Duration::from_secs(seconds))
+            .stream();
+        while let Some(click) = collector.next().await {
+            let user = click.user.id.get();
+            let choice = click
+                .data
+                .custom_id
+                .rsplit('-')
+                .next()
+                .and_then(|i| i.parse::<usize>().ok());
+            let text = match choice {
+                _ if answers.iter().any(|(id, _, _)| *id == user) => {
+                    "You've already answered this one."
+                }
+                Some(choice) if choice < question.options.len() => {
+                    answers.push((user, click.user.display_name().to_string(), choice));
+                    "Answer locked in."
+                }
+                // A stale or mangled button, it still needs an answer
+                _ => "That's not one of the options.",
+            };
+            let response = CreateInteractionResponseMessage::new()
+                .content(text)
+                .ephemeral(true);
+            if let Err(e) = click
+                .create_response(ctx, CreateInteractionResponse::Message(response))
+                .await
+            {
+                println!("Error: {:?}", e);
+            }
+        }
+
+        let right = score_answers(&mut tallies, answers, question.answer, difficulty);
+        let outcome = match right.len() {
+            0 => "Nobody got it.".to_string(),
+            _ => format!(
+                "Got it: {} (first one gets +{})",
+                right.join(", "),
+                FIRST_BONUS
+            ),
+        };
+        let revealed = format!(
+            "{}\n✅ **{}**\n{}\n{}",
+            header, question.options[question.answer], question.explanation, outcome
+        );
+        handle
+            .edit(
+                ctx,
+                CreateReply::default()
+                    .content(revealed)
+                    .components(components(&prefix, &question, true)),
+            )
+            .await?;
+    }
+
+    let ranked = rank(tallies);
+    let awards: Vec<Award> = ranked
+        .iter()
+        .map(|(user, tally)| Award {
+            user: *user,
+            name: tally.name.clone(),
+            points: tally.points,
+            won: tally.won,
+        })
+        .collect();
+    ctx.data().quiz.leaderboard.award(guild, &awards).await;
+
+    let lines: Vec<String> = ranked
+        .iter()
+        .map(|(_, tally)| {
+            format!(
+                "{} {} · {} pts ({}/{} right)",
+                if tally.won { "🏆" } else { "▫️" },
+                tally.name,
+                tally.points,
+                tally.correct,
+                asked
+            )
+        })
+        .collect();
+    let body = if lines.is_empty() {
+        "Nobody played this time.".to_string()
+    } else {
+        lines.join("\n")
+    };
+    let embed = CreateEmbed::new().title("Quiz results").description(&body);
+    render::send_embed(ctx, embed, format!("**Quiz results**\n{}", body)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use catalog::Continent;
+
+    fn cities(list: &[(&str, Continent)]) -> Vec<&'static CatalogCity> {
+        list.iter()
+            .map(|(name, continent)| {
+                let city = CatalogCity {
+                    name: name.to_string(),
+                    country: format!("{} country", name),
+                    flag: String::new(),
+                    continent: *continent,
+                    lat: 0.0,
+                    lon: 0.0,
+                    population: 1,
+                };
+                &*Box::leak(Box::new(city))
+            })
+            .collect()
+    }
+
+    fn distinct(picked: &[&CatalogCity]) -> bool {
+        let names: HashSet<&str> = picked.iter().map(|c| c.name.as_str()).collect();
+        names.len() == picked.len()
+    }
+
+    #[test]
+    fn easy_draws_from_other_continents() {
+        let cities = cities(&[
+            ("Paris", Continent::Europe),
+            ("Rome", Continent::Europe),
+            ("Oslo", Continent::Europe),
+            ("Lima", Continent::SouthAmerica),
+            ("Quito", Continent::SouthAmerica),
+            ("Bogota", Continent::SouthAmerica),
+            ("Tokyo", Continent::Asia),
+        ]);
+        for _ in 0..50 {
+            let picked = pick(&cities, Difficulty::Easy).unwrap();
+            assert_eq!(picked.len(), 4);
+            assert!(distinct(&picked));
+            // Three from elsewhere are always available
+            let target = picked[0].continent;
+            assert!(picked[1..].iter().all(|c| c.continent != target));
+        }
+    }
+
+    #[test]
+    fn hard_draws_from_the_same_continent() {
+        let cities = cities(&[
+            ("Paris", Continent::Europe),
+            ("Rome", Continent::Europe),
+            ("Oslo", Continent::Europe),
+            ("Bern", Continent::Europe),
+            ("Lima", Continent::SouthAmerica),
+            ("Quito", Continent::SouthAmerica),
+            ("Bogota", Continent::SouthAmerica),
+            ("Santiago", Continent::SouthAmerica),
+        ]);
+        for _ in 0..50 {
+            let picked = pick(&cities, Difficulty::Hard).unwrap();
+            assert!(distinct(&picked));
+            let target = picked[0].continent;
+            assert!(picked[1..].iter().all(|c| c.continent == target));
+        }
+    }
+
+    #[test]
+    fn pick_fills_up_when_too_few_match() {
+        // Hard wants three more in Oceania, there's only one
+        let cities = cities(&[
+            ("Sydney", Continent::Oceania),
+            ("Auckland", Continent::Oceania),
+            ("Cairo", Continent::Africa),
+            ("Lagos", Continent::Africa),
+            ("Nairobi", Continent::Africa),
+        ]);
+        for _ in 0..50 {
+            let picked = pick(&cities, Difficulty::Hard).unwrap();
+            assert_eq!(picked.len(), 4);
+            assert!(distinct(&picked));
+            if picked[0].continent == Continent::Oceania {
+                assert_eq!(picked[1].continent, Continent::Oceania);
+            }
+        }
+        assert!(pick(&cities[..3], Difficulty::Medium).is_none());
+        assert!(pick(&[], Difficulty::Easy).is_none());
+    }
+
+    #[test]
+    fn shuffled_tracks_the_answer() {
+        let options: Vec<String> = ["right", "a", "b", "c"].map(String::from).to_vec();
+        for _ in 0..20 {
+            let (options, answer) = shuffled(options.clone());
+            assert_eq!(options[answer], "right");
+        }
+    }
+
+    fn answer(user: u64, choice: usize) -> (u64, String, usize) {
+        (user, format!("player {}", user), choice)
+    }
+
+    #[test]
+    fn first_right_answer_gets_the_bonus() {
+        let mut tallies = HashMap::new();
+        let right = score_answers(
+            &mut tallies,
+            vec![answer(1, 2), answer(2, 0), answer(3, 0)],
+            0,
+            Difficulty::Medium,
+        );
+        assert_eq!(right, vec!["player 2", "player 3"]);
+        assert_eq!(tallies[&2].points, 20 + FIRST_BONUS);
+        assert_eq!(tallies[&3].points, 20);
+        // Wrong answers still count as playing
+        assert_eq!(tallies[&1].points, 0);
+        assert_eq!(tallies[&1].correct, 0);
+    }
+
+    #[test]
+    fn tallies_add_up_across_questions() {
+        let mut tallies = HashMap::new();
+        score_answers(
+            &mut tallies,
+            vec![answer(1, 0), answer(2, 0)],
+            0,
+            Difficulty::Hard,
+        );
+        score_answers(
+            &mut tallies,
+            vec![answer(2, 1), answer(1, 1)],
+            1,
+            Difficulty::Hard,
+        );
+        score_answers(&mut tallies, vec![answer(3, 3)], 2, Difficulty::Hard);
+
+        let ranked = rank(tallies);
+        let summary: Vec<(u64, u64, u32, bool)> = ranked
+            .iter()
+            .map(|(user, t)| (*user, t.points, t.correct, t.won))
+            .collect();
+        // Both got the bonus once, so they tie
+        assert_eq!(summary[2], (3, 0, 0, false));
+        assert!(summary[..2].contains(&(1, 65, 2, true)));
+        assert!(summary[..2].contains(&(2, 65, 2, true)));
+    }
+
+    #[test]
+    fn nobody_wins_with_nothing() {
+        let mut tallies = HashMap::new();
+        score_answers(&mut tallies, vec![answer(1, 1)], 0, Difficulty::Easy);
+        let ranked = rank(tallies);
+        assert!(!ranked[0].1.won);
+        assert!(rank(HashMap::new()).is_empty());
+    }
+}
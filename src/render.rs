@@ -0,0 +1,586 @@
+use crate::catalog::{Extremes, Observation};
+use crate::history::{Stat, Summary};
+use crate::leaderboard::Season;
+use crate::meteo;
+use crate::units::{Speed, Temperature, UnitFormat, UnitSystem};
+use crate::weather::{ForecastResponse, WeatherResponse};
+use crate::{Context, Error};
+use poise::serenity_prelude as serenity;
+use poise::CreateReply;
+use serenity::{CreateEmbed, CreateEmbedFooter, Timestamp};
+
+// Pick an embed color based on how hot or cold it is
+pub fn temperature_color(temperature: Temperature) -> u32 {
+    match temperature.fahrenheit() {
+        f if f < 14.0 => 0x6A5ACD, // Bitter cold - slate blue
+        f if f < 32.0 => 0x1E90FF, // Freezing - blue
+        f if f < 50.0 => 0x00BFFF, // Cold - light blue
+        f if f < 65.0 => 0x3CB371, // Cool - green
+        f if f < 80.0 => 0xFFD700, // Warm - yellow
+        f if f < 95.0 => 0xFF8C00, // Hot - orange
+        _ => 0xDC143C,             // Scorching - red
+    }
+}
+
+// OpenWeather icon code (e.g. "10d") to a thumbnail URL
+pub fn icon_url(icon: &str) -> String {
+    format!("https://openweathermap.org/img/wn/{}@2x.png", icon)
+}
+
+// Title used by both the embed and the text fallback
+fn title(weather: &WeatherResponse, flag: Option<&str>) -> String {
+    match flag {
+        Some(flag) => format!("{}, {} {}", weather.name, weather.sys.country, flag),
+        None => format!("{}, {}", weather.name, weather.sys.country),
+    }
+}
+
+// Capitalized condition description, e.g. "Light rain"
+pub fn condition_text(weather: &WeatherResponse) -> Option<String> {
+    weather.condition().map(|c| {
+        let mut chars = c.description.chars();
+        match chars.next() {
+            Some(first) => first.to_uppercase().collect::<String>() + chars.as_str(),
+            None => c.main.clone(),
+        }
+    })
+}
+
+// Name/value pairs shown for a full weather report
+fn fields(weather: &WeatherResponse, fmt: &UnitFormat) -> Vec<(&'static str, String)> {
+    let mut wind = format!(
+        "{} {}",
+        weather.wind.speed.format(fmt),
+        meteo::compass(weather.wind.deg as f64)
+    );
+    if let Some(gust) = weather.wind.gust {
+        wind.push_str(&format!(" (gusts {})", gust.format(fmt)));
+    }
+
+    let mut fields = vec![
+        ("🌡️ Temp", weather.main.temp.format(fmt)),
+        ("😓 Feels Like", weather.main.feels_like.format(fmt)),
+        ("🧊 Min Temp", weather.main.temp_min.format(fmt)),
+        ("🔥 Max Temp", weather.main.temp_max.format(fmt)),
+        ("🌬️ Pressure", weather.main.pressure.format(fmt)),
+        ("💧 Humidity", format!("{}%", weather.main.humidity)),
+        (
+            "🌫️ Dew Point",
+            meteo::dew_point(weather.main.temp, weather.main.humidity).format(fmt),
+        ),
+        ("💨 Wind", wind),
+        ("☁️ Clouds", format!("{}%", weather.clouds.all)),
+    ];
+    if let Some(visibility) = weather.visibility {
+        fields.push(("👀 Visibility", visibility.format(fmt)));
+    }
+    if let Some(rain) = &weather.rain {
+        if let Some(amount) = rain.rain_1h {
+            fields.push(("🌧️ Rain (1h)", amount.format(fmt)));
+        } else if let Some(amount) = rain.rain_3h {
+            fields.push(("🌧️ Rain (3h)", amount.format(fmt)));
+        }
+    }
+    if let Some(snow) = &weather.snow {
+        if let Some(amount) = snow.snow_1h {
+            fields.push(("🌨️ Snow (1h)", amount.format(fmt)));
+        } else if let Some(amount) = snow.snow_3h {
+            fields.push(("🌨️ Snow (3h)", amount.format(fmt)));
+        }
+    }
+    fields
+}
+
+// Build the rich embed for a weather report
+pub fn weather_embed(
+    weather: &WeatherResponse,
+    flag: Option<&str>,
+    fmt: &UnitFormat,
+) -> CreateEmbed {
+    let mut embed = CreateEmbed::new()
+        .title(title(weather, flag))
+        .color(temperature_color(weather.main.temp))
+        .fields(
+            fields(weather, fmt)
+                .into_iter()
+                .map(|(name, value)| (name, value, true)),
+        )
+        .footer(CreateEmbedFooter::new("Observed"));
+
+    if let Some(description) = condition_text(weather) {
+        embed = embed.description(description);
+    }
+    if let Some(condition) = weather.condition() {
+        embed = embed.thumbnail(icon_url(&condition.icon));
+    }
+    if let Ok(observed) = Timestamp::from_unix_timestamp(weather.dt) {
+        embed = embed.timestamp(observed);
+    }
+    embed
+}
+
+// Plain text version of the same report
+pub fn weather_text(weather: &WeatherResponse, flag: Option<&str>, fmt: &UnitFormat) -> String {
+    let mut text = format!("The weather in {} is:", title(weather, flag));
+    if let Some(description) = condition_text(weather) {
+        text.push_str(&format!("\n{}", description));
+    }
+    for (name, value) in fields(weather, fmt) {
+        text.push_str(&format!("\n{}: {}", name, value));
+    }
+    if weather.dt > 0 {
+        text.push_str(&format!("\nObserved <t:{}:R>", weather.dt));
+    }
+    text
+}
+
+// Rows shown for each city in /compare: label, value, and the number used to
+// find the highest and lowest (None for rows that aren't compared)
+fn compare_rows(
+    weather: &WeatherResponse,
+    fmt: &UnitFormat,
+) -> Vec<(&'static str, String, Option<f64>)> {
+    let local = |timestamp: u64| {
+        weather
+            .local_time(timestamp as i64)
+            .map(|t| t.format("%-I:%M%P").to_string())
+            .unwrap_or_else(|| "?".to_string())
+    };
+    let main = &weather.main;
+    vec![
+        ("🌡️", main.temp.format(fmt), Some(main.temp.fahrenheit())),
+        (
+            "😓",
+            main.feels_like.format(fmt),
+            Some(main.feels_like.fahrenheit()),
+        ),
+        (
+            "💧",
+            format!("{}%", main.humidity),
+            Some(main.humidity as f64),
+        ),
+        (
+            "💨",
+            weather.wind.speed.format(fmt),
+            Some(weather.wind.speed.mph()),
+        ),
+        (
+            "☁️",
+            format!("{}%", weather.clouds.all),
+            Some(weather.clouds.all as f64),
+        ),
+        ("🌅", local(weather.sys.sunrise), None),
+        ("🌇", local(weather.sys.sunset), None),
+    ]
+}
+
+// Each city's rows with the highest and lowest value of every compared row marked
+fn compare_columns(weathers: &[WeatherResponse], fmt: &UnitFormat) -> Vec<(String, String)> {
+    let rows: Vec<_> = weathers.iter().map(|w| compare_rows(w, fmt)).collect();
+    let row_count = rows.first().map_or(0, |r| r.len());
+
+    let mut columns: Vec<Vec<String>> = vec![Vec::new(); weathers.len()];
+    for row in 0..row_count {
+        let values: Vec<Option<f64>> = rows.iter().map(|city| city[row].2).collect();
+        let max = values.iter().flatten().cloned().fold(f64::MIN, f64::max);
+        let min = values.iter().flatten().cloned().fold(f64::MAX, f64::min);
+        for (city, column) in columns.iter_mut().enumerate() {
+            let (label, text, value) = &rows[city][row];
+            // Only worth highlighting if the cities actually differ
+            let marker = match value {
+                Some(v) if max > min && *v == max => " 🔺",
+                Some(v) if max > min && *v == min => " 🔻",
+                _ => "",
+            };
+            column.push(format!("{} {}{}", label, text, marker));
+        }
+    }
+
+    weathers
+        .iter()
+        .zip(columns)
+        .map(|(weather, lines)| (title(weather, None), lines.join("\n")))
+        .collect()
+}
+
+// Side by side current conditions for a few cities, one column each
+pub fn compare_embed(weathers: &[WeatherResponse], fmt: &UnitFormat) -> CreateEmbed {
+    let mut embed = CreateEmbed::new()
+        .title("Weather comparison")
+        .fields(
+            compare_columns(weathers, fmt)
+                .into_iter()
+                .map(|(name, value)| (name, value, true)),
+        )
+        .footer(CreateEmbedFooter::new("🔺 highest · 🔻 lowest"));
+    if let Some(warmest) = weathers
+        .iter()
+        .map(|w| w.main.temp)
+        .reduce(Temperature::max)
+    {
+        embed = embed.color(temperature_color(warmest));
+    }
+    embed
+}
+
+pub fn compare_text(weathers: &[WeatherResponse], fmt: &UnitFormat) -> String {
+    compare_columns(weathers, fmt)
+        .into_iter()
+        .map(|(name, value)| format!("**{}**\n{}", name, value))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+// The standout cities of a catalog sweep, with the value that earned each spot
+fn extremes_fields(extremes: &Extremes, fmt: &UnitFormat) -> Vec<(&'static str, String)> {
+    let line = |o: &Observation, value: String| {
+        format!(
+            "{} {}, {} · {}",
+            o.city.flag, o.city.name, o.city.country, value
+        )
+    };
+    vec![
+        (
+            "🔥 Hottest",
+            line(
+                extremes.hottest,
+                extremes.hottest.weather.main.temp.format(fmt),
+            ),
+        ),
+        (
+            "🧊 Coldest",
+            line(
+                extremes.coldest,
+                extremes.coldest.weather.main.temp.format(fmt),
+            ),
+        ),
+        (
+            "💨 Windiest",
+            line(
+                extremes.windiest,
+                extremes.windiest.weather.wind.speed.format(fmt),
+            ),
+        ),
+        (
+            "💧 Most humid",
+            line(
+                extremes.most_humid,
+                format!("{}%", extremes.most_humid.weather.main.humidity),
+            ),
+        ),
+        (
+            "☁️ Cloudiest",
+            line(
+                extremes.cloudiest,
+                format!("{}% cloud cover", extremes.cloudiest.weather.clouds.all),
+            ),
+        ),
+    ]
+}
+
+// `scope` describes what was swept, e.g. "Europe" or "the world"
+pub fn extremes_embed(
+    extremes: &Extremes,
+    checked: usize,
+    scope: &str,
+    fmt: &UnitFormat,
+) -> CreateEmbed {
+    CreateEmbed::new()
+        .title(format!("🌍 Weather extremes across {}", scope))
+        .color(temperature_color(extremes.hottest.weather.main.temp))
+        .fields(
+            extremes_fields(extremes, fmt)
+                .into_iter()
+                .map(|(name, value)| (name, value, false)),
+        )
+        .footer(CreateEmbedFooter::new(format!(
+            "{} cities checked",
+            checked
+        )))
+        .timestamp(Timestamp::now())
+}
+
+pub fn extremes_text(extremes: &Extremes, checked: usize, scope: &str, fmt: &UnitFormat) -> String {
+    let mut text = format!("🌍 Weather extremes across {}", scope);
+    for (name, value) in extremes_fields(extremes, fmt) {
+        text.push_str(&format!("\n{}: {}", name, value));
+    }
+    text.push_str(&format!("\n({} cities checked)", checked));
+    text
+}
+
+// Small embed for the single-value commands (temp, wind, ...)
+pub fn simple_embed(weather: &WeatherResponse, title: &str, body: &str) -> CreateEmbed {
+    let mut embed = CreateEmbed::new()
+        .title(title)
+        .description(body)
+        .color(temperature_color(weather.main.temp));
+    if let Some(condition) = weather.condition() {
+        embed = embed.thumbnail(icon_url(&condition.icon));
+    }
+    if let Ok(observed) = Timestamp::from_unix_timestamp(weather.dt) {
+        embed = embed.timestamp(observed);
+    }
+    embed
+}
+
+// Whether the bot is allowed to post embeds where the command was used
+pub fn embeds_allowed(ctx: Context<'_>) -> bool {
+    match ctx {
+        poise::Context::Application(actx) => actx
+            .interaction
+            .app_permissions
+            .map(|p| p.embed_links())
+            .unwrap_or(true),
+        // Prefix commands fall back when the send fails instead
+        poise::Context::Prefix(_) => true,
+    }
+}
+
+// Top of a game's season standings, one line per player
+fn leaderboard_lines(season: &Season, limit: usize) -> Vec<String> {
+    season
+        .ranked()
+        .into_iter()
+        .take(limit)
+        .enumerate()
+        .map(|(i, (_, standing))| {
+            let place = match i {
+                0 => "🥇".to_string(),
+                1 => "🥈".to_string(),
+                2 => "🥉".to_string(),
+                _ => format!("{}.", i + 1),
+            };
+            format!(
+                "{} {} · {} pts ({} played, {} won)",
+                place, standing.name, standing.points, standing.played, standing.wins
+            )
+        })
+        .collect()
+}
+
+fn leaderboard_title(game: &str, season: &Season) -> String {
+    match season.ended {
+        Some(_) => format!("{} leaderboard · season {} (final)", game, season.number),
+        None => format!("{} leaderboard · season {}", game, season.number),
+    }
+}
+
+pub fn leaderboard_embed(game: &str, season: &Season, limit: usize) -> CreateEmbed {
+    let lines = leaderboard_lines(season, limit);
+    let description = if lines.is_empty() {
+        "Nobody has played this season yet.".to_string()
+    } else {
+        lines.join("\n")
+    };
+    CreateEmbed::new()
+        .title(leaderboard_title(game, season))
+        .description(description)
+        .footer(CreateEmbedFooter::new(format!(
+            "{} players this season",
+            season.standings.len()
+        )))
+}
+
+pub fn leaderboard_text(game: &str, season: &Season, limit: usize) -> String {
+    let lines = leaderboard_lines(season, limit);
+    if lines.is_empty() {
+        return format!(
+            "{}\nNobody has played this season yet.",
+            leaderboard_title(game, season)
+        );
+    }
+    format!("{}\n{}", leaderboard_title(game, season), lines.join("\n"))
+}
+
+// "▲ 2.5°F" style change in the average, flat when it rounds to nothing
+fn delta(change: f64, text: String) -> String {
+    if change.abs() < 0.05 {
+        "no change".to_string()
+    } else if change > 0.0 {
+        format!("▲ {}", text)
+    } else {
+        format!("▼ {}", text)
+    }
+}
+
+// Min/avg/max for temperature, humidity and wind, with the change in the
+// average against another window if there is one
+fn history_fields(
+    summary: &Summary,
+    compared: Option<(&str, &Summary)>,
+    fmt: &UnitFormat,
+) -> Vec<(&'static str, String)> {
+    let temp = |k: f64| Temperature::from_kelvin(k).format(fmt);
+    let wind = |mps: f64| Speed::from_meters_per_sec(mps).format(fmt);
+    let humidity = |pct: f64| format!("{}%", fmt.number(pct, 0));
+    let line = |stat: &Stat, show: &dyn Fn(f64) -> String| {
+        format!(
+            "min {} · avg {} · max {}",
+            show(stat.min),
+            show(stat.avg),
+            show(stat.max)
+        )
+    };
+
+    let mut temperature = line(&summary.temp, &temp);
+    let mut humid = line(&summary.humidity, &humidity);
+    let mut windy = line(&summary.wind, &wind);
+    if let Some((label, other)) = compared {
+        let change = Temperature::from_kelvin(summary.temp.avg).value(fmt.system)
+            - Temperature::from_kelvin(other.temp.avg).value(fmt.system);
+        let text = format!(
+            "{}{}",
+            fmt.number(change.abs(), 1),
+            Temperature::symbol(fmt.system)
+        );
+        temperature.push_str(&format!("\n{} vs {}", delta(change, text), label));
+
+        let change = summary.humidity.avg - other.humidity.avg;
+        let text = format!("{} points", fmt.number(change.abs(), 0));
+        humid.push_str(&format!("\n{} vs {}", delta(change, text), label));
+
+        let change = summary.wind.avg - other.wind.avg;
+        windy.push_str(&format!(
+            "\n{} vs {}",
+            delta(change, wind(change.abs())),
+            label
+        ));
+    }
+    vec![
+        ("🌡️ Temperature", temperature),
+        ("💧 Humidity", humid),
+        ("💨 Wind", windy),
+    ]
+}
+
+pub fn history_embed(
+    title: &str,
+    summary: &Summary,
+    compared: Option<(&str, &Summary)>,
+    fmt: &UnitFormat,
+) -> CreateEmbed {
+    let average = Temperature::from_kelvin(summary.temp.avg);
+    CreateEmbed::new()
+        .title(title)
+        .fields(
+            history_fields(summary, compared, fmt)
+                .into_iter()
+                .map(|(name, value)| (name, value, false)),
+        )
+        .color(temperature_color(average))
+        .footer(CreateEmbedFooter::new(format!(
+            "From {} recorded observations",
+            summary.samples
+        )))
+}
+
+pub fn history_text(
+    title: &str,
+    summary: &Summary,
+    compared: Option<(&str, &Summary)>,
+    fmt: &UnitFormat,
+) -> String {
+    let mut text = format!("**{}**", title);
+    for (name, value) in history_fields(summary, compared, fmt) {
+        text.push_str(&format!("\n{}: {}", name, value.replace('\n', " · ")));
+    }
+    text
+}
+
+// Send an embed, or the plain text fallback if embeds are disabled
+pub async fn send_embed(
+    ctx: Context<'_>,
+    embed: CreateEmbed,
+    fallback: String,
+) -> Result<(), Error> {
+    if !embeds_allowed(ctx) {
+        ctx.say(fallback).await?;
+        return Ok(());
+    }
+
+    if let Err(e) = ctx.send(CreateReply::default().embed(embed)).await {
+        println!("Error sending embed, falling back to text: {:?}", e);
+        ctx.say(fallback).await?;
+    }
+    Ok(())
+}
+
+// Units and number format for the person who ran the command: their own
+// /units choice, then their server's, imperial if neither picked one
+pub async fn unit_format(ctx: Context<'_>) -> UnitFormat {
+    let user = ctx.author().id.get();
+    let guild = ctx.guild_id().map(|g| g.get());
+    let system = ctx.data().preferences.units(user, guild).await;
+    UnitFormat::new(system.unwrap_or(UnitSystem::Imperial), ctx.locale())
+}
+
+// Send a full weather report
+pub async fn send_weather(
+    ctx: Context<'_>,
+    weather: &WeatherResponse,
+    flag: Option<&str>,
+) -> Result<(), Error> {
+    let fmt = unit_format(ctx).await;
+    send_embed(
+        ctx,
+        weather_embed(weather, flag, &fmt),
+        weather_text(weather, flag, &fmt),
+    )
+    .await
+}
+
+// Next 24 hours in 3-hour steps
+pub fn hourly_embed(forecast: &ForecastResponse, fmt: &UnitFormat) -> CreateEmbed {
+    let mut lines = Vec::new();
+    for entry in forecast.hourly(24) {
+        let condition = entry
+            .weather
+            .first()
+            .map(|c| c.description.clone())
+            .unwrap_or_default();
+        lines.push(format!(
+            "<t:{}:t> {} · {} · 💧 {:.0}%",
+            entry.dt,
+            entry.main.temp.format(fmt),
+            condition,
+            entry.pop * 100.0
+        ));
+    }
+
+    let mut embed = CreateEmbed::new()
+        .title(format!("Hourly forecast for {}", forecast.city.name))
+        .description(lines.join("\n"));
+    if let Some(first) = forecast.list.first() {
+        embed = embed.color(temperature_color(first.main.temp));
+    }
+    embed
+}
+
+// Day-by-day highs and lows for as many days as the provider returns
+pub fn daily_embed(forecast: &ForecastResponse, fmt: &UnitFormat) -> CreateEmbed {
+    let days = forecast.daily();
+    let mut embed = CreateEmbed::new().title(format!("Daily forecast for {}", forecast.city.name));
+    for day in days.iter().take(7) {
+        let condition = day
+            .condition
+            .as_ref()
+            .map(|c| c.description.clone())
+            .unwrap_or_default();
+        embed = embed.field(
+            day.date.format("%a %b %-d").to_string(),
+            format!(
+                "🔥 {} / 🧊 {}\n{} · 💧 {:.0}%",
+                day.temp_max.format(fmt),
+                day.temp_min.format(fmt),
+                condition,
+                day.pop * 100.0
+            ),
+            true,
+        );
+    }
+    if let Some(today) = days.first() {
+        embed = embed.color(temperature_color(today.temp_max));
+    }
+    embed
+}
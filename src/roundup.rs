@@ -0,0 +1,169 @@
+use crate::catalog;
+use crate::render;
+use crate::storage::JsonStore;
+use crate::units::UnitFormat;
+use chrono::{DateTime, Datelike, Duration, TimeZone, Utc};
+use poise::serenity_prelude as serenity;
+use serde::{Deserialize, Serialize};
+use serenity::{ChannelId, CreateMessage, Http};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// How often the scheduler checks whether a roundup is due
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum Day {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Day {
+    // Days after Monday
+    pub fn index(self) -> u32 {
+        self as u32
+    }
+}
+
+// Where and when a guild gets its weekly extremes roundup
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Schedule {
+    pub channel: u64,
+    // 0 = Monday
+    pub weekday: u32,
+    // Hour of the day, UTC
+    pub hour: u32,
+    #[serde(default)]
+    pub last_posted: i64,
+}
+
+impl Schedule {
+    // This week's posting time
+    fn slot(&self, now: DateTime<Utc>) -> i64 {
+        let monday = now.date_naive() - Duration::days(now.weekday().num_days_from_monday() as i64);
+        let day = monday + Duration::days(self.weekday as i64);
+        Utc.from_utc_datetime(&day.and_hms_opt(self.hour, 0, 0).unwrap())
+            .timestamp()
+    }
+
+    pub fn next(&self, now: DateTime<Utc>) -> i64 {
+        let slot = self.slot(now);
+        if slot > now.timestamp() {
+            slot
+        } else {
+            slot + 7 * 86400
+        }
+    }
+
+    fn is_due(&self, now: DateTime<Utc>) -> bool {
+        let slot = self.slot(now);
+        slot <= now.timestamp() && self.last_posted < slot
+    }
+}
+
+pub struct Roundups {
+    store: JsonStore<HashMap<u64, Schedule>>,
+}
+
+impl Roundups {
+    pub fn new() -> Roundups {
+        Roundups {
+            store: JsonStore::open("roundups"),
+        }
+    }
+
+    // Start the schedule from now so it doesn't fire straight away for this week
+    pub async fn set(&self, guild: u64, channel: u64, day: Day, hour: u32) -> Schedule {
+        let schedule = Schedule {
+            channel,
+            weekday: day.index(),
+            hour,
+            last_posted: Utc::now().timestamp(),
+        };
+        self.store
+            .update(|schedules| {
+                schedules.insert(guild, schedule);
+            })
+            .await;
+        schedule
+    }
+
+    // Returns whether there was a schedule to remove
+    pub async fn remove(&self, guild: u64) -> bool {
+        self.store
+            .update(|schedules| schedules.remove(&guild).is_some())
+            .await
+    }
+
+    // Schedules that are due, marked as posted so they only go out once
+    async fn take_due(&self, now: DateTime<Utc>) -> Vec<Schedule> {
+        self.store
+            .update(|schedules| {
+                schedules
+                    .values_mut()
+                    .filter(|s| s.is_due(now))
+                    .map(|s| {
+                        s.last_posted = now.timestamp();
+                        *s
+                    })
+                    .collect()
+            })
+            .await
+    }
+}
+
+impl Default for Roundups {
+    fn default() -> Self {
+        Roundups::new()
+    }
+}
+
+// Sweep the whole catalog and post the extremes to each channel
+async fn post(http: &Http, channels: &[u64]) {
+    let (observations, _) = catalog::sweep(catalog::filter(&catalog::Filter::default())).await;
+    let extremes = match catalog::extremes(&observations) {
+        Some(extremes) => extremes,
+        None => {
+            println!("Error: no weather data for the weekly roundup");
+            return;
+        }
+    };
+
+    let fmt = UnitFormat::default();
+    let checked = observations.len();
+    for &channel in channels {
+        let embed = render::extremes_embed(&extremes, checked, "the world", &fmt);
+        let channel = ChannelId::new(channel);
+        if let Err(e) = channel
+            .send_message(http, CreateMessage::new().embed(embed))
+            .await
+        {
+            // Probably can't embed there, try plain text
+            println!("Error posting roundup embed: {:?}", e);
+            let text = render::extremes_text(&extremes, checked, "the world", &fmt);
+            if let Err(e) = channel
+                .send_message(http, CreateMessage::new().content(text))
+                .await
+            {
+                println!("Error posting roundup: {:?}", e);
+            }
+        }
+    }
+}
+
+// Post roundups as they come due. Runs for the life of the bot.
+pub async fn run(roundups: Arc<Roundups>, http: Arc<Http>) {
+    loop {
+        let due = roundups.take_due(Utc::now()).await;
+        if !due.is_empty() {
+            let channels: Vec<u64> = due.iter().map(|s| s.channel).collect();
+            post(&http, &channels).await;
+        }
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
@@ -0,0 +1,70 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::PathBuf;
+use tokio::sync::Mutex;
+
+// A value kept in memory and saved to DATA_DIR/<name>.json after every change.
+// Good enough for the small amounts of per-guild state the bot keeps.
+pub struct JsonStore<T> {
+    path: PathBuf,
+    data: Mutex<T>,
+}
+
+// Where store files live, DATA_DIR or ./data
+fn data_dir() -> PathBuf {
+    PathBuf::from(std::env::var("DATA_DIR").unwrap_or_else(|_| "data".to_string()))
+}
+
+impl<T: Serialize + DeserializeOwned + Default> JsonStore<T> {
+    // Load the store, starting empty if the file doesn't exist yet
+    pub fn open(name: &str) -> JsonStore<T> {
+        let path = data_dir().join(format!("{}.json", name));
+        let data = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+                println!("Error reading {}, starting empty: {}", path.display(), e);
+                T::default()
+            }),
+            Err(_) => T::default(),
+        };
+        JsonStore {
+            path,
+            data: Mutex::new(data),
+        }
+    }
+
+    // An empty store in the temp dir, so tests don't touch DATA_DIR
+    #[cfg(test)]
+    pub fn scratch(name: &str) -> JsonStore<T> {
+        let file = format!("weatherbot-{}-{}.json", std::process::id(), name);
+        JsonStore {
+            path: std::env::temp_dir().join(file),
+            data: Mutex::new(T::default()),
+        }
+    }
+
+    pub async fn read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        let data = self.data.lock().await;
+        f(&data)
+    }
+
+    // Change the data and write it back to disk
+    pub async fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let mut data = self.data.lock().await;
+        let result = f(&mut data);
+        if let Err(e) = self.save(&data).await {
+            println!("Error saving {}: {}", self.path.display(), e);
+        }
+        result
+    }
+
+    // Write to a temp file and rename so a crash never leaves half a file
+    async fn save(&self, data: &T) -> std::io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let text = serde_json::to_string_pretty(data)?;
+        let tmp = self.path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, text).await?;
+        tokio::fs::rename(&tmp, &self.path).await
+    }
+}
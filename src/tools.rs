@@ -0,0 +1,364 @@
+use crate::chatbot::{ChatRequest, Llm, LlmError, Message, Tool, ToolCall};
+use crate::meteo;
+use crate::weather::{get_forecast_cached, get_weather_cached, ForecastResponse, WeatherResponse};
+use chrono::{TimeZone, Utc};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+// How many rounds of tool calls we allow before giving up on an answer
+const MAX_TOOL_ROUNDS: usize = 4;
+
+const SYSTEM_PROMPT: &str = "You are a weather assistant in a Discord server. \
+Use the provided tools to look up real data before answering; never guess numbers. \
+When you use a value from a tool, quote it with its unit (e.g. \"high of 78°F, 40% chance of rain\"). \
+If the tools can't answer the question, say so. Keep answers under 150 words.";
+
+// Functions the model may call to ground its answer
+pub fn definitions() -> Vec<Tool> {
+    let city = json!({
+        "type": "string",
+        "description": "City name, optionally with country, e.g. \"Charlotte\" or \"Paris, FR\""
+    });
+    vec![
+        Tool::function(
+            "get_weather",
+            "Current conditions for a city: temperature, feels like, humidity, wind, clouds, precipitation and visibility.",
+            json!({
+                "type": "object",
+                "properties": { "city": city },
+                "required": ["city"]
+            }),
+        ),
+        Tool::function(
+            "get_forecast",
+            "Forecast for a city in 3-hour steps for up to 5 days, with local times, temperature, chance of precipitation and wind.",
+            json!({
+                "type": "object",
+                "properties": {
+                    "city": city,
+                    "hours": { "type": "integer", "description": "How many hours ahead to return (3-120, default 24)" }
+                },
+                "required": ["city"]
+            }),
+        ),
+        Tool::function(
+            "get_astronomy",
+            "Sunrise, sunset, day length and moon phase for a city today.",
+            json!({
+                "type": "object",
+                "properties": { "city": city },
+                "required": ["city"]
+            }),
+        ),
+    ]
+}
+
+#[derive(Deserialize)]
+struct CityArgs {
+    city: String,
+    #[serde(default)]
+    hours: Option<usize>,
+}
+
+// Local time at a location as "2024-05-01 14:00"
+fn local_time(timestamp: i64, timezone: i32) -> String {
+    Utc.timestamp_opt(timestamp + timezone as i64, 0)
+        .single()
+        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default()
+}
+
+fn weather_json(weather: &WeatherResponse) -> Value {
+    let main = &weather.main;
+    json!({
+        "location": format!("{}, {}", weather.name, weather.sys.country),
+        "observed_local_time": local_time(weather.dt, weather.timezone),
+        "conditions": weather.condition().map(|c| c.description.clone()),
+        "temperature_f": round(main.temp.fahrenheit()),
+        "temperature_c": round(main.temp.celsius()),
+        "feels_like_f": round(main.feels_like.fahrenheit()),
+        "feels_like_c": round(main.feels_like.celsius()),
+        "dew_point_f": round(meteo::dew_point(main.temp, main.humidity).fahrenheit()),
+        "humidity_percent": main.humidity,
+        "wind_mph": round(weather.wind.speed.mph()),
+        "wind_kmh": round(weather.wind.speed.kmh()),
+        "wind_gust_mph": weather.wind.gust.map(|g| round(g.mph())),
+        "wind_direction": meteo::compass(weather.wind.deg as f64),
+        "cloud_cover_percent": weather.clouds.all,
+        "rain_recent_mm": weather.rain_recent().map(|r| round(r.mm())),
+        "snow_recent_mm": weather.snow_recent().map(|s| round(s.mm())),
+        "visibility_km": weather.visibility.map(|v| round(v.km())),
+    })
+}
+
+fn forecast_json(forecast: &ForecastResponse, hours: usize) -> Value {
+    let timezone = forecast.city.timezone;
+    let entries: Vec<Value> = forecast
+        .hourly(hours.clamp(3, 120))
+        .iter()
+        .map(|entry| {
+            json!({
+                "local_time": local_time(entry.dt, timezone),
+                "conditions": entry.weather.first().map(|c| c.description.clone()),
+                "temperature_f": round(entry.main.temp.fahrenheit()),
+                "temperature_c": round(entry.main.temp.celsius()),
+                "feels_like_f": round(entry.main.feels_like.fahrenheit()),
+                "precipitation_chance_percent": (entry.pop * 100.0).round(),
+                "wind_mph": round(entry.wind.speed.mph()),
+                "humidity_percent": entry.main.humidity,
+            })
+        })
+        .collect();
+    json!({
+        "location": format!("{}, {}", forecast.city.name, forecast.city.country),
+        "entries": entries,
+    })
+}
+
+// Fraction of the way through the lunar cycle (0 = new moon, 0.5 = full)
+fn moon_phase(timestamp: i64) -> (f64, &'static str) {
+    // Known new moon: 2000-01-06 18:14 UTC
+    let synodic_month = 29.530588853;
+    let days = (timestamp - 947182440) as f64 / 86400.0;
+    let phase = (days / synodic_month).rem_euclid(1.0);
+    let name = match phase {
+        p if !(0.03..0.97).contains(&p) => "New moon",
+        p if p < 0.22 => "Waxing crescent",
+        p if p < 0.28 => "First quarter",
+        p if p < 0.47 => "Waxing gibbous",
+        p if p < 0.53 => "Full moon",
+        p if p < 0.72 => "Waning gibbous",
+        p if p < 0.78 => "Last quarter",
+        _ => "Waning crescent",
+    };
+    (phase, name)
+}
+
+fn astronomy_json(weather: &WeatherResponse) -> Value {
+    let sunrise = weather.sys.sunrise as i64;
+    let sunset = weather.sys.sunset as i64;
+    let day_length = (sunset - sunrise).max(0);
+    let (phase, phase_name) = moon_phase(weather.dt);
+    json!({
+        "location": format!("{}, {}", weather.name, weather.sys.country),
+        "sunrise_local_time": local_time(sunrise, weather.timezone),
+        "sunset_local_time": local_time(sunset, weather.timezone),
+        "solar_noon_local_time": local_time(sunrise + day_length / 2, weather.timezone),
+        "day_length": format!("{}h {}m", day_length / 3600, (day_length % 3600) / 60),
+        "moon_phase": phase_name,
+        "moon_illumination_percent": round((1.0 - (phase * 2.0 * std::f64::consts::PI).cos()) / 2.0 * 100.0),
+    })
+}
+
+fn round(value: f64) -> f64 {
+    (value * 10.0).round() / 10.0
+}
+
+// Run one tool call and return its JSON result (errors are reported to the model, not raised)
+pub async fn execute(call: &ToolCall) -> Value {
+    let args: CityArgs = match serde_json::from_str(&call.function.arguments) {
+        Ok(args) => args,
+        Err(e) => return json!({ "error": format!("invalid arguments: {}", e) }),
+    };
+
+    let result = match call.function.name.as_str() {
+        "get_weather" => get_weather_cached(&args.city)
+            .await
+            .map(|w| weather_json(&w)),
+        "get_forecast" => get_forecast_cached(&args.city)
+            .await
+            .map(|f| forecast_json(&f, args.hours.unwrap_or(24))),
+        "get_astronomy" => get_weather_cached(&args.city)
+            .await
+            .map(|w| astronomy_json(&w)),
+        name => return json!({ "error": format!("unknown tool '{}'", name) }),
+    };
+
+    result.unwrap_or_else(|e| json!({ "error": format!("no data for '{}': {}", args.city, e) }))
+}
+
+// Answer a free-form question, letting the model call our weather tools.
+// Returns the answer and a list of the lookups it was based on.
+pub async fn answer(llm: &dyn Llm, question: &str) -> Result<(String, Vec<String>), LlmError> {
+    let mut messages = vec![Message::system(SYSTEM_PROMPT), Message::user(question)];
+    let mut sources = Vec::new();
+
+    for _ in 0..MAX_TOOL_ROUNDS {
+        let request = ChatRequest::new(messages.clone())
+            .max_tokens(400)
+            .tools(definitions());
+        let response = llm.complete(request).await?;
+        let message = match response.choices.into_iter().next() {
+            Some(choice) => choice.message,
+            None => return Err(LlmError::Empty),
+        };
+
+        if message.tool_calls.is_empty() {
+            let text = message.content.trim().to_string();
+            return if text.is_empty() {
+                Err(LlmError::Empty)
+            } else {
+                Ok((text, sources))
+            };
+        }
+
+        // Run every call the model asked for and hand the results back
+        messages.push(message.clone());
+        for call in &message.tool_calls {
+            let result = execute(call).await;
+            let args: Value = serde_json::from_str(&call.function.arguments).unwrap_or(Value::Null);
+            let city = args.get("city").and_then(|c| c.as_str()).unwrap_or("?");
+            sources.push(format!("{}({})", call.function.name, city));
+            messages.push(Message::tool(&call.id, result.to_string()));
+        }
+    }
+
+    Err(LlmError::TooManyToolCalls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chatbot::testing::{response, FakeLlm};
+    use crate::chatbot::FunctionCall;
+
+    fn call(name: &str, arguments: &str) -> ToolCall {
+        ToolCall {
+            id: format!("call-{}", name),
+            kind: "function".to_string(),
+            function: FunctionCall {
+                name: name.to_string(),
+                arguments: arguments.to_string(),
+            },
+        }
+    }
+
+    // The model asking for tools instead of answering
+    fn calling(calls: Vec<ToolCall>) -> crate::chatbot::OpenAIResponse {
+        let mut reply = response("", None);
+        reply.choices[0].message.tool_calls = calls;
+        reply
+    }
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32) -> i64 {
+        Utc.with_ymd_and_hms(y, m, d, h, min, 0)
+            .unwrap()
+            .timestamp()
+    }
+
+    #[test]
+    fn moon_phase_matches_known_new_moons() {
+        for new_moon in [
+            utc(2000, 1, 6, 18, 14),
+            utc(2024, 1, 11, 11, 57),
+            utc(2025, 3, 29, 10, 58),
+        ] {
+            let (phase, name) = moon_phase(new_moon);
+            assert!(!(0.02..0.98).contains(&phase), "{}", phase);
+            assert_eq!(name, "New moon");
+        }
+    }
+
+    #[test]
+    fn moon_phase_matches_known_full_moons() {
+        for full_moon in [
+            utc(2023, 8, 31, 1, 36),
+            utc(2024, 1, 25, 17, 54),
+            utc(2025, 10, 7, 3, 48),
+        ] {
+            let (phase, name) = moon_phase(full_moon);
+            assert!((phase - 0.5).abs() < 0.02, "{}", phase);
+            assert_eq!(name, "Full moon");
+        }
+    }
+
+    #[test]
+    fn moon_phase_works_before_the_reference() {
+        // The 1969 Moon landing was a few days after new moon
+        let (phase, name) = moon_phase(utc(1969, 7, 20, 20, 17));
+        assert!((0.03..0.22).contains(&phase), "{}", phase);
+        assert_eq!(name, "Waxing crescent");
+    }
+
+    #[tokio::test]
+    async fn execute_reports_bad_arguments() {
+        for arguments in [
+            "",
+            "not json",
+            "{}",
+            r#"{"city": 5}"#,
+            r#"{"town": "Oslo"}"#,
+        ] {
+            let result = execute(&call("get_weather", arguments)).await;
+            let error = result["error"].as_str().unwrap();
+            assert!(
+                error.starts_with("invalid arguments"),
+                "{}: {}",
+                arguments,
+                error
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn execute_reports_unknown_tools() {
+        let result = execute(&call("get_tides", r#"{"city": "Oslo"}"#)).await;
+        assert_eq!(result["error"], "unknown tool 'get_tides'");
+    }
+
+    #[tokio::test]
+    async fn answers_without_tools() {
+        let llm = FakeLlm::replying("  Pack an umbrella.  ");
+        let (text, sources) = answer(&llm, "Rain in Oslo?").await.unwrap();
+        assert_eq!(text, "Pack an umbrella.");
+        assert!(sources.is_empty());
+
+        let requests = llm.requests.lock().unwrap();
+        assert_eq!(requests[0].tools.len(), definitions().len());
+        assert_eq!(requests[0].messages[1].content, "Rain in Oslo?");
+    }
+
+    #[tokio::test]
+    async fn tool_results_go_back_to_the_model() {
+        let llm = FakeLlm::scripted(vec![
+            calling(vec![
+                call("get_tides", r#"{"city": "Oslo"}"#),
+                call("get_weather", "oops"),
+            ]),
+            response("No tide data, sorry.", None),
+        ]);
+        let (text, sources) = answer(&llm, "Tides in Oslo?").await.unwrap();
+        assert_eq!(text, "No tide data, sorry.");
+        assert_eq!(sources, vec!["get_tides(Oslo)", "get_weather(?)"]);
+
+        // The second request carries the calls and a result for each
+        let requests = llm.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        let messages = &requests[1].messages;
+        let roles: Vec<&str> = messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["system", "user", "assistant", "tool", "tool"]);
+        assert_eq!(messages[2].tool_calls.len(), 2);
+        assert_eq!(messages[3].tool_call_id.as_deref(), Some("call-get_tides"));
+        assert!(messages[3].content.contains("unknown tool"));
+        assert!(messages[4].content.contains("invalid arguments"));
+    }
+
+    #[tokio::test]
+    async fn endless_tool_calls_give_up() {
+        let script = (0..MAX_TOOL_ROUNDS + 1)
+            .map(|_| calling(vec![call("get_tides", r#"{"city": "Oslo"}"#)]))
+            .collect();
+        let llm = FakeLlm::scripted(script);
+        let result = answer(&llm, "Tides?").await;
+        assert!(matches!(result, Err(LlmError::TooManyToolCalls)));
+        assert_eq!(llm.requests.lock().unwrap().len(), MAX_TOOL_ROUNDS);
+    }
+
+    #[tokio::test]
+    async fn empty_answers_are_errors() {
+        let llm = FakeLlm::replying("   ");
+        assert!(matches!(answer(&llm, "Hi").await, Err(LlmError::Empty)));
+        let llm = FakeLlm::failing();
+        assert!(answer(&llm, "Hi").await.is_err());
+    }
+}
@@ -0,0 +1,327 @@
+use crate::gazetteer::Place;
+use crate::geo;
+use crate::weather::{get_forecast_at_cached, Coord, ForecastEntry};
+use chrono::{Duration, NaiveTime, TimeZone, Utc};
+use futures::stream::{self, StreamExt};
+
+// Most stops a single /trip can have
+pub const MAX_STOPS: usize = 8;
+// Most forecast lookups one trip may make
+const MAX_WAYPOINTS: usize = 12;
+// And how many of them run at once
+const CONCURRENT_LOOKUPS: usize = 4;
+// Check the weather about this often along the road
+const WAYPOINT_SPACING_KM: f64 = 150.0;
+// Legs longer than this are assumed to be flown
+const MAX_DRIVE_KM: f64 = 1000.0;
+
+// A point we'll pass through and when
+#[derive(Debug, Clone)]
+pub struct Waypoint {
+    pub coord: Coord,
+    // Set for the stops themselves, None for points along the way
+    pub stop: Option<String>,
+    // Distance from the start
+    pub km: f64,
+    // Unix time we expect to be there
+    pub eta: i64,
+    // Whether we got here by plane
+    pub flown: bool,
+}
+
+// Split the route into waypoints with estimated arrival times
+pub fn plan(stops: &[Place], depart: i64) -> Vec<Waypoint> {
+    let legs: Vec<f64> = stops
+        .windows(2)
+        .map(|pair| geo::haversine_km(pair[0].coord, pair[1].coord))
+        .collect();
+    let total: f64 = legs.iter().sum();
+    let spacing = WAYPOINT_SPACING_KM.max(total / (MAX_WAYPOINTS - 1) as f64);
+
+    let first = match stops.first() {
+        Some(first) => first,
+        None => return Vec::new(),
+    };
+    let mut waypoints = vec![Waypoint {
+        coord: first.coord,
+        stop: Some(first.name.clone()),
+        km: 0.0,
+        eta: depart,
+        flown: false,
+    }];
+    let (mut km, mut time) = (0.0, depart as f64);
+
+    for (pair, leg_km) in stops.windows(2).zip(legs) {
+        let flown = leg_km > MAX_DRIVE_KM;
+        let hours = if flown {
+            geo::flying_hours(leg_km)
+        } else {
+            geo::driving_hours(leg_km)
+        };
+
+        // Nothing to check on between airports
+        if !flown {
+            let segments = (leg_km / spacing).ceil().max(1.0) as usize;
+            for i in 1..segments {
+                let fraction = i as f64 / segments as f64;
+                waypoints.push(Waypoint {
+                    coord: geo::interpolate(pair[0].coord, pair[1].coord, fraction),
+                    stop: None,
+                    km: km + leg_km * fraction,
+                    eta: (time + hours * fraction * 3600.0) as i64,
+                    flown,
+                });
+            }
+        }
+
+        km += leg_km;
+        time += hours * 3600.0;
+        waypoints.push(Waypoint {
+            coord: pair[1].coord,
+            stop: Some(pair[1].name.clone()),
+            km,
+            eta: time as i64,
+            flown,
+        });
+    }
+    cap(waypoints)
+}
+
+// Rounding every leg up can plan a few more points than MAX_WAYPOINTS, so
+// spread out the ones along the way until it fits. Stops are always kept.
+fn cap(waypoints: Vec<Waypoint>) -> Vec<Waypoint> {
+    let stops = waypoints.iter().filter(|w| w.stop.is_some()).count();
+    let en_route = waypoints.len() - stops;
+    let room = MAX_WAYPOINTS.saturating_sub(stops);
+    if en_route <= room {
+        return waypoints;
+    }
+    // Keeps exactly `room` of them, evenly spaced
+    let mut seen = 0;
+    waypoints
+        .into_iter()
+        .filter(|w| {
+            if w.stop.is_some() {
+                return true;
+            }
+            let keep = (seen + 1) * room / en_route > seen * room / en_route;
+            seen += 1;
+            keep
+        })
+        .collect()
+}
+
+// What the forecast expects at a waypoint when we get there
+pub struct Report {
+    pub waypoint: Waypoint,
+    // Stop name, or the nearest place the weather API knows of
+    pub name: String,
+    // None when it's past the end of the forecast
+    pub entry: Option<ForecastEntry>,
+}
+
+pub async fn forecast(waypoints: Vec<Waypoint>) -> Vec<Report> {
+    // Collected first so the buffered stream's future is Send, and `buffered`
+    // keeps them in waypoint order
+    let lookups: Vec<_> = waypoints
+        .iter()
+        .map(|waypoint| get_forecast_at_cached(waypoint.coord))
+        .collect();
+    let forecasts: Vec<_> = stream::iter(lookups)
+        .buffered(CONCURRENT_LOOKUPS)
+        .collect()
+        .await;
+
+    waypoints
+        .into_iter()
+        .zip(forecasts)
+        .map(|(waypoint, forecast)| {
+            let forecast = forecast.ok();
+            let name = match (&waypoint.stop, &forecast) {
+                (Some(stop), _) => stop.clone(),
+                (None, Some(f)) if !f.city.name.is_empty() => format!("near {}", f.city.name),
+                _ => "en route".to_string(),
+            };
+            // Forecast steps are 3 hours apart, so the closest one is within 90 minutes
+            let entry = forecast.and_then(|f| {
+                f.list
+                    .into_iter()
+                    .min_by_key(|e| (e.dt - waypoint.eta).abs())
+                    .filter(|e| (e.dt - waypoint.eta).abs() <= 90 * 60)
+            });
+            Report {
+                waypoint,
+                name,
+                entry,
+            }
+        })
+        .collect()
+}
+
+// Departure time from "8am", "8:30pm", "14:00" or "tomorrow 9am", read as the
+// first stop's local time. A time that has already passed today means tomorrow.
+pub fn parse_depart(text: &str, utc_offset: i32, now: i64) -> Option<i64> {
+    let text = text.trim().to_lowercase();
+    if text.is_empty() || text == "now" {
+        return Some(now);
+    }
+    let (tomorrow, time) = match text.strip_prefix("tomorrow") {
+        Some(rest) => (true, rest.trim()),
+        None => (false, text.as_str()),
+    };
+    if tomorrow && time.is_empty() {
+        return Some(now + 24 * 3600);
+    }
+
+    let (time, meridiem) = if let Some(t) = time.strip_suffix("am") {
+        (t.trim(), Some(false))
+    } else if let Some(t) = time.strip_suffix("pm") {
+        (t.trim(), Some(true))
+    } else {
+        (time, None)
+    };
+    let (hour, minute) = match time.split_once(':') {
+        Some((h, m)) => (h.parse::<u32>().ok()?, m.parse::<u32>().ok()?),
+        None => (time.parse::<u32>().ok()?, 0),
+    };
+    let hour = match meridiem {
+        Some(_) if hour == 0 || hour > 12 => return None,
+        Some(true) if hour < 12 => hour + 12,
+        Some(false) if hour == 12 => 0,
+        _ => hour,
+    };
+    let time = NaiveTime::from_hms_opt(hour, minute, 0)?;
+
+    let local_now = Utc
+        .timestamp_opt(now + utc_offset as i64, 0)
+        .single()?
+        .naive_utc();
+    let mut depart = local_now.date().and_time(time);
+    if tomorrow || depart < local_now {
+        depart += Duration::days(1);
+    }
+    Some(Utc.from_utc_datetime(&depart).timestamp() - utc_offset as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gazetteer;
+
+    // 10:00 local on Sunday March 10 2024, five hours behind UTC
+    const OFFSET: i32 = -5 * 3600;
+
+    fn now() -> i64 {
+        utc(10, 15, 0)
+    }
+
+    fn utc(day: u32, hour: u32, minute: u32) -> i64 {
+        Utc.with_ymd_and_hms(2024, 3, day, hour, minute, 0)
+            .unwrap()
+            .timestamp()
+    }
+
+    #[test]
+    fn depart_defaults_to_now() {
+        assert_eq!(parse_depart("", OFFSET, now()), Some(now()));
+        assert_eq!(parse_depart(" Now ", OFFSET, now()), Some(now()));
+        assert_eq!(parse_depart("tomorrow", OFFSET, now()), Some(now() + 86400));
+    }
+
+    #[test]
+    fn depart_reads_twelve_hour_times() {
+        // Noon is still ahead today, midnight has passed so it's tonight's
+        assert_eq!(parse_depart("12pm", OFFSET, now()), Some(utc(10, 17, 0)));
+        assert_eq!(parse_depart("12am", OFFSET, now()), Some(utc(11, 5, 0)));
+        assert_eq!(parse_depart("8:30pm", OFFSET, now()), Some(utc(11, 1, 30)));
+        assert_eq!(parse_depart("11 AM", OFFSET, now()), Some(utc(10, 16, 0)));
+        assert_eq!(parse_depart("14:00", OFFSET, now()), Some(utc(10, 19, 0)));
+    }
+
+    #[test]
+    fn depart_times_already_passed_mean_tomorrow() {
+        assert_eq!(parse_depart("8am", OFFSET, now()), Some(utc(11, 13, 0)));
+        assert_eq!(parse_depart("9:59", OFFSET, now()), Some(utc(11, 14, 59)));
+        // Right now is still today
+        assert_eq!(parse_depart("10am", OFFSET, now()), Some(now()));
+    }
+
+    #[test]
+    fn depart_tomorrow_skips_a_day() {
+        assert_eq!(
+            parse_depart("tomorrow 9am", OFFSET, now()),
+            Some(utc(11, 14, 0))
+        );
+        // Even when the time is still ahead today
+        assert_eq!(
+            parse_depart("Tomorrow 11am", OFFSET, now()),
+            Some(utc(11, 16, 0))
+        );
+    }
+
+    #[test]
+    fn depart_rejects_nonsense() {
+        for text in [
+            "13pm",
+            "0am",
+            "25:00",
+            "8:75",
+            "noon",
+            "8 oclock",
+            "tomorrow-ish",
+            ":30",
+        ] {
+            assert_eq!(parse_depart(text, OFFSET, now()), None, "{}", text);
+        }
+    }
+
+    fn stops(names: &[&str]) -> Vec<Place> {
+        names
+            .iter()
+            .map(|name| Place::from(gazetteer::find(name).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn long_drives_stay_within_the_lookup_limit() {
+        // Eight stops of a few hundred km each would round up past the limit
+        let route = stops(&[
+            "Charlotte",
+            "Atlanta",
+            "Nashville",
+            "Chicago",
+            "Detroit",
+            "Toronto",
+            "Montreal",
+            "Boston",
+        ]);
+        let waypoints = plan(&route, 0);
+        assert!(waypoints.len() <= MAX_WAYPOINTS);
+        let kept: Vec<_> = waypoints.iter().filter_map(|w| w.stop.clone()).collect();
+        assert_eq!(kept.len(), route.len());
+        // Still in order along the road
+        assert!(waypoints.windows(2).all(|pair| pair[0].km <= pair[1].km));
+    }
+
+    #[test]
+    fn cap_spreads_out_the_points_it_keeps() {
+        let point = |km: f64, stop: bool| Waypoint {
+            coord: Coord { lat: 0.0, lon: 0.0 },
+            stop: stop.then(|| "stop".to_string()),
+            km,
+            eta: 0,
+            flown: false,
+        };
+        let mut waypoints = vec![point(0.0, true)];
+        waypoints.extend((1..=20).map(|km| point(km as f64, false)));
+        waypoints.push(point(21.0, true));
+
+        let capped = cap(waypoints);
+        assert_eq!(capped.len(), MAX_WAYPOINTS);
+        let kms: Vec<f64> = capped.iter().map(|w| w.km).collect();
+        assert_eq!(
+            kms,
+            vec![0.0, 2.0, 4.0, 6.0, 8.0, 10.0, 12.0, 14.0, 16.0, 18.0, 20.0, 21.0]
+        );
+    }
+}
@@ -0,0 +1,407 @@
+use serde::{Deserialize, Serialize};
+
+// Which set of units to show values in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UnitSystem {
+    // °F, inHg, mph, miles, inches
+    Imperial,
+    // °C, hPa, km/h, kilometers, millimeters
+    Metric,
+    // K, kPa, m/s, meters, millimeters
+    Scientific,
+}
+
+impl UnitSystem {
+    // System the unit toggle switches to
+    pub fn next(self) -> UnitSystem {
+        match self {
+            UnitSystem::Imperial => UnitSystem::Metric,
+            UnitSystem::Metric => UnitSystem::Scientific,
+            UnitSystem::Scientific => UnitSystem::Imperial,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            UnitSystem::Imperial => "imperial",
+            UnitSystem::Metric => "metric",
+            UnitSystem::Scientific => "scientific",
+        }
+    }
+
+    // Parse user input like "metric", "f", "celsius"
+    pub fn parse(input: &str) -> Option<UnitSystem> {
+        match input.trim().to_lowercase().as_str() {
+            "imperial" | "us" | "f" | "fahrenheit" => Some(UnitSystem::Imperial),
+            "metric" | "si" | "c" | "celsius" => Some(UnitSystem::Metric),
+            "scientific" | "k" | "kelvin" => Some(UnitSystem::Scientific),
+            _ => None,
+        }
+    }
+}
+
+// Unit system plus locale details needed to print a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnitFormat {
+    pub system: UnitSystem,
+    decimal_comma: bool,
+}
+
+impl UnitFormat {
+    // `locale` is a Discord locale such as "en-US" or "de"
+    pub fn new(system: UnitSystem, locale: Option<&str>) -> UnitFormat {
+        UnitFormat {
+            system,
+            decimal_comma: locale.map(uses_decimal_comma).unwrap_or(false),
+        }
+    }
+
+    pub fn with_system(self, system: UnitSystem) -> UnitFormat {
+        UnitFormat { system, ..self }
+    }
+
+    // Format a number with the locale's decimal separator
+    pub fn number(&self, value: f64, precision: usize) -> String {
+        let text = format!("{:.*}", precision, value);
+        if self.decimal_comma {
+            text.replace('.', ",")
+        } else {
+            text
+        }
+    }
+}
+
+impl Default for UnitFormat {
+    fn default() -> Self {
+        UnitFormat::new(UnitSystem::Imperial, None)
+    }
+}
+
+// Languages that write 1,5 instead of 1.5
+const DECIMAL_COMMA_LANGUAGES: &[&str] = &[
+    "bg", "cs", "da", "de", "el", "es", "fi", "fr", "hr", "hu", "id", "it", "lt", "nl", "no", "pl",
+    "pt", "ro", "ru", "sv", "tr", "uk", "vi",
+];
+
+fn uses_decimal_comma(locale: &str) -> bool {
+    let language = locale.split('-').next().unwrap_or(locale);
+    DECIMAL_COMMA_LANGUAGES.contains(&language)
+}
+
+// Temperature, stored in Kelvin (what the API returns)
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Temperature(f64);
+
+impl Temperature {
+    pub fn from_kelvin(kelvin: f64) -> Temperature {
+        Temperature(kelvin)
+    }
+
+    pub fn from_celsius(celsius: f64) -> Temperature {
+        Temperature(celsius + 273.15)
+    }
+
+    pub fn from_fahrenheit(fahrenheit: f64) -> Temperature {
+        Temperature((fahrenheit - 32.0) * (5.0 / 9.0) + 273.15)
+    }
+
+    pub fn kelvin(self) -> f64 {
+        self.0
+    }
+
+    pub fn celsius(self) -> f64 {
+        self.0 - 273.15
+    }
+
+    pub fn fahrenheit(self) -> f64 {
+        (self.0 - 273.15) * (9.0 / 5.0) + 32.0
+    }
+
+    // Value in the given system (°F, °C or K)
+    pub fn value(self, system: UnitSystem) -> f64 {
+        match system {
+            UnitSystem::Imperial => self.fahrenheit(),
+            UnitSystem::Metric => self.celsius(),
+            UnitSystem::Scientific => self.kelvin(),
+        }
+    }
+
+    pub fn symbol(system: UnitSystem) -> &'static str {
+        match system {
+            UnitSystem::Imperial => "°F",
+            UnitSystem::Metric => "°C",
+            UnitSystem::Scientific => "K",
+        }
+    }
+
+    pub fn format(self, fmt: &UnitFormat) -> String {
+        format!(
+            "{}{}",
+            fmt.number(self.value(fmt.system), 1),
+            Temperature::symbol(fmt.system)
+        )
+    }
+
+    // Read "72", "72F", "22 °C" or "295K". A bare number is in `default` units.
+    pub fn parse(text: &str, default: UnitSystem) -> Option<Temperature> {
+        let text = text.trim().to_lowercase();
+        let (number, system) = if let Some(n) = text.strip_suffix('f') {
+            (n, UnitSystem::Imperial)
+        } else if let Some(n) = text.strip_suffix('c') {
+            (n, UnitSystem::Metric)
+        } else if let Some(n) = text.strip_suffix('k') {
+            (n, UnitSystem::Scientific)
+        } else {
+            (text.as_str(), default)
+        };
+        let value: f64 = number.trim().trim_end_matches('°').trim().parse().ok()?;
+        if !value.is_finite() {
+            return None;
+        }
+        Some(match system {
+            UnitSystem::Imperial => Temperature::from_fahrenheit(value),
+            UnitSystem::Metric => Temperature::from_celsius(value),
+            UnitSystem::Scientific => Temperature::from_kelvin(value),
+        })
+    }
+
+    pub fn min(self, other: Temperature) -> Temperature {
+        Temperature(self.0.min(other.0))
+    }
+
+    pub fn max(self, other: Temperature) -> Temperature {
+        Temperature(self.0.max(other.0))
+    }
+}
+
+// Atmospheric pressure, stored in hectopascals (what the API returns)
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Pressure(f64);
+
+impl Pressure {
+    pub fn from_hpa(hpa: f64) -> Pressure {
+        Pressure(hpa)
+    }
+
+    pub fn from_inhg(inhg: f64) -> Pressure {
+        Pressure(inhg / 0.02953)
+    }
+
+    pub fn hpa(self) -> f64 {
+        self.0
+    }
+
+    pub fn kpa(self) -> f64 {
+        self.0 / 10.0
+    }
+
+    pub fn inhg(self) -> f64 {
+        self.0 * 0.02953
+    }
+
+    pub fn format(self, fmt: &UnitFormat) -> String {
+        match fmt.system {
+            UnitSystem::Imperial => format!("{} inHg", fmt.number(self.inhg(), 2)),
+            UnitSystem::Metric => format!("{} hPa", fmt.number(self.hpa(), 0)),
+            UnitSystem::Scientific => format!("{} kPa", fmt.number(self.kpa(), 1)),
+        }
+    }
+}
+
+// Speed, stored in meters per second (what the API returns)
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Speed(f64);
+
+impl Speed {
+    pub fn from_meters_per_sec(mps: f64) -> Speed {
+        Speed(mps)
+    }
+
+    pub fn from_kmh(kmh: f64) -> Speed {
+        Speed(kmh / 3.6)
+    }
+
+    pub fn from_mph(mph: f64) -> Speed {
+        Speed(mph / 2.23694)
+    }
+
+    pub fn meters_per_sec(self) -> f64 {
+        self.0
+    }
+
+    pub fn kmh(self) -> f64 {
+        self.0 * 3.6
+    }
+
+    pub fn mph(self) -> f64 {
+        self.0 * 2.23694
+    }
+
+    pub fn format(self, fmt: &UnitFormat) -> String {
+        match fmt.system {
+            UnitSystem::Imperial => format!("{} mph", fmt.number(self.mph(), 1)),
+            UnitSystem::Metric => format!("{} km/h", fmt.number(self.kmh(), 1)),
+            UnitSystem::Scientific => format!("{} m/s", fmt.number(self.meters_per_sec(), 1)),
+        }
+    }
+}
+
+// Distance, stored in meters
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Distance(f64);
+
+impl Distance {
+    pub fn from_meters(meters: f64) -> Distance {
+        Distance(meters)
+    }
+
+    pub fn from_km(km: f64) -> Distance {
+        Distance(km * 1000.0)
+    }
+
+    pub fn from_miles(miles: f64) -> Distance {
+        Distance(miles / 0.621371 * 1000.0)
+    }
+
+    pub fn meters(self) -> f64 {
+        self.0
+    }
+
+    pub fn km(self) -> f64 {
+        self.0 / 1000.0
+    }
+
+    pub fn miles(self) -> f64 {
+        self.km() * 0.621371
+    }
+
+    pub fn nautical_miles(self) -> f64 {
+        self.0 / 1852.0
+    }
+
+    pub fn format(self, fmt: &UnitFormat) -> String {
+        match fmt.system {
+            UnitSystem::Imperial => format!("{} mi", fmt.number(self.miles(), 2)),
+            UnitSystem::Metric => format!("{} km", fmt.number(self.km(), 2)),
+            UnitSystem::Scientific => format!("{} m", fmt.number(self.meters(), 0)),
+        }
+    }
+}
+
+// Precipitation depth, stored in millimeters (what the API returns)
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Precipitation(f64);
+
+impl Precipitation {
+    pub fn from_mm(mm: f64) -> Precipitation {
+        Precipitation(mm)
+    }
+
+    pub fn from_inches(inches: f64) -> Precipitation {
+        Precipitation(inches * 25.4)
+    }
+
+    pub fn mm(self) -> f64 {
+        self.0
+    }
+
+    pub fn inches(self) -> f64 {
+        self.0 / 25.4
+    }
+
+    pub fn format(self, fmt: &UnitFormat) -> String {
+        match fmt.system {
+            UnitSystem::Imperial => format!("{} in", fmt.number(self.inches(), 2)),
+            UnitSystem::Metric | UnitSystem::Scientific => {
+                format!("{} mm", fmt.number(self.mm(), 1))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // Relative tolerance, conversions go through a couple of multiplications
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() <= 1e-9 * a.abs().max(b.abs()).max(1.0)
+    }
+
+    proptest! {
+        #[test]
+        fn temperature_round_trips(celsius in -100.0f64..100.0) {
+            let t = Temperature::from_celsius(celsius);
+            prop_assert!(close(Temperature::from_fahrenheit(t.fahrenheit()).celsius(), celsius));
+            prop_assert!(close(Temperature::from_kelvin(t.kelvin()).celsius(), celsius));
+            for system in [UnitSystem::Imperial, UnitSystem::Metric, UnitSystem::Scientific] {
+                let text = format!("{}", t.value(system));
+                let parsed = Temperature::parse(&text, system).unwrap();
+                prop_assert!(close(parsed.kelvin(), t.kelvin()));
+            }
+        }
+
+        #[test]
+        fn speed_round_trips(mps in 0.0f64..150.0) {
+            let s = Speed::from_meters_per_sec(mps);
+            prop_assert!(close(Speed::from_kmh(s.kmh()).meters_per_sec(), mps));
+            prop_assert!(close(Speed::from_mph(s.mph()).meters_per_sec(), mps));
+        }
+
+        #[test]
+        fn pressure_round_trips(hpa in 850.0f64..1100.0) {
+            let p = Pressure::from_hpa(hpa);
+            prop_assert!(close(Pressure::from_inhg(p.inhg()).hpa(), hpa));
+            prop_assert!(close(p.kpa() * 10.0, hpa));
+        }
+
+        #[test]
+        fn distance_round_trips(meters in 0.0f64..50_000_000.0) {
+            let d = Distance::from_meters(meters);
+            prop_assert!(close(Distance::from_km(d.km()).meters(), meters));
+            prop_assert!(close(Distance::from_miles(d.miles()).meters(), meters));
+            // A nautical mile is exactly 1852 m
+            prop_assert!(close(d.nautical_miles() * 1852.0, meters));
+        }
+
+        #[test]
+        fn precipitation_round_trips(mm in 0.0f64..500.0) {
+            let p = Precipitation::from_mm(mm);
+            prop_assert!(close(Precipitation::from_inches(p.inches()).mm(), mm));
+        }
+
+        #[test]
+        fn parse_never_panics(text in "\\PC*") {
+            let _ = Temperature::parse(&text, UnitSystem::Metric);
+        }
+    }
+
+    #[test]
+    fn known_temperatures() {
+        assert!(close(Temperature::from_celsius(100.0).fahrenheit(), 212.0));
+        assert!(close(Temperature::from_fahrenheit(-40.0).celsius(), -40.0));
+        assert!(close(Temperature::from_kelvin(273.15).celsius(), 0.0));
+    }
+
+    #[test]
+    fn parse_suffixes() {
+        let parse = |text| Temperature::parse(text, UnitSystem::Imperial).map(|t| t.celsius());
+        assert!(close(parse("22C").unwrap(), 22.0));
+        assert!(close(parse("22 °c").unwrap(), 22.0));
+        let seventy_two = Temperature::from_fahrenheit(72.0).celsius();
+        assert!(close(parse("72").unwrap(), seventy_two));
+        assert!(close(parse(" 72°F ").unwrap(), seventy_two));
+        assert!(close(parse("295K").unwrap(), 295.0 - 273.15));
+        assert!(close(parse("-5c").unwrap(), -5.0));
+        assert_eq!(parse("warm"), None);
+        assert_eq!(parse("NaN"), None);
+        assert_eq!(parse("inf"), None);
+        assert_eq!(parse(""), None);
+    }
+}
@@ -0,0 +1,456 @@
+use crate::chatbot::{
+    Capabilities, ChatRequest, Llm, LlmError, OpenAIResponse, StreamEvent, TextStream,
+};
+use crate::storage::JsonStore;
+use crate::Context;
+use chrono::{Duration as ChronoDuration, NaiveDate, TimeZone, Utc};
+use futures::StreamExt;
+use poise::serenity_prelude as serenity;
+use serde::{Deserialize, Serialize};
+use serenity::async_trait;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+// Daily token budgets an admin has set for a guild (None = use the default)
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Limits {
+    pub user_daily: Option<u64>,
+    pub guild_daily: Option<u64>,
+}
+
+// Token counts for one user or guild
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Counter {
+    pub tokens: u64,
+    pub requests: u64,
+}
+
+impl Counter {
+    fn add(&mut self, tokens: u64) {
+        self.tokens += tokens;
+        self.requests += 1;
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UsageData {
+    // UTC day the daily counters belong to
+    pub day: Option<NaiveDate>,
+    pub users_today: HashMap<u64, Counter>,
+    pub guilds_today: HashMap<u64, Counter>,
+    // Running totals per guild, never reset
+    pub guilds_total: HashMap<u64, Counter>,
+    pub limits: HashMap<u64, Limits>,
+}
+
+impl UsageData {
+    // Start fresh counters when the UTC day changes
+    fn roll_over(&mut self) {
+        let today = Utc::now().date_naive();
+        if self.day != Some(today) {
+            self.day = Some(today);
+            self.users_today.clear();
+            self.guilds_today.clear();
+        }
+    }
+}
+
+// Refusal when someone has used up their share for the day
+#[derive(Debug)]
+pub struct BudgetExhausted {
+    guild_wide: bool,
+    limit: u64,
+}
+
+impl fmt::Display for BudgetExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let whose = if self.guild_wide {
+            "This server has"
+        } else {
+            "You've"
+        };
+        write!(
+            f,
+            "{} used today's AI budget ({} tokens). It resets <t:{}:R>.",
+            whose,
+            self.limit,
+            next_reset()
+        )
+    }
+}
+
+// Unix time of the next UTC midnight, when daily counters reset
+pub fn next_reset() -> i64 {
+    let tomorrow = Utc::now().date_naive() + ChronoDuration::days(1);
+    Utc.from_utc_datetime(&tomorrow.and_hms_opt(0, 0, 0).unwrap())
+        .timestamp()
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+// Records LLM token usage and enforces the daily budgets
+pub struct UsageTracker {
+    store: JsonStore<UsageData>,
+    default_user_daily: u64,
+    default_guild_daily: u64,
+    // Dollars per 1000 tokens, used for the cost estimate in /usage
+    pub cost_per_1k: f64,
+}
+
+impl UsageTracker {
+    // Defaults come from USER_DAILY_TOKENS, GUILD_DAILY_TOKENS and LLM_COST_PER_1K_TOKENS
+    pub fn new() -> UsageTracker {
+        UsageTracker {
+            store: JsonStore::open("usage"),
+            default_user_daily: env_or("USER_DAILY_TOKENS", 5_000),
+            default_guild_daily: env_or("GUILD_DAILY_TOKENS", 50_000),
+            cost_per_1k: env_or("LLM_COST_PER_1K_TOKENS", 0.002),
+        }
+    }
+
+    // Budgets in effect for a guild (DMs use the defaults)
+    pub async fn limits(&self, guild: Option<u64>) -> (u64, u64) {
+        let limits = match guild {
+            Some(guild) => self
+                .store
+                .read(|data| data.limits.get(&guild).copied())
+                .await
+                .unwrap_or_default(),
+            None => Limits::default(),
+        };
+        (
+            limits.user_daily.unwrap_or(self.default_user_daily),
+            limits.guild_daily.unwrap_or(self.default_guild_daily),
+        )
+    }
+
+    pub async fn set_limits(&self, guild: u64, limits: Limits) {
+        self.store
+            .update(|data| {
+                data.limits.insert(guild, limits);
+            })
+            .await;
+    }
+
+    // Ok if both the user and their guild still have budget left today
+    pub async fn check(&self, user: u64, guild: Option<u64>) -> Result<(), BudgetExhausted> {
+        let (user_limit, guild_limit) = self.limits(guild).await;
+        self.store
+            .update(|data| {
+                data.roll_over();
+                let used = data.users_today.get(&user).map_or(0, |c| c.tokens);
+                if used >= user_limit {
+                    return Err(BudgetExhausted {
+                        guild_wide: false,
+                        limit: user_limit,
+                    });
+                }
+                if let Some(guild) = guild {
+                    let used = data.guilds_today.get(&guild).map_or(0, |c| c.tokens);
+                    if used >= guild_limit {
+                        return Err(BudgetExhausted {
+                            guild_wide: true,
+                            limit: guild_limit,
+                        });
+                    }
+                }
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn record(&self, user: u64, guild: Option<u64>, tokens: u64) {
+        self.store
+            .update(|data| {
+                data.roll_over();
+                data.users_today.entry(user).or_default().add(tokens);
+                if let Some(guild) = guild {
+                    data.guilds_today.entry(guild).or_default().add(tokens);
+                    data.guilds_total.entry(guild).or_default().add(tokens);
+                }
+            })
+            .await;
+    }
+
+    // (user today, guild today, guild all time)
+    pub async fn report(&self, user: u64, guild: Option<u64>) -> (Counter, Counter, Counter) {
+        self.store
+            .read(|data| {
+                // Counters from an earlier day are only cleared on the next write
+                let today = data.day == Some(Utc::now().date_naive());
+                let counter = |counters: &HashMap<u64, Counter>, id: u64| {
+                    if today {
+                        counters.get(&id).copied().unwrap_or_default()
+                    } else {
+                        Counter::default()
+                    }
+                };
+                let user_today = counter(&data.users_today, user);
+                let (guild_today, guild_total) = match guild {
+                    Some(guild) => (
+                        counter(&data.guilds_today, guild),
+                        data.guilds_total.get(&guild).copied().unwrap_or_default(),
+                    ),
+                    None => Default::default(),
+                };
+                (user_today, guild_today, guild_total)
+            })
+            .await
+    }
+
+    pub fn cost(&self, tokens: u64) -> f64 {
+        tokens as f64 / 1000.0 * self.cost_per_1k
+    }
+}
+
+impl Default for UsageTracker {
+    fn default() -> Self {
+        UsageTracker::new()
+    }
+}
+
+// An Llm that charges every completion to a user and guild
+pub struct MeteredLlm {
+    inner: Arc<dyn Llm>,
+    tracker: Arc<UsageTracker>,
+    user: u64,
+    guild: Option<u64>,
+}
+
+// Check the caller's budget and hand back an Llm that records what they use
+pub async fn metered(ctx: Context<'_>) -> Result<MeteredLlm, BudgetExhausted> {
+    let data = ctx.data();
+    let user = ctx.author().id.get();
+    let guild = ctx.guild_id().map(|g| g.get());
+    data.usage.check(user, guild).await?;
+    Ok(MeteredLlm {
+        inner: data.llm.clone(),
+        tracker: data.usage.clone(),
+        user,
+        guild,
+    })
+}
+
+// Rough token count for text we don't have a usage block for (~4 characters per token)
+fn estimate_tokens(text: &str) -> u64 {
+    (text.chars().count() as u64).div_ceil(4)
+}
+
+// Records the usage of a streamed reply once the stream is dropped
+struct StreamMeter {
+    tracker: Arc<UsageTracker>,
+    user: u64,
+    guild: Option<u64>,
+    prompt_tokens: u64,
+    completion: String,
+    // The server's count, if the stream got far enough to send it
+    usage: Option<u64>,
+}
+
+impl Drop for StreamMeter {
+    fn drop(&mut self) {
+        let tokens = match self.usage {
+            Some(tokens) if tokens > 0 => tokens,
+            _ => self.prompt_tokens + estimate_tokens(&self.completion),
+        };
+        let (tracker, user, guild) = (self.tracker.clone(), self.user, self.guild);
+        tokio::spawn(async move { tracker.record(user, guild, tokens).await });
+    }
+}
+
+#[async_trait]
+impl Llm for MeteredLlm {
+    async fn complete(&self, request: ChatRequest) -> Result<OpenAIResponse, LlmError> {
+        let prompt_tokens = request
+            .messages
+            .iter()
+            .map(|m| estimate_tokens(&m.content))
+            .sum::<u64>();
+        let response = self.inner.complete(request).await?;
+
+        // Prefer the server's own count, not every local server sends one
+        let tokens = match &response.usage {
+            Some(usage) if usage.total_tokens > 0 => usage.total_tokens,
+            _ => prompt_tokens + response.text().map_or(0, |t| estimate_tokens(&t)),
+        };
+        self.tracker.record(self.user, self.guild, tokens).await;
+        Ok(response)
+    }
+
+    // Charged by the server's count from the end of the stream, or an
+    // estimate if it never came (older servers, or the reply was cut short)
+    async fn stream(&self, request: ChatRequest) -> Result<TextStream, LlmError> {
+        let prompt_tokens = request
+            .messages
+            .iter()
+            .map(|m| estimate_tokens(&m.content))
+            .sum::<u64>();
+        let pieces = self.inner.stream(request).await?;
+
+        let mut meter = StreamMeter {
+            tracker: self.tracker.clone(),
+            user: self.user,
+            guild: self.guild,
+            prompt_tokens,
+            completion: String::new(),
+            usage: None,
+        };
+        Ok(Box::pin(pieces.map(move |piece| {
+            match &piece {
+                Ok(StreamEvent::Text(text)) => meter.completion.push_str(text),
+                Ok(StreamEvent::Usage(usage)) => meter.usage = Some(usage.total_tokens),
+                Err(_) => {}
+            }
+            piece
+        })))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chatbot::testing::FakeLlm;
+    use crate::chatbot::{ChatRequest, Message, Usage};
+
+    fn tracker(name: &str) -> Arc<UsageTracker> {
+        Arc::new(UsageTracker {
+            store: JsonStore::scratch(name),
+            default_user_daily: 1_000,
+            default_guild_daily: 10_000,
+            cost_per_1k: 0.002,
+        })
+    }
+
+    fn metered(llm: FakeLlm, tracker: &Arc<UsageTracker>) -> MeteredLlm {
+        MeteredLlm {
+            inner: Arc::new(llm),
+            tracker: tracker.clone(),
+            user: 1,
+            guild: Some(2),
+        }
+    }
+
+    fn request(prompt: &str) -> ChatRequest {
+        ChatRequest::new(vec![Message::user(prompt)])
+    }
+
+    #[tokio::test]
+    async fn complete_records_the_servers_count() {
+        let tracker = tracker("server-count");
+        let mut llm = FakeLlm::replying("Sunny");
+        llm.usage = Some(Usage {
+            prompt_tokens: 30,
+            completion_tokens: 12,
+            total_tokens: 42,
+        });
+        metered(llm, &tracker)
+            .complete(request("hi"))
+            .await
+            .unwrap();
+
+        let (user, guild, total) = tracker.report(1, Some(2)).await;
+        assert_eq!((user.tokens, user.requests), (42, 1));
+        assert_eq!(guild.tokens, 42);
+        assert_eq!(total.tokens, 42);
+    }
+
+    #[tokio::test]
+    async fn complete_estimates_without_a_usage_block() {
+        let tracker = tracker("estimate");
+        // 8 characters of prompt and 12 of reply, ~4 characters a token
+        let llm = FakeLlm::replying("Rain all day");
+        metered(llm, &tracker)
+            .complete(request("forecast"))
+            .await
+            .unwrap();
+
+        let (user, _, _) = tracker.report(1, Some(2)).await;
+        assert_eq!(user.tokens, 2 + 3);
+    }
+
+    #[tokio::test]
+    async fn streams_are_charged_once_dropped() {
+        let tracker = tracker("stream");
+        let llm = metered(FakeLlm::streaming(&["Clear ", "skies"]), &tracker);
+        let pieces: Vec<_> = llm
+            .stream(request("forecast"))
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(pieces.len(), 2);
+
+        // The meter records from a spawned task when the stream is dropped
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let (user, _, _) = tracker.report(1, Some(2)).await;
+        assert_eq!((user.tokens, user.requests), (2 + 3, 1));
+    }
+
+    #[tokio::test]
+    async fn streams_prefer_the_servers_count() {
+        let tracker = tracker("stream-usage");
+        let mut llm = FakeLlm::streaming(&["Clear ", "skies"]);
+        llm.usage = Some(Usage {
+            prompt_tokens: 20,
+            completion_tokens: 4,
+            total_tokens: 24,
+        });
+        let pieces: Vec<_> = metered(llm, &tracker)
+            .stream(request("forecast"))
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(pieces.len(), 3);
+
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let (user, _, _) = tracker.report(1, Some(2)).await;
+        assert_eq!((user.tokens, user.requests), (24, 1));
+    }
+
+    #[tokio::test]
+    async fn failed_requests_are_not_charged() {
+        let tracker = tracker("failed");
+        let result = metered(FakeLlm::failing(), &tracker)
+            .complete(request("hi"))
+            .await;
+        assert!(result.is_err());
+        let (user, _, _) = tracker.report(1, Some(2)).await;
+        assert_eq!(user.requests, 0);
+    }
+
+    #[tokio::test]
+    async fn yesterdays_counts_are_not_reported() {
+        let tracker = tracker("yesterday");
+        tracker.record(1, Some(2), 500).await;
+        tracker
+            .store
+            .update(|data| data.day = data.day.and_then(|d| d.pred_opt()))
+            .await;
+
+        let (user, guild, total) = tracker.report(1, Some(2)).await;
+        assert_eq!((user.tokens, guild.tokens), (0, 0));
+        assert_eq!(total.tokens, 500);
+    }
+
+    #[tokio::test]
+    async fn budgets_are_enforced() {
+        let tracker = tracker("budget");
+        assert!(tracker.check(1, Some(2)).await.is_ok());
+        tracker.record(1, Some(2), 1_000).await;
+        assert!(tracker.check(1, Some(2)).await.is_err());
+        // Someone else in the same guild still has room
+        assert!(tracker.check(3, Some(2)).await.is_ok());
+    }
+}
@@ -1,64 +1,80 @@
-use reqwest;
 use reqwest::header;
-use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use std::env;
-use rand::{Rng, thread_rng};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, FixedOffset, NaiveDate, TimeZone, Utc};
+use crate::units::{Distance, Precipitation, Pressure, Speed, Temperature};
 
-#[derive(Debug, Deserialize)]
-#[allow(dead_code)]
+// Models for the current conditions payload. Optional sections (rain, snow,
+// gusts, ...) have defaults so a response without them still parses, but the
+// location and temperature are required so an error body isn't read as a
+// report at 0 K.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
 pub struct Coord {
     pub lon: f64,
     pub lat: f64,
 }
 
-#[derive(Debug, Deserialize)]
-#[allow(dead_code)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Weather {
-    id: u32,
-    main: String,
-    description: String,
-    icon: String,
+    pub id: u32,
+    pub main: String,
+    pub description: String,
+    pub icon: String,
 }
 
-#[derive(Debug, Deserialize)]
-#[allow(dead_code)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Main {
-    pub temp: f64,
-    pub feels_like: f64,
-    pub temp_min: f64,
-    pub temp_max: f64,
-    pub pressure: u32,
+    pub temp: Temperature,
+    #[serde(default)]
+    pub feels_like: Temperature,
+    #[serde(default)]
+    pub temp_min: Temperature,
+    #[serde(default)]
+    pub temp_max: Temperature,
+    #[serde(default)]
+    pub pressure: Pressure,
+    #[serde(default)]
     pub humidity: u32,
+    #[serde(default)]
+    pub sea_level: Option<Pressure>,
+    #[serde(default)]
+    pub grnd_level: Option<Pressure>,
 }
 
-#[derive(Debug, Deserialize)]
-#[allow(dead_code)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Wind {
-    pub speed: f64,
-    deg: u32,
+    pub speed: Speed,
+    pub deg: u32,
+    pub gust: Option<Speed>,
 }
 
-impl Wind {
-    //public method to get wind speed in meters per second
-    pub fn get_speed_meters_per_sec(&self) -> f64 {
-        self.speed
-    }
-
-    //public method to get wind speed in miles per hour
-    pub fn get_speed_mph(&self) -> f64 {
-        self.speed * 2.23694 // Convert m/s to mph
-    }
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rain {
+    #[serde(rename = "1h")]
+    pub rain_1h: Option<Precipitation>,
+    #[serde(rename = "3h")]
+    pub rain_3h: Option<Precipitation>,
 }
 
-#[derive(Debug, Deserialize)]
-#[allow(dead_code)]
-pub struct Rain {
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Snow {
     #[serde(rename = "1h")]
-    rain_1h: f64,
+    pub snow_1h: Option<Precipitation>,
+    #[serde(rename = "3h")]
+    pub snow_3h: Option<Precipitation>,
 }
 
-#[derive(Debug, Deserialize)]
-#[allow(dead_code)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Clouds {
     pub all: u32,
 }
@@ -70,44 +86,91 @@ impl Clouds {
     }
 }
 
-#[derive(Debug, Deserialize)]
-#[allow(dead_code)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Sys {
     pub country: String,
     pub sunrise: u64,
     pub sunset: u64,
 }
 
-#[derive(Debug, Deserialize)]
-#[allow(dead_code)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct WeatherResponse {
+    #[serde(default)]
+    pub id: u64,
     pub coord: Coord,
+    #[serde(default)]
     pub weather: Vec<Weather>,
     pub main: Main,
+    #[serde(default)]
+    pub visibility: Option<Distance>,
+    #[serde(default)]
     pub wind: Wind,
+    #[serde(default)]
     pub rain: Option<Rain>,
+    #[serde(default)]
+    pub snow: Option<Snow>,
+    #[serde(default)]
     pub clouds: Clouds,
+    #[serde(default)]
     pub sys: Sys,
     pub name: String,
+    // Time of the observation (unix seconds)
+    #[serde(default)]
+    pub dt: i64,
+    // Offset from UTC in seconds
+    #[serde(default)]
+    pub timezone: i32,
 }
 
-pub async fn get_weather(
-    city: &str,
-) -> Result<WeatherResponse, Box<dyn std::error::Error + Send + Sync>> {
+impl WeatherResponse {
+    // First condition reported for the location, if any
+    pub fn condition(&self) -> Option<&Weather> {
+        self.weather.first()
+    }
+
+    // Rain in the last hour, falling back to the 3 hour total
+    pub fn rain_recent(&self) -> Option<Precipitation> {
+        self.rain.as_ref().and_then(|r| r.rain_1h.or(r.rain_3h))
+    }
+
+    // Snow in the last hour, falling back to the 3 hour total
+    pub fn snow_recent(&self) -> Option<Precipitation> {
+        self.snow.as_ref().and_then(|s| s.snow_1h.or(s.snow_3h))
+    }
+
+    // A unix timestamp converted to the location's local time
+    pub fn local_time(&self, timestamp: i64) -> Option<DateTime<FixedOffset>> {
+        let offset = FixedOffset::east_opt(self.timezone)?;
+        offset.timestamp_opt(timestamp, 0).single()
+    }
+}
+
+// Headers needed for every RapidAPI request
+fn rapidapi_headers() -> Result<header::HeaderMap, Box<dyn std::error::Error + Send + Sync>> {
     let mut headers = header::HeaderMap::new();
     let api_key = env::var("API_KEY")?;
     headers.insert(
         "X-RapidAPI-Host",
         "weather-api138.p.rapidapi.com".parse().unwrap(),
     );
-    headers.insert("X-RapidAPI-Key", api_key.parse().unwrap());
+    headers.insert("X-RapidAPI-Key", api_key.parse()?);
+    Ok(headers)
+}
+
+// GET an endpoint of the weather API and parse the JSON body
+async fn fetch<T: DeserializeOwned>(
+    endpoint: &str,
+    query: &str,
+) -> Result<T, Box<dyn std::error::Error + Send + Sync>> {
+    let headers = rapidapi_headers()?;
 
     let client = reqwest::Client::builder().build()?;
     
     let res = client
-        .get(&format!(
-            "https://weather-api138.p.rapidapi.com/weather?city_name={}",
-            city
+        .get(format!(
+            "https://weather-api138.p.rapidapi.com/{}?{}",
+            endpoint, query
         ))
         .headers(headers)
         .send()
@@ -115,45 +178,232 @@ pub async fn get_weather(
     
     if res.status().is_success() {
         let body = res.text().await?;
-        let weather_response: WeatherResponse = serde_json::from_str(&body)?;
-        Ok(weather_response)
+        Ok(serde_json::from_str(&body)?)
     } else {
         Err(format!("Request failed with status code: {}", res.status()).into())
     }
 }
-pub fn get_random_city() -> (&'static str, &'static str, &'static str) {
-    let cities = vec![
-        ("New York", "USA", "🇺🇸"), ("Los Angeles", "USA", "🇺🇸"), ("Miami", "USA", "🇺🇸"), ("Honolulu", "USA", "🇺🇸"),
-        ("Vancouver", "Canada", "🇨🇦"), ("Toronto", "Canada", "🇨🇦"), ("Mexico City", "Mexico", "🇲🇽"), ("Monterrey", "Mexico", "🇲🇽"),
-        ("London", "UK", "🇬🇧"), ("Manchester", "UK", "🇬🇧"), ("Munich", "Germany", "🇩🇪"), ("Berlin", "Germany", "🇩🇪"),
-        ("Madrid", "Spain", "🇪🇸"), ("Barcelona", "Spain", "🇪🇸"), ("Milan", "Italy", "🇮🇹"), ("Rome", "Italy", "🇮🇹"),
-        ("Paris", "France", "🇫🇷"), ("Marseille", "France", "🇫🇷"), ("Glasgow", "UK", "🇬🇧"),
-        ("Copenhagen", "Denmark", "🇩🇰"), ("Oslo", "Norway", "🇳🇴"), ("Stockholm", "Sweden", "🇸🇪"), ("Helsinki", "Finland", "🇫🇮"),
-        ("Moscow", "Russia", "🇷🇺"), ("Kyiv", "Ukraine", "🇺🇦"), ("Warsaw", "Poland", "🇵🇱"), ("Prague", "Czechia", "🇨🇿"),
-        ("Amsterdam", "Netherlands", "🇳🇱"), ("Brussels", "Belgium", "🇧🇪"), ("Bern", "Switzerland", "🇨🇭"), ("Vienna", "Austria", "🇦🇹"),
-        ("Budapest", "Hungary", "🇭🇺"), ("Zagreb", "Croatia", "🇭🇷"), ("Bucharest", "Romania", "🇷🇴"), ("Athens", "Greece", "🇬🇷"),
-        ("Lisbon", "Portugal", "🇵🇹"), ("Istanbul", "Turkey", "🇹🇷"), ("Casablanca", "Morocco", "🇲🇦"), ("Baku", "Azerbaijan", "🇦🇿"),
-        ("Cairo", "Egypt", "🇪🇬"), ("Riyadh", "Saudi Arabia", "🇸🇦"), ("Doha", "Qatar", "🇶🇦"), ("Dubai", "UAE", "🇦🇪"),
-        ("Muscat", "Oman", "🇴🇲"), ("Tehran", "Iran", "🇮🇷"), ("Lagos", "Nigeria", "🇳🇬"), ("Dakar", "Senegal", "🇸🇳"), ("Cape Town", "South Africa", "🇿🇦"),
-        ("Mumbai", "India", "🇮🇳"), ("Dhaka", "Bangladesh", "🇧🇩"), ("Bangkok", "Thailand", "🇹🇭"), ("Kuala Lumpur", "Malaysia", "🇲🇾"),
-        ("Tokyo", "Japan", "🇯🇵"), ("Seoul", "South Korea", "🇰🇷"), ("Beijing", "China", "🇨🇳"), ("Shanghai", "China", "🇨🇳"),
-        ("Sydney", "Australia", "🇦🇺"), ("Perth", "Australia", "🇦🇺"), ("Melbourne", "Australia", "🇦🇺"), ("Auckland", "New Zealand", "🇳🇿"),
-        ("Reykjavík", "Iceland", "🇮🇸"), ("Nairobi", "Kenya", "🇰🇪"), ("Harare", "Zimbabwe", "🇿🇼"), ("Luanda", "Angola", "🇦🇴"),
-        ("Libreville", "Gabon", "🇬🇦"), ("Lusaka", "Zambia", "🇿🇲"), ("Freetown", "Sierra Leone", "🇸🇱"), ("Algiers", "Algeria", "🇩🇿"),
-        ("Rio de Janeiro", "Brazil", "🇧🇷"), ("Buenos Aires", "Argentina", "🇦🇷"), ("Montevideo", "Uruguay", "🇺🇾"),
-        ("Santiago", "Chile", "🇨🇱"), ("Lima", "Peru", "🇵🇪"), ("La Paz", "Bolivia", "🇧🇴"), ("Medellín", "Colombia", "🇨🇴"),
-        ("Panama City", "Panama", "🇵🇦"), ("San Jose", "Costa Rica", "🇨🇷"), ("San Salvador", "El Salvador", "🇸🇻"),
-        ("Havana", "Cuba", "🇨🇺"), ("Guatemala City", "Guatemala", "🇬🇹"), ("Tegucigalpa", "Honduras", "🇭🇳"), ("Managua", "Nicaragua", "🇳🇮"),
-        ("Kingston", "Jamaica", "🇯🇲"), ("Santo Domingo", "Dominican Replublic", "🇩🇴"), ("Bridgetown", "Barbados", "🇧🇧"),
-        ("Suva", "Fiji", "🇫🇯"), ("Port Moresby", "Papua New Guinea", "🇵🇬"), ("Ulaanbaatar", "Mongolia", "🇲🇳")
-
-    ];
-    // This creates a local variable `rng`
-    let mut rng = thread_rng();
-
-    //let idx = rand::thread_rng().gen_range(0..cities.len());
-    let idx = rng.gen_range(0..cities.len());
-    cities[idx]
+
+pub async fn get_weather(
+    city: &str,
+) -> Result<WeatherResponse, Box<dyn std::error::Error + Send + Sync>> {
+    fetch("weather", &format!("city_name={}", city)).await
+}
+
+// Current conditions at a point rather than a named city
+pub async fn get_weather_at(
+    coord: Coord,
+) -> Result<WeatherResponse, Box<dyn std::error::Error + Send + Sync>> {
+    fetch("weather", &format!("lat={}&lon={}", coord.lat, coord.lon)).await
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ForecastEntry {
+    pub dt: i64,
+    pub main: Main,
+    pub weather: Vec<Weather>,
+    pub wind: Wind,
+    pub clouds: Clouds,
+    pub visibility: Option<Distance>,
+    pub rain: Option<Rain>,
+    pub snow: Option<Snow>,
+    // Probability of precipitation (0 to 1)
+    pub pop: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ForecastCity {
+    pub name: String,
+    pub country: String,
+    pub coord: Coord,
+    // Offset from UTC in seconds
+    pub timezone: i32,
+    pub sunrise: u64,
+    pub sunset: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ForecastResponse {
+    pub list: Vec<ForecastEntry>,
+    pub city: ForecastCity,
+}
+
+// One day of forecast entries rolled up into a high/low
+#[derive(Debug, Clone)]
+pub struct DailySummary {
+    pub date: NaiveDate,
+    pub temp_min: Temperature,
+    pub temp_max: Temperature,
+    pub pop: f64,
+    pub condition: Option<Weather>,
+}
+
+impl ForecastResponse {
+    // The next `hours` worth of 3-hour forecast entries
+    pub fn hourly(&self, hours: usize) -> &[ForecastEntry] {
+        let count = (hours / 3).max(1).min(self.list.len());
+        &self.list[..count]
+    }
+
+    // Group entries by the city's local date
+    pub fn daily(&self) -> Vec<DailySummary> {
+        let mut days: Vec<DailySummary> = Vec::new();
+        for entry in &self.list {
+            let date = match Utc.timestamp_opt(entry.dt + self.city.timezone as i64, 0).single() {
+                Some(time) => time.date_naive(),
+                None => continue,
+            };
+            match days.last_mut() {
+                Some(day) if day.date == date => {
+                    day.temp_min = day.temp_min.min(entry.main.temp_min);
+                    day.temp_max = day.temp_max.max(entry.main.temp_max);
+                    day.pop = day.pop.max(entry.pop);
+                    // Prefer the midday condition for the day's icon
+                    let hour = (entry.dt + self.city.timezone as i64).rem_euclid(86400) / 3600;
+                    if (11..=14).contains(&hour) {
+                        day.condition = entry.weather.first().cloned();
+                    }
+                }
+                _ => days.push(DailySummary {
+                    date,
+                    temp_min: entry.main.temp_min,
+                    temp_max: entry.main.temp_max,
+                    pop: entry.pop,
+                    condition: entry.weather.first().cloned(),
+                }),
+            }
+        }
+        days
+    }
+}
+
+pub async fn get_forecast(
+    city: &str,
+) -> Result<ForecastResponse, Box<dyn std::error::Error + Send + Sync>> {
+    fetch("forecast", &format!("city_name={}", city)).await
 }
 
+pub async fn get_forecast_at(
+    coord: Coord,
+) -> Result<ForecastResponse, Box<dyn std::error::Error + Send + Sync>> {
+    fetch("forecast", &format!("lat={}&lon={}", coord.lat, coord.lon)).await
+}
+
+// A cached API response and when we fetched it
+struct Cached<T> {
+    fetched: Instant,
+    value: T,
+}
+
+type Cache<T> = OnceLock<Mutex<HashMap<String, Cached<T>>>>;
+
+static WEATHER_CACHE: Cache<WeatherResponse> = OnceLock::new();
+static FORECAST_CACHE: Cache<ForecastResponse> = OnceLock::new();
+
+// How long cached responses stay fresh, WEATHER_CACHE_SECS or 10 minutes
+fn cache_ttl() -> Duration {
+    let secs = env::var("WEATHER_CACHE_SECS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(600);
+    Duration::from_secs(secs)
+}
+
+fn cache_key(city: &str) -> String {
+    city.trim().to_lowercase()
+}
 
+fn cache_get<T: Clone>(cache: &Cache<T>, city: &str) -> Option<T> {
+    let map = cache.get_or_init(Default::default).lock().unwrap();
+    map.get(&cache_key(city))
+        .filter(|hit| hit.fetched.elapsed() < cache_ttl())
+        .map(|hit| hit.value.clone())
+}
+
+fn cache_put<T>(cache: &Cache<T>, city: &str, value: T) {
+    let mut map = cache.get_or_init(Default::default).lock().unwrap();
+    map.insert(cache_key(city), Cached { fetched: Instant::now(), value });
+}
+
+// Same as get_weather, but reuses a recent response for the same city
+pub async fn get_weather_cached(
+    city: &str,
+) -> Result<WeatherResponse, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(weather) = cache_get(&WEATHER_CACHE, city) {
+        return Ok(weather);
+    }
+    let weather = get_weather(city).await?;
+    cache_put(&WEATHER_CACHE, city, weather.clone());
+    Ok(weather)
+}
+
+// Same as get_forecast, but reuses a recent response for the same city
+pub async fn get_forecast_cached(
+    city: &str,
+) -> Result<ForecastResponse, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(forecast) = cache_get(&FORECAST_CACHE, city) {
+        return Ok(forecast);
+    }
+    let forecast = get_forecast(city).await?;
+    cache_put(&FORECAST_CACHE, city, forecast.clone());
+    Ok(forecast)
+}
+
+// Cache key for a point, ~1km resolution so nearby lookups share a response
+fn coord_key(coord: Coord) -> String {
+    format!("@{:.2},{:.2}", coord.lat, coord.lon)
+}
+
+pub async fn get_weather_at_cached(
+    coord: Coord,
+) -> Result<WeatherResponse, Box<dyn std::error::Error + Send + Sync>> {
+    let key = coord_key(coord);
+    if let Some(weather) = cache_get(&WEATHER_CACHE, &key) {
+        return Ok(weather);
+    }
+    let weather = get_weather_at(coord).await?;
+    cache_put(&WEATHER_CACHE, &key, weather.clone());
+    Ok(weather)
+}
+
+pub async fn get_forecast_at_cached(
+    coord: Coord,
+) -> Result<ForecastResponse, Box<dyn std::error::Error + Send + Sync>> {
+    let key = coord_key(coord);
+    if let Some(forecast) = cache_get(&FORECAST_CACHE, &key) {
+        return Ok(forecast);
+    }
+    let forecast = get_forecast_at(coord).await?;
+    cache_put(&FORECAST_CACHE, &key, forecast.clone());
+    Ok(forecast)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_bodies_are_not_reports() {
+        assert!(serde_json::from_str::<WeatherResponse>("{}").is_err());
+        let error = r#"{"cod": "404", "message": "city not found"}"#;
+        assert!(serde_json::from_str::<WeatherResponse>(error).is_err());
+    }
+
+    #[test]
+    fn optional_sections_default() {
+        let body = r#"{
+            "coord": {"lon": -80.84, "lat": 35.23},
+            "main": {"temp": 295.15},
+            "name": "Charlotte"
+        }"#;
+        let weather: WeatherResponse = serde_json::from_str(body).unwrap();
+        assert_eq!(weather.name, "Charlotte");
+        assert!((weather.main.temp.celsius() - 22.0).abs() < 1e-9);
+        assert!(weather.rain.is_none());
+        assert!(weather.wind.gust.is_none());
+        assert_eq!(weather.clouds.all, 0);
+    }
+}